@@ -0,0 +1,55 @@
+//! Compares deserializing wide records in full into a `serde_json::Map` and deserializing
+//! only two of their fields with a `Projection`.
+//!
+//! Run with `cargo bench --bench projection`.
+
+use hyper_json_stream::Projection;
+use serde::de::DeserializeSeed;
+use serde_json::{Map, Value};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ROUNDS: u32 = 20;
+
+/// `count` records of `width` fields each.
+fn records(count: usize, width: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let fields: Vec<String> = (0..width)
+                .map(|field| match field % 3 {
+                    0 => format!(r#""field_{}": {}"#, field, i * field),
+                    1 => format!(r#""field_{}": "value {} of record {}""#, field, field, i),
+                    _ => format!(r#""field_{}": [{}, "{}", null]"#, field, i, field),
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        })
+        .collect()
+}
+
+/// The average time to deserialize `records` with `parse`.
+fn run(records: &[String], parse: impl Fn(&str) -> Map<String, Value>) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for record in records {
+            black_box(parse(record));
+        }
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    let projection = Projection::new(&["field_0", "field_1"]);
+    for width in [10, 50, 200] {
+        let records = records(20_000, width);
+        let full = run(&records, |record| serde_json::from_str(record).unwrap());
+        let projected = run(&records, |record| {
+            let mut de = serde_json::Deserializer::from_str(record);
+            projection.clone().deserialize(&mut de).unwrap()
+        });
+        println!(
+            "records of {:>3} fields: full {:>9.2?}, projected {:>9.2?}",
+            width, full, projected
+        );
+    }
+}
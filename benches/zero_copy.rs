@@ -0,0 +1,69 @@
+//! Compares feeding the chunks of a body by copy with `JsonArrayDecoder::feed` and without
+//! copying them with `JsonArrayDecoder::feed_bytes`.
+//!
+//! Run with `cargo bench --bench zero_copy`.
+
+use hyper::body::Bytes;
+use hyper_json_stream::{JsonArrayDecoder, StreamTarget};
+use serde::Deserialize;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Item {
+    id: u64,
+    name: String,
+    tags: Vec<String>,
+}
+
+const ROUNDS: u32 = 20;
+
+/// A body of `count` elements, split in chunks of `chunk_size` bytes.
+fn body(count: usize, chunk_size: usize) -> Vec<Bytes> {
+    let mut json = String::from("[");
+    for i in 0..count {
+        if i > 0 {
+            json.push_str(", ");
+        }
+        json.push_str(&format!(
+            r#"{{"id": {}, "name": "item number {}", "tags": ["a", "b", "c"]}}"#,
+            i, i
+        ));
+    }
+    json.push(']');
+    let json = Bytes::from(json);
+    (0..json.len())
+        .step_by(chunk_size)
+        .map(|start| json.slice(start..json.len().min(start + chunk_size)))
+        .collect()
+}
+
+/// The average time to decode `chunks`, feeding them with `feed`.
+fn run(chunks: &[Bytes], feed: impl Fn(&mut JsonArrayDecoder<Item>, &Bytes)) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut decoder = JsonArrayDecoder::new(StreamTarget::TopLevelArray, 0);
+        for chunk in chunks {
+            feed(&mut decoder, chunk);
+            while let Some(item) = decoder.poll_element().unwrap() {
+                black_box(item);
+            }
+        }
+        decoder.finish();
+        assert!(decoder.poll_element().unwrap().is_none());
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn main() {
+    for chunk_size in [1024, 16 * 1024, 64 * 1024] {
+        let chunks = body(200_000, chunk_size);
+        let copy = run(&chunks, |decoder, chunk| decoder.feed(chunk));
+        let zero_copy = run(&chunks, |decoder, chunk| decoder.feed_bytes(chunk.clone()));
+        println!(
+            "chunks of {:>5} bytes: copy {:>9.2?}, zero-copy {:>9.2?}",
+            chunk_size, copy, zero_copy
+        );
+    }
+}
@@ -1,7 +1,3 @@
-use http::Method;
-use hyper::Request;
-use hyper::Uri;
-
 use futures_util::stream::StreamExt;
 use http_body_util::Empty;
 use hyper::body::Bytes;
@@ -29,15 +25,8 @@ async fn main() {
             .enable_http2()
             .build(),
     );
-    let req = Request::builder()
-        .method(Method::GET)
-        .uri(Uri::from_static(url))
-        .header("Accept-Encoding", "gzip")
-        .body(Empty::new())
-        .unwrap();
-    let res = client.request(req);
-
-    let stream: JsonStream<Country> = JsonStream::new(res, 1, 100);
+    // Send a GET accepting gzip, which is decoded on the fly.
+    let stream: JsonStream<Country> = JsonStream::get(&client, url, 1, 100).unwrap();
 
     // Optionally take only a number of elements from the list
     let mut stream = stream.take(10);
@@ -0,0 +1,34 @@
+#![no_main]
+
+use std::sync::{Arc, Mutex};
+
+use hyper_json_stream::PartialJson;
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+// Feeds arbitrary bytes, split in two chunks, to the scanner in robust mode. The robust
+// mode must never fail, and the skipped ranges must be ordered and within the input.
+fuzz_target!(|data: &[u8]| {
+    let Some((&split, input)) = data.split_first() else {
+        return;
+    };
+    let split = split as usize % (input.len() + 1);
+    let level = (split % 3) as u32 + 1;
+
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+    let sink = skipped.clone();
+    let mut json: PartialJson<Value> = PartialJson::new(0, level);
+    json.robust(Box::new(move |range| sink.lock().unwrap().push(range)));
+
+    for chunk in [&input[..split], &input[split..]] {
+        json.push(chunk);
+        while json.next().expect("robust mode never fails").is_some() {}
+    }
+
+    let mut end = 0;
+    for range in skipped.lock().unwrap().iter() {
+        assert!(range.start >= end && range.start <= range.end);
+        assert!(range.end <= input.len() as u64);
+        end = range.end;
+    }
+});
@@ -6,13 +6,17 @@ use std::{
 
 use libz_sys::uInt;
 
+use crate::stream::budget::Budget;
+
 const ALIGN: usize = mem::align_of::<usize>();
 
 fn align_up(size: usize, align: usize) -> usize {
     (size + align - 1) & !(align - 1)
 }
 
-pub extern "C" fn zalloc(_ptr: *mut c_void, items: uInt, item_size: uInt) -> *mut c_void {
+/// Allocator for zlib. The `opaque` pointer is either null or points to the `Budget` the
+/// allocations are reserved from.
+pub extern "C" fn zalloc(opaque: *mut c_void, items: uInt, item_size: uInt) -> *mut c_void {
     // We need to multiply `items` and `item_size` to get the actual desired
     // allocation size. Since `zfree` doesn't receive a size argument we
     // also need to allocate space for a `usize` as a header so we can store
@@ -33,11 +37,19 @@ pub extern "C" fn zalloc(_ptr: *mut c_void, items: uInt, item_size: uInt) -> *mu
         Err(_) => return ptr::null_mut(),
     };
 
+    let budget = opaque as *const Budget;
+    if !budget.is_null() && !unsafe { &*budget }.acquire(size) {
+        return ptr::null_mut();
+    }
+
     unsafe {
         // Allocate the data, and if successful store the size we allocated
         // at the beginning and then return an offset pointer.
         let ptr = alloc::alloc(layout) as *mut usize;
         if ptr.is_null() {
+            if !budget.is_null() {
+                (*budget).release(size);
+            }
             return ptr as *mut c_void;
         }
         *ptr = size;
@@ -45,7 +57,7 @@ pub extern "C" fn zalloc(_ptr: *mut c_void, items: uInt, item_size: uInt) -> *mu
     }
 }
 
-pub extern "C" fn zfree(_ptr: *mut c_void, address: *mut c_void) {
+pub extern "C" fn zfree(opaque: *mut c_void, address: *mut c_void) {
     unsafe {
         // Move our address being freed back one pointer, read the size we
         // stored in `zalloc`, and then free it using the standard Rust
@@ -53,6 +65,10 @@ pub extern "C" fn zfree(_ptr: *mut c_void, address: *mut c_void) {
         let ptr = (address as *mut usize).offset(-1);
         let size = *ptr;
         let layout = Layout::from_size_align_unchecked(size, ALIGN);
-        alloc::dealloc(ptr as *mut u8, layout)
+        alloc::dealloc(ptr as *mut u8, layout);
+        let budget = opaque as *const Budget;
+        if !budget.is_null() {
+            (*budget).release(size);
+        }
     }
 }
@@ -3,5 +3,36 @@ mod ffi;
 mod stream;
 mod util;
 
-pub use crate::stream::json_stream::JsonStream;
+pub use crate::stream::array_decoder::JsonArrayDecoder;
+pub use crate::stream::batched::Batched;
+pub use crate::stream::body::BoxedBody;
+pub use crate::stream::budget::DecompressionBudget;
+#[cfg(feature = "timeout")]
+pub use crate::stream::clock::{Clock, Sleep, TokioClock};
+pub use crate::stream::content_range::ContentRange;
+#[cfg(feature = "tokio")]
+pub use crate::stream::decoded_body::DecodedBody;
+pub use crate::stream::dedup::DedupJsonStream;
+pub use crate::stream::embedded::{Base64Decoder, StringField};
+pub use crate::stream::error_body::ErrorBody;
+pub use crate::stream::footer::FooterJsonStream;
+#[cfg(feature = "timeout")]
+pub use crate::stream::json_stream::Drained;
+pub use crate::stream::json_stream::{count_elements, parse_array, JsonStream};
+pub use crate::stream::merged::MergedJsonStream;
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub use crate::stream::partial_json::PartialJson;
+pub use crate::stream::pause::PauseSignal;
+pub use crate::stream::pool::BufferPool;
+pub use crate::stream::projection::Projection;
+pub use crate::stream::raw::RawJsonStream;
+pub use crate::stream::stats::StreamStats;
+pub use crate::stream::target::StreamTarget;
+#[cfg(feature = "test-util")]
+pub use crate::stream::throttle::ThrottledJsonStream;
+#[cfg(feature = "test-util")]
+pub use crate::stream::watchdog::assert_wakes_on_pending;
 pub use crate::util::JsonStreamError;
+#[cfg(feature = "sink")]
+pub use crate::util::PipeError;
@@ -0,0 +1,80 @@
+use hyper::body::Bytes;
+use serde::de::DeserializeOwned;
+
+use crate::stream::partial_json::PartialJson;
+use crate::stream::target::StreamTarget;
+use crate::util::JsonStreamError;
+
+/// Decodes the elements of a json document fed in chunks, independently of hyper, for
+/// bytes coming from any transport like the messages of a WebSocket. This is the parser
+/// `JsonStream` runs on the decompressed body of a response.
+///
+/// Bytes are fed with `feed`, and the elements completed so far are taken with
+/// `poll_element` until it returns `Ok(None)`. Once the document is complete, `finish`
+/// flushes a value that has no delimiter, like the one of `StreamTarget::SingleValue`.
+pub struct JsonArrayDecoder<T> {
+    json: PartialJson<T>,
+}
+
+impl<T: DeserializeOwned> JsonArrayDecoder<T> {
+    /// Create a decoder yielding the given `target` of the json document. The `capacity`
+    /// is the initial size of the buffer holding the incomplete elements.
+    pub fn new(target: StreamTarget, capacity: usize) -> Self {
+        let (framing, level) = target.framing();
+        JsonArrayDecoder {
+            json: PartialJson::with_framing(capacity, level, framing),
+        }
+    }
+    /// Append `bytes` to the document.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.json.push(bytes);
+    }
+    /// Append `bytes` to the document, without copying them to the buffer of the
+    /// incomplete elements unless they hold only part of an element.
+    pub fn feed_bytes(&mut self, bytes: Bytes) {
+        self.json.push_bytes(bytes);
+    }
+    /// Signal the end of the document, no more bytes will be fed.
+    pub fn finish(&mut self) {
+        self.json.finish();
+    }
+    /// The next complete element, if any. Once the decoder is finished, `Ok(None)` means
+    /// that the whole document was decoded. An error leaves the decoder in an unspecified
+    /// state, it should not be polled anymore.
+    pub fn poll_element(&mut self) -> Result<Option<T>, JsonStreamError> {
+        self.json.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonArrayDecoder;
+    use crate::stream::target::StreamTarget;
+
+    #[test]
+    fn feed() {
+        let mut decoder: JsonArrayDecoder<Vec<u32>> =
+            JsonArrayDecoder::new(StreamTarget::TopLevelArray, 0);
+        let mut elements = Vec::new();
+        for chunk in [&b"[[1, 2"[..], b"], [3]", b", []]"] {
+            decoder.feed(chunk);
+            while let Some(element) = decoder.poll_element().unwrap() {
+                elements.push(element);
+            }
+        }
+        decoder.finish();
+        assert!(decoder.poll_element().unwrap().is_none());
+        assert_eq!(elements, [vec![1, 2], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn single_value() {
+        let mut decoder: JsonArrayDecoder<u32> =
+            JsonArrayDecoder::new(StreamTarget::SingleValue, 0);
+        decoder.feed(b"4");
+        decoder.feed(b"2");
+        assert!(decoder.poll_element().unwrap().is_none());
+        decoder.finish();
+        assert_eq!(decoder.poll_element().unwrap(), Some(42));
+    }
+}
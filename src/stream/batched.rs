@@ -0,0 +1,65 @@
+use futures_core::stream::{FusedStream, Stream};
+use serde::de::DeserializeOwned;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A stream yielding the elements of a `JsonStream` in batches, created by
+/// `JsonStream::batched`.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct Batched<T> {
+    stream: JsonStream<T>,
+    size: usize,
+    batch: Vec<T>,
+}
+
+impl<T> Batched<T> {
+    pub(crate) fn new(stream: JsonStream<T>, size: usize) -> Self {
+        assert!(size > 0, "the size of the batches must be positive");
+        Batched {
+            stream,
+            size,
+            batch: Vec::new(),
+        }
+    }
+}
+
+// The compiler adds a T: Unpin bound, but a `Vec<T>` and a `JsonStream<T>` are Unpin.
+impl<T> Unpin for Batched<T> {}
+
+impl<T: DeserializeOwned> Stream for Batched<T> {
+    type Item = Result<Vec<T>, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Vec<T>, JsonStreamError>>> {
+        let this = self.get_mut();
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(item))) => {
+                    this.batch.push(item);
+                    if this.batch.len() == this.size {
+                        return Poll::Ready(Some(Ok(mem::take(&mut this.batch))));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => {
+                    // The elements of the current batch are lost with the error.
+                    this.batch.clear();
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Ready(None) if this.batch.is_empty() => return Poll::Ready(None),
+                Poll::Ready(None) => return Poll::Ready(Some(Ok(mem::take(&mut this.batch)))),
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FusedStream for Batched<T> {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated() && self.batch.is_empty()
+    }
+}
@@ -0,0 +1,73 @@
+use hyper::body::{Body, Bytes, Frame, Incoming, SizeHint};
+use std::error::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{fmt, io};
+
+use crate::util::JsonStreamError;
+
+/// A body of any origin, like a mocked one, for `JsonStream::from_boxed_body`. It must be
+/// `Sync` for the stream to be: `Body` has `&self` methods, like `size_hint`.
+pub type BoxedBody =
+    Box<dyn Body<Data = Bytes, Error = Box<dyn Error + Send + Sync>> + Send + Sync>;
+
+/// The body a `JsonStream` reads from: the one of hyper, polled without a dynamic call, or
+/// a boxed one.
+pub(crate) enum ResponseBody {
+    Incoming(Incoming),
+    Boxed(Pin<BoxedBody>),
+}
+
+impl From<Incoming> for ResponseBody {
+    fn from(body: Incoming) -> Self {
+        ResponseBody::Incoming(body)
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseBody::Incoming(body) => body.fmt(f),
+            ResponseBody::Boxed(_) => f.pad("BoxedBody"),
+        }
+    }
+}
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+    type Error = JsonStreamError;
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, JsonStreamError>>> {
+        match self.get_mut() {
+            ResponseBody::Incoming(body) => Pin::new(body)
+                .poll_frame(cx)
+                .map(|frame| frame.map(|frame| frame.map_err(JsonStreamError::from))),
+            ResponseBody::Boxed(body) => body
+                .as_mut()
+                .poll_frame(cx)
+                .map(|frame| frame.map(|frame| frame.map_err(boxed_error))),
+        }
+    }
+    fn is_end_stream(&self) -> bool {
+        match self {
+            ResponseBody::Incoming(body) => body.is_end_stream(),
+            ResponseBody::Boxed(body) => body.is_end_stream(),
+        }
+    }
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            ResponseBody::Incoming(body) => body.size_hint(),
+            ResponseBody::Boxed(body) => body.size_hint(),
+        }
+    }
+}
+
+/// The error of a boxed body, as an `io::Error` unless it is already one.
+fn boxed_error(err: Box<dyn Error + Send + Sync>) -> JsonStreamError {
+    match err.downcast::<io::Error>() {
+        Ok(err) => JsonStreamError::IOError(*err),
+        Err(err) => JsonStreamError::IOError(io::Error::other(err)),
+    }
+}
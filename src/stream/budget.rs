@@ -0,0 +1,62 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A cap on the total memory used by the decompressors of all the streams sharing it.
+///
+/// Cloning the budget returns a handle to the same budget. Decompressors reserve their
+/// memory from the budget before allocating it and give it back once dropped. A stream
+/// whose decompressor cannot get the memory it needs does not wait for memory to be
+/// released: it fails with `JsonStreamError::EncodingError`.
+#[derive(Clone)]
+pub struct DecompressionBudget {
+    pub(crate) inner: Arc<Budget>,
+}
+
+pub(crate) struct Budget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl DecompressionBudget {
+    /// Create a budget allowing `limit` bytes of decompression memory in total.
+    pub fn new(limit: usize) -> Self {
+        DecompressionBudget {
+            inner: Arc::new(Budget {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+    /// The total number of bytes the budget allows.
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+    /// The number of bytes currently in use by the decompressors sharing the budget.
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for DecompressionBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecompressionBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl Budget {
+    /// Reserve `size` bytes, returns false if that would exceed the limit.
+    pub(crate) fn acquire(&self, size: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                used.checked_add(size).filter(|&used| used <= self.limit)
+            })
+            .is_ok()
+    }
+    pub(crate) fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,97 @@
+use encoding_rs::{DecoderResult, Encoding, UTF_8};
+use http::HeaderMap;
+
+use crate::util::JsonStreamError;
+
+/// Size of the scratch buffer the bytes are transcoded into.
+const OUTPUT_SIZE: usize = 1024;
+
+/// Transcodes a body in a legacy charset, like ISO-8859-1, to UTF-8.
+pub(crate) struct Transcoder {
+    decoder: encoding_rs::Decoder,
+}
+
+impl Transcoder {
+    /// The transcoder of the `charset` parameter of the `Content-Type` header, if it names
+    /// a known charset other than UTF-8.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(http::header::CONTENT_TYPE)?.to_str().ok()?;
+        let charset = content_type.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"'))
+        })?;
+        let encoding = Encoding::for_label(charset.as_bytes())?;
+        (encoding != UTF_8).then(|| Transcoder {
+            decoder: encoding.new_decoder_without_bom_handling(),
+        })
+    }
+    /// Transcode `input`, handing the UTF-8 bytes to `output`. An incomplete character at
+    /// the end of `input` is kept until the next call, or is an error when `last` is set.
+    pub(crate) fn transcode(
+        &mut self,
+        mut input: &[u8],
+        last: bool,
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        let mut buffer = [0; OUTPUT_SIZE];
+        loop {
+            let (res, read, written) =
+                self.decoder
+                    .decode_to_utf8_without_replacement(input, &mut buffer, last);
+            output(&buffer[..written]);
+            input = &input[read..];
+            match res {
+                DecoderResult::InputEmpty => return Ok(()),
+                DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(_, _) => {
+                    return Err(JsonStreamError::EncodingError(format!(
+                        "Invalid {} bytes",
+                        self.decoder.encoding().name()
+                    )))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transcoder;
+    use http::HeaderMap;
+
+    fn from_content_type(content_type: &str) -> Option<Transcoder> {
+        let mut headers = HeaderMap::new();
+        headers.insert(http::header::CONTENT_TYPE, content_type.parse().unwrap());
+        Transcoder::from_headers(&headers)
+    }
+
+    #[test]
+    fn from_headers() {
+        assert!(from_content_type("application/json").is_none());
+        assert!(from_content_type("application/json; charset=utf-8").is_none());
+        assert!(from_content_type("application/json; charset=unknown").is_none());
+        assert!(from_content_type("application/json; charset=\"ISO-8859-1\"").is_some());
+        assert!(from_content_type("application/json;Charset=shift_jis").is_some());
+    }
+
+    #[test]
+    fn transcode_across_chunks() {
+        // "α" in Shift_JIS is two bytes, split between the chunks.
+        let mut transcoder = from_content_type("application/json; charset=shift_jis").unwrap();
+        let mut out = Vec::new();
+        let mut output = |bytes: &[u8]| out.extend_from_slice(bytes);
+        let input = b"[\"\x83\xbf\"]";
+        transcoder
+            .transcode(&input[..3], false, &mut output)
+            .unwrap();
+        transcoder
+            .transcode(&input[3..], true, &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "[\"α\"]");
+
+        let mut transcoder = from_content_type("application/json; charset=shift_jis").unwrap();
+        assert!(transcoder.transcode(b"[\"\x83", true, &mut |_| {}).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::time::{Duration, Instant};
+
+/// A future completing at a deadline of a `Clock`. It is `Sync`, as the sleep of tokio is,
+/// so that the streams holding it are too.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// The source of time of the timeouts, which can be replaced by a mock clock in tests.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+    /// A future completing once `now()` reaches `deadline`.
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+}
+
+/// The timer of tokio, the default `Clock`. It follows `tokio::time::pause` and
+/// `tokio::time::advance`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// Fails a stream when it makes no progress for a while.
+pub(crate) struct Timeout {
+    duration: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    sleep: Option<Sleep>,
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout {
+            duration: None,
+            clock: Arc::new(TokioClock),
+            sleep: None,
+        }
+    }
+}
+
+impl Timeout {
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        self.duration = Some(duration);
+        self.sleep = None;
+    }
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+        self.sleep = None;
+    }
+    /// The clock measuring the countdown.
+    pub(crate) fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+    /// Restart the countdown, the stream made progress.
+    pub(crate) fn reset(&mut self) {
+        self.sleep = None;
+    }
+    /// Start the countdown if needed, and check whether it ran out.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        let duration = match self.duration {
+            Some(duration) => duration,
+            None => return false,
+        };
+        let sleep = match self.sleep {
+            Some(ref mut sleep) => sleep,
+            None => {
+                let deadline = self.clock.now() + duration;
+                self.sleep.insert(self.clock.sleep_until(deadline))
+            }
+        };
+        sleep.as_mut().poll(cx).is_ready()
+    }
+}
@@ -0,0 +1,66 @@
+use http::HeaderMap;
+
+/// The `Content-Range` of a `206 Partial Content` response, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// The offset of the first byte served.
+    pub start: u64,
+    /// The offset of the last byte served, inclusive.
+    pub end: u64,
+    /// The size of the full resource, if known.
+    pub complete_length: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parse a `Content-Range` header value, like `bytes 0-499/1234` or `bytes 0-499/*`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (range, complete_length) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.trim().split_once('-')?;
+        let range = ContentRange {
+            start: start.parse().ok()?,
+            end: end.parse().ok()?,
+            complete_length: match complete_length.trim() {
+                "*" => None,
+                length => Some(length.parse().ok()?),
+            },
+        };
+        let in_bounds = range
+            .complete_length
+            .is_none_or(|length| range.end < length);
+        (range.start <= range.end && in_bounds).then_some(range)
+    }
+    /// The `Content-Range` of the headers of a response.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        ContentRange::parse(headers.get(http::header::CONTENT_RANGE)?.to_str().ok()?)
+    }
+    /// The number of bytes served.
+    pub fn served_length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentRange;
+
+    #[test]
+    fn parse() {
+        let range = ContentRange::parse("bytes 0-499/1234").unwrap();
+        assert_eq!(
+            (range.start, range.end, range.complete_length),
+            (0, 499, Some(1234))
+        );
+        assert_eq!(range.served_length(), 500);
+        let range = ContentRange::parse("bytes 500-999/*").unwrap();
+        assert_eq!(range.complete_length, None);
+        for invalid in [
+            "bytes */1234",
+            "bytes 5-4/10",
+            "bytes 0-10/10",
+            "items 0-1/2",
+            "0-1/2",
+        ] {
+            assert_eq!(ContentRange::parse(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{cmp, fmt, io};
+
+use http::Response;
+use hyper::body::{Body, Incoming};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::stream::decoder::Decoder;
+use crate::stream::encoding::ContentEncoding;
+use crate::util::JsonStreamError;
+
+/// An `AsyncRead` over the body of a response, decompressed according to its
+/// `Content-Encoding` header. Useful to hand the raw json bytes to another parser.
+///
+/// Unlike `JsonStream`, the status of the response is not checked.
+pub struct DecodedBody {
+    body: Incoming,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl DecodedBody {
+    /// Create a new `DecodedBody` from a response.
+    pub fn new(resp: Response<Incoming>) -> Result<Self, JsonStreamError> {
+        let (parts, body) = resp.into_parts();
+        let decoder = Decoder::new(&ContentEncoding::from_headers(&parts.headers), None)?;
+        Ok(DecodedBody {
+            body,
+            decoder,
+            buffer: Vec::new(),
+            pos: 0,
+        })
+    }
+}
+
+impl fmt::Debug for DecodedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("DecodedBody")
+    }
+}
+
+impl AsyncRead for DecodedBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let DecodedBody {
+            body,
+            decoder,
+            buffer,
+            pos,
+        } = self.get_mut();
+        loop {
+            if *pos < buffer.len() {
+                let len = cmp::min(buf.remaining(), buffer.len() - *pos);
+                buf.put_slice(&buffer[*pos..*pos + len]);
+                *pos += len;
+                return Poll::Ready(Ok(()));
+            }
+            buffer.clear();
+            *pos = 0;
+            match Pin::new(&mut *body).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(frame))) => {
+                    // Trailers carry no body bytes, they are skipped.
+                    if let Ok(data) = frame.into_data() {
+                        decoder
+                            .decode(&data, |bytes| buffer.extend_from_slice(bytes))
+                            .map_err(io::Error::from)?;
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Err(io::Error::other(err))),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DecodedBody;
+    use crate::util::test_server::{chunked, respond};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn read_gzip_body() {
+        let json = r#"[{"a": 1}, {"a": 2}]"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 8);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let resp = respond(response).await.await.unwrap();
+        let mut body = DecodedBody::new(resp).unwrap();
+        let mut res = String::new();
+        body.read_to_string(&mut res).await.unwrap();
+        assert_eq!(res, json);
+    }
+}
@@ -0,0 +1,848 @@
+use std::ffi::{c_int, c_uint, c_void, CStr};
+use std::sync::Arc;
+use std::{cmp, mem, ptr};
+
+use libz_sys as zlib;
+
+use crate::ffi::{zalloc, zfree};
+use crate::stream::budget::{Budget, DecompressionBudget};
+#[cfg(feature = "charset")]
+use crate::stream::charset::Transcoder;
+use crate::stream::embedded::EmbeddedBody;
+use crate::stream::encoding::ContentEncoding;
+#[cfg(feature = "compress")]
+use crate::stream::lzw::Lzw;
+use crate::stream::multipart::Multipart;
+use crate::util::JsonStreamError;
+
+/// Size of the scratch buffer zlib inflates into.
+const OUTPUT_SIZE: usize = 1024;
+/// The size of a gzip trailer, the CRC32 and the ISIZE. A zlib one only has an Adler-32.
+const TRAILER_SIZE: usize = 8;
+/// The first bytes of a gzip member.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// 15 window bits, +32 to detect both the zlib and the gzip header.
+const AUTO_WINDOW_BITS: c_int = 47;
+
+/// Decodes the body of a response according to its `ContentEncoding`, then transcodes it
+/// to UTF-8 according to its charset.
+pub struct Decoder {
+    stream: Option<Box<zlib::z_stream>>,
+    /// zlib reached the end of the compressed stream, with no input after it.
+    ended: bool,
+    /// The body received so far, while it can still be decoded another way because
+    /// nothing was decoded from it yet.
+    fallback: Option<Vec<u8>>,
+    /// The offset in the compressed body of a range being resumed, which must start a
+    /// gzip member, and its first bytes until there are enough of them to tell.
+    resumed_at: Option<(u64, Vec<u8>)>,
+    /// Whether the member being inflated is gzip rather than zlib, told by its first byte
+    /// once zlib gets it.
+    gzip: Option<bool>,
+    /// The checksum and the length of the member being inflated.
+    check: Check,
+    /// Kept alive as long as zlib may allocate from it.
+    budget: Option<Arc<Budget>>,
+    #[cfg(feature = "compress")]
+    lzw: Option<Box<Lzw>>,
+    #[cfg(feature = "charset")]
+    charset: Option<Transcoder>,
+    /// Applied to the decoded body.
+    stages: Stages,
+}
+// The z_stream only holds pointers to memory owned by zlib and is never shared, so the
+// decoder can be sent to another thread.
+unsafe impl Send for Decoder {}
+// There are no &self methods, it is not possible to synchronously access the z_stream.
+unsafe impl Sync for Decoder {}
+
+impl Decoder {
+    pub fn new(
+        encoding: &ContentEncoding,
+        budget: Option<&DecompressionBudget>,
+    ) -> Result<Self, JsonStreamError> {
+        Decoder::with_window_bits(encoding, budget, AUTO_WINDOW_BITS)
+    }
+    /// Create a decoder inflating compressed bodies with the given zlib `window_bits`, see
+    /// `valid_window_bits`.
+    pub fn with_window_bits(
+        encoding: &ContentEncoding,
+        budget: Option<&DecompressionBudget>,
+        window_bits: c_int,
+    ) -> Result<Self, JsonStreamError> {
+        let budget = budget.map(|budget| budget.inner.clone());
+        match encoding {
+            ContentEncoding::None | ContentEncoding::Identity => Ok(Decoder {
+                stream: None,
+                ended: false,
+                fallback: None,
+                resumed_at: None,
+                gzip: None,
+                check: Check::default(),
+                budget,
+                #[cfg(feature = "compress")]
+                lzw: None,
+                #[cfg(feature = "charset")]
+                charset: None,
+                stages: Stages::default(),
+            }),
+            #[cfg(feature = "compress")]
+            ContentEncoding::Compress => Ok(Decoder {
+                stream: None,
+                ended: false,
+                fallback: None,
+                resumed_at: None,
+                gzip: None,
+                check: Check::default(),
+                budget: budget.clone(),
+                lzw: Some(Box::new(Lzw::new(budget))),
+                #[cfg(feature = "charset")]
+                charset: None,
+                stages: Stages::default(),
+            }),
+            ContentEncoding::Gzip => {
+                if !valid_window_bits(window_bits) {
+                    return Err(JsonStreamError::EncodingError(format!(
+                        "Invalid zlib window bits: {}",
+                        window_bits
+                    )));
+                }
+                let opaque = match budget {
+                    Some(ref budget) => Arc::as_ptr(budget) as *mut c_void,
+                    None => ptr::null_mut(),
+                };
+                let mut stream = Box::new(zlib::z_stream {
+                    next_in: ptr::null_mut(),
+                    avail_in: 0,
+                    total_in: 0,
+                    next_out: ptr::null_mut(),
+                    avail_out: 0,
+                    total_out: 0,
+                    msg: ptr::null_mut(),
+                    adler: 0,
+                    data_type: 0,
+                    reserved: 0,
+                    opaque,
+                    state: ptr::null_mut(),
+                    zalloc,
+                    zfree,
+                });
+                let res = unsafe {
+                    zlib::inflateInit2_(
+                        &mut *stream,
+                        window_bits,
+                        zlib::zlibVersion(),
+                        mem::size_of::<zlib::z_stream>() as c_int,
+                    )
+                };
+                if res == zlib::Z_OK {
+                    Ok(Decoder {
+                        stream: Some(stream),
+                        ended: false,
+                        fallback: None,
+                        resumed_at: None,
+                        gzip: None,
+                        check: Check::default(),
+                        budget,
+                        #[cfg(feature = "compress")]
+                        lzw: None,
+                        #[cfg(feature = "charset")]
+                        charset: None,
+                        stages: Stages::default(),
+                    })
+                } else if res == zlib::Z_MEM_ERROR && budget.is_some() {
+                    Err(budget_exhausted())
+                } else {
+                    Err(JsonStreamError::EncodingError(
+                        "Failed to decode the payload with gzip".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+    /// Transcode the decoded bytes to UTF-8 with `charset`.
+    #[cfg(feature = "charset")]
+    pub(crate) fn charset(mut self, charset: Option<Transcoder>) -> Self {
+        self.charset = charset;
+        self
+    }
+    /// Decode only the body of the json part of a multipart body, see `Multipart`.
+    pub(crate) fn multipart(mut self, multipart: Option<Multipart>) -> Self {
+        self.stages.multipart = multipart;
+        self
+    }
+    /// Decode the body embedded in a string of the decoded body, see `EmbeddedBody`.
+    pub(crate) fn embedded(mut self, embedded: Option<EmbeddedBody>) -> Self {
+        self.stages.embedded = embedded.map(Box::new);
+        self
+    }
+    /// When the compressed body cannot be inflated from its start, decode it as is if it
+    /// looks like json, or as raw deflate, instead of failing, for servers sending
+    /// another encoding than the one they announce. The body is kept until the first
+    /// decoded bytes, after which there is no fallback anymore.
+    pub(crate) fn fallback(mut self, enabled: bool) -> Self {
+        self.fallback = (enabled && self.stream.is_some()).then(Vec::new);
+        self
+    }
+    /// Decode a range of the compressed body starting at `offset`, which can only be
+    /// inflated if it starts a gzip member: the decoding fails otherwise, instead of
+    /// producing garbage. There is no fallback then, and a body compressed with LZW,
+    /// which cannot be resumed, fails right away.
+    pub(crate) fn resumed_at(mut self, offset: u64) -> Result<Self, JsonStreamError> {
+        #[cfg(feature = "compress")]
+        if self.lzw.is_some() {
+            return Err(JsonStreamError::EncodingError(format!(
+                "cannot resume compress at offset {}",
+                offset
+            )));
+        }
+        if self.stream.is_some() && offset > 0 {
+            self.fallback = None;
+            self.resumed_at = Some((offset, Vec::new()));
+        }
+        Ok(self)
+    }
+    /// Whether the decoded bytes are the input bytes, for an identity encoding in UTF-8.
+    pub(crate) fn is_passthrough(&self) -> bool {
+        #[cfg(feature = "compress")]
+        if self.lzw.is_some() {
+            return false;
+        }
+        #[cfg(feature = "charset")]
+        if self.charset.is_some() {
+            return false;
+        }
+        self.stream.is_none() && self.stages.is_empty()
+    }
+    /// Whether the body is inflated or decompressed, by this decoder or by the one of the
+    /// embedded body, rather than passed on as is, like after a fallback to plain json.
+    pub(crate) fn is_decompressing(&self) -> bool {
+        #[cfg(feature = "compress")]
+        if self.lzw.is_some() {
+            return true;
+        }
+        self.stream.is_some()
+            || self
+                .stages
+                .embedded
+                .as_ref()
+                .is_some_and(|embedded| embedded.is_decompressing())
+    }
+    /// Decode `input`, handing the decoded bytes to `output`. Input that is not enough to
+    /// produce output is kept by the decoder until the next call.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        if self.stages.is_empty() {
+            return self.transcode(input, output);
+        }
+        let mut stages = mem::take(&mut self.stages);
+        let mut output = output;
+        let mut staged = Ok(());
+        let decoded = self.transcode(input, |bytes| {
+            if staged.is_ok() {
+                staged = stages.feed(bytes, &mut output);
+            }
+        });
+        self.stages = stages;
+        decoded.and(staged)
+    }
+    fn transcode(
+        &mut self,
+        input: &[u8],
+        output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        // The bytes are inflated first, the charset applies to the decompressed body.
+        #[cfg(feature = "charset")]
+        if let Some(mut charset) = self.charset.take() {
+            let mut output = output;
+            let mut transcoded = Ok(());
+            let inflated = self.inflate(input, |bytes| {
+                if transcoded.is_ok() {
+                    transcoded = charset.transcode(bytes, false, &mut output);
+                }
+            });
+            self.charset = Some(charset);
+            return inflated.and(transcoded);
+        }
+        self.inflate(input, output)
+    }
+    /// Signal the end of the body, flushing the decoded bytes still pending to `output`.
+    #[cfg_attr(not(feature = "charset"), allow(unused_mut))]
+    pub fn finish(&mut self, mut output: impl FnMut(&[u8])) -> Result<(), JsonStreamError> {
+        // An empty body is not a truncated one, whatever its encoding: whether it is valid
+        // is up to `JsonStream::allow_empty_body`.
+        let empty = self
+            .stream
+            .as_ref()
+            .is_some_and(|stream| stream.total_in == 0)
+            && self
+                .resumed_at
+                .as_ref()
+                .is_none_or(|(_, head)| head.is_empty());
+        if self.stream.is_some() && !self.ended && !empty {
+            return Err(inflate_error("unexpected end of the compressed body"));
+        }
+        #[cfg(feature = "charset")]
+        if let Some(ref mut charset) = self.charset {
+            let stages = &mut self.stages;
+            let mut staged = Ok(());
+            charset.transcode(&[], true, &mut |bytes: &[u8]| {
+                if staged.is_ok() {
+                    staged = stages.feed(bytes, &mut output);
+                }
+            })?;
+            staged?;
+        }
+        self.stages.finish(output)
+    }
+    fn inflate(
+        &mut self,
+        input: &[u8],
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        if let Some((offset, mut head)) = self.resumed_at.take() {
+            head.extend_from_slice(input);
+            if head.len() < GZIP_MAGIC.len() {
+                self.resumed_at = Some((offset, head));
+                return Ok(());
+            }
+            if !head.starts_with(&GZIP_MAGIC) {
+                return Err(JsonStreamError::EncodingError(format!(
+                    "cannot resume gzip at arbitrary offset {}: the range does not start a \
+                     gzip member",
+                    offset
+                )));
+            }
+            return self.inflate(&head, output);
+        }
+        let mut received = match self.fallback.take() {
+            Some(received) => received,
+            None => return self.inflate_input(input, output),
+        };
+        received.extend_from_slice(input);
+        let mut decoded = false;
+        match self.inflate_input(input, |bytes| {
+            decoded |= !bytes.is_empty();
+            output(bytes)
+        }) {
+            Ok(()) if !decoded => self.fallback = Some(received),
+            Ok(()) => {}
+            Err(err) if !decoded => return self.salvage(&received, err, output),
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+    /// Decode `received`, the whole body so far, as is if it looks like json, or as raw
+    /// deflate. The error `err` of the announced encoding is returned otherwise.
+    fn salvage(
+        &mut self,
+        received: &[u8],
+        err: JsonStreamError,
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        if looks_like_json(received) {
+            if let Some(mut stream) = self.stream.take() {
+                unsafe { zlib::inflateEnd(&mut *stream) };
+            }
+            output(received);
+            return Ok(());
+        }
+        if let Some(ref mut stream) = self.stream {
+            if unsafe { zlib::inflateReset2(&mut **stream, -15) } == zlib::Z_OK {
+                self.ended = false;
+                self.check = Check::default();
+                let mut inflated = Vec::new();
+                if self
+                    .inflate_input(received, |bytes| inflated.extend_from_slice(bytes))
+                    .is_ok()
+                {
+                    output(&inflated);
+                    return Ok(());
+                }
+            }
+        }
+        Err(err)
+    }
+    fn inflate_input(
+        &mut self,
+        input: &[u8],
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        #[cfg(feature = "compress")]
+        if let Some(ref mut lzw) = self.lzw {
+            return lzw.decode(input, output);
+        }
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => {
+                output(input);
+                return Ok(());
+            }
+        };
+        let mut buffer = [0; OUTPUT_SIZE];
+        for input in input.chunks(c_uint::MAX as usize) {
+            stream.next_in = input.as_ptr() as *mut u8;
+            stream.avail_in = input.len() as c_uint;
+            loop {
+                if self.gzip.is_none() && stream.avail_in > 0 {
+                    // A zlib header never starts like a gzip one, its compression method
+                    // would be 15.
+                    let first = input[input.len() - stream.avail_in as usize];
+                    self.gzip = Some(first == GZIP_MAGIC[0]);
+                }
+                stream.next_out = buffer.as_mut_ptr();
+                stream.avail_out = cmp::min(buffer.len(), c_uint::MAX as usize) as c_uint;
+                let read = input.len() - stream.avail_in as usize;
+                // Stops after every deflate block, to know where the trailer starts.
+                let res = unsafe { zlib::inflate(&mut **stream, zlib::Z_BLOCK) };
+                let inflated = &buffer[..buffer.len() - stream.avail_out as usize];
+                self.check.update(inflated, self.gzip == Some(true));
+                output(inflated);
+                match self.check.trailer {
+                    Some(ref mut trailer) => {
+                        let read = &input[read..input.len() - stream.avail_in as usize];
+                        trailer.extend(read.iter().take(TRAILER_SIZE - trailer.len()));
+                    }
+                    // At the end of the last block.
+                    None if stream.data_type & 0xC0 == 0xC0 => {
+                        self.check.trailer = Some(Vec::with_capacity(TRAILER_SIZE));
+                    }
+                    None => {}
+                }
+                match res {
+                    // The output buffer was too small, there might be more output pending.
+                    zlib::Z_OK if stream.avail_out == 0 => {}
+                    // Stopped at the end of a block, what follows may end the stream even
+                    // without more input.
+                    zlib::Z_OK if stream.data_type & 0x80 != 0 => {}
+                    zlib::Z_OK => break,
+                    // No progress is possible until we get more input.
+                    zlib::Z_BUF_ERROR => break,
+                    zlib::Z_STREAM_END => {
+                        // Concatenated gzip members are decoded as one body.
+                        if stream.avail_in == 0 {
+                            self.ended = true;
+                            break;
+                        }
+                        unsafe { zlib::inflateReset(&mut **stream) };
+                        self.ended = false;
+                        self.gzip = None;
+                        self.check = Check::default();
+                    }
+                    zlib::Z_MEM_ERROR if self.budget.is_some() => return Err(budget_exhausted()),
+                    _ => {
+                        let msg = if stream.msg.is_null() {
+                            format!("zlib::inflate returned {}", res)
+                        } else {
+                            unsafe { CStr::from_ptr(stream.msg) }
+                                .to_string_lossy()
+                                .into_owned()
+                        };
+                        // zlib rejects a trailer not matching the bytes it produced, which
+                        // are checked here too to tell which of its values is wrong.
+                        let mismatch = match res {
+                            zlib::Z_DATA_ERROR => self.check.mismatch(self.gzip == Some(true)),
+                            _ => None,
+                        };
+                        return Err(match mismatch {
+                            Some(mismatch) => {
+                                JsonStreamError::ChecksumMismatch(mismatch.to_string())
+                            }
+                            None => inflate_error(&msg),
+                        });
+                    }
+                }
+            }
+        }
+        stream.next_in = ptr::null_mut();
+        Ok(())
+    }
+}
+
+/// The checksum and the length of a gzip member or a zlib stream, computed from the
+/// inflated bytes, and its trailer once the last deflate block has ended.
+#[derive(Default)]
+struct Check {
+    /// The CRC32 of a gzip member or the Adler-32 of a zlib stream, once bytes are inflated.
+    sum: Option<u32>,
+    /// The length modulo 2^32, like the ISIZE of a gzip trailer.
+    length: u32,
+    trailer: Option<Vec<u8>>,
+}
+
+impl Check {
+    fn update(&mut self, bytes: &[u8], gzip: bool) {
+        if bytes.is_empty() {
+            return;
+        }
+        let sum = self.sum(gzip) as _;
+        let (ptr, len) = (bytes.as_ptr(), bytes.len() as c_uint);
+        let sum = match gzip {
+            true => unsafe { zlib::crc32(sum, ptr, len) },
+            false => unsafe { zlib::adler32(sum, ptr, len) },
+        };
+        self.sum = Some(sum as u32);
+        self.length = self.length.wrapping_add(bytes.len() as u32);
+    }
+    fn sum(&self, gzip: bool) -> u32 {
+        self.sum.unwrap_or(if gzip { 0 } else { 1 })
+    }
+    /// The first value of the trailer not matching the inflated bytes: the CRC32 then
+    /// the ISIZE of a gzip member, stored little endian, or the Adler-32 of a zlib
+    /// stream, stored big endian.
+    fn mismatch(&self, gzip: bool) -> Option<&'static str> {
+        let trailer = self.trailer.as_deref()?;
+        let value = |range: std::ops::Range<usize>| -> Option<[u8; 4]> {
+            trailer.get(range)?.try_into().ok()
+        };
+        if !gzip {
+            return (u32::from_be_bytes(value(0..4)?) != self.sum(false))
+                .then_some("zlib adler32 mismatch");
+        }
+        if u32::from_le_bytes(value(0..4)?) != self.sum(true) {
+            return Some("gzip crc mismatch");
+        }
+        (u32::from_le_bytes(value(4..8)?) != self.length).then_some("gzip length mismatch")
+    }
+}
+
+/// The stages applied to the decoded body, in order.
+#[derive(Default)]
+struct Stages {
+    multipart: Option<Multipart>,
+    embedded: Option<Box<EmbeddedBody>>,
+}
+
+impl Stages {
+    fn is_empty(&self) -> bool {
+        self.multipart.is_none() && self.embedded.is_none()
+    }
+    fn feed(
+        &mut self,
+        bytes: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        match (&mut self.multipart, &mut self.embedded) {
+            (Some(multipart), Some(embedded)) => {
+                let mut embedded_res = Ok(());
+                multipart.feed(bytes, &mut |part: &[u8]| {
+                    if embedded_res.is_ok() {
+                        embedded_res = embedded.decode(part, output);
+                    }
+                })?;
+                embedded_res
+            }
+            (Some(multipart), None) => multipart.feed(bytes, output),
+            (None, Some(embedded)) => embedded.decode(bytes, output),
+            (None, None) => {
+                output(bytes);
+                Ok(())
+            }
+        }
+    }
+    fn finish(&mut self, mut output: impl FnMut(&[u8])) -> Result<(), JsonStreamError> {
+        if let Some(ref multipart) = self.multipart {
+            multipart.finish()?;
+        }
+        match self.embedded {
+            Some(ref mut embedded) => embedded.finish(&mut output),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The zlib `windowBits` conventions: 8 to 15 for a zlib stream, 0 to use the window
+/// size of the zlib header, -8 to -15 for raw deflate without header, +16 for a gzip
+/// stream only and +32 to detect a zlib or a gzip header.
+pub fn valid_window_bits(window_bits: c_int) -> bool {
+    matches!(window_bits, -15..=-8 | 0 | 8..=15 | 24..=31 | 32 | 40..=47)
+}
+
+/// Whether `bytes`, the start of a body, look like json: text starting like a json value.
+/// Compressed bytes are binary, with control characters and invalid UTF-8.
+fn looks_like_json(bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        // A character cut at the end of `bytes`.
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return false,
+    };
+    let text = text.trim_start();
+    !text
+        .chars()
+        .any(|c| c.is_control() && !c.is_ascii_whitespace())
+        && text.starts_with(|c: char| c.is_ascii_digit() || "[{\"-tfn".contains(c))
+}
+
+/// A failure to decompress the body, as opposed to a failure to parse it.
+fn inflate_error(msg: &str) -> JsonStreamError {
+    JsonStreamError::EncodingError(format!("gzip inflate error: {}", msg))
+}
+
+pub(crate) fn budget_exhausted() -> JsonStreamError {
+    JsonStreamError::EncodingError("The decompression budget is exhausted".to_string())
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        if let Some(ref mut stream) = self.stream {
+            unsafe { zlib::inflateEnd(&mut **stream) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoder;
+    use crate::stream::budget::DecompressionBudget;
+    use crate::stream::encoding::ContentEncoding;
+    use crate::util::JsonStreamError;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::{Compression, Crc};
+    use std::io::Write;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decode_gzip_in_small_chunks() {
+        let json: String = (0..1000).map(|i| format!("{},", i)).collect();
+        let compressed = gzip(json.as_bytes());
+        for size in [1, 7, 512, compressed.len()] {
+            let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+            let mut res: Vec<u8> = Vec::new();
+            for chunk in compressed.chunks(size) {
+                decoder.decode(chunk, |b| res.extend_from_slice(b)).unwrap();
+            }
+            assert_eq!(res, json.as_bytes());
+        }
+    }
+    #[test]
+    fn decode_without_progress() {
+        // Without input, or with too little of it, inflate returns Z_BUF_ERROR, which only
+        // means that it needs more input.
+        let json = b"[1, 2, 3]";
+        let compressed = gzip(json);
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let mut res: Vec<u8> = Vec::new();
+        decoder.decode(&[], |_| {}).unwrap();
+        for byte in &compressed {
+            decoder
+                .decode(&[*byte], |b| res.extend_from_slice(b))
+                .unwrap();
+            decoder.decode(&[], |_| {}).unwrap();
+        }
+        decoder.finish(|_| {}).unwrap();
+        assert_eq!(res, json);
+    }
+    #[test]
+    fn decode_gzip_members() {
+        let mut compressed = gzip(b"[1, 2");
+        compressed.extend(gzip(b", 3]"));
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let mut res: Vec<u8> = Vec::new();
+        decoder
+            .decode(&compressed, |b| res.extend_from_slice(b))
+            .unwrap();
+        assert_eq!(res, b"[1, 2, 3]");
+    }
+    #[test]
+    fn decode_gzip_optional_header_fields() {
+        let json = br#"[{"name": "a"}, {"name": "b"}]"#;
+        // FHCRC | FEXTRA | FNAME | FCOMMENT
+        let mut compressed = vec![0x1f, 0x8b, 8, 0x1e, 0, 0, 0, 0, 0, 255];
+        compressed.extend([4, 0, b'a', b'b', 1, 2]);
+        compressed.extend(b"cities.json\0");
+        compressed.extend(b"a comment\0");
+        let mut crc = Crc::new();
+        crc.update(&compressed);
+        compressed.extend((crc.sum() as u16).to_le_bytes());
+        let mut encoder = DeflateEncoder::new(compressed, Compression::default());
+        encoder.write_all(json).unwrap();
+        let mut compressed = encoder.finish().unwrap();
+        let mut crc = Crc::new();
+        crc.update(json);
+        compressed.extend(crc.sum().to_le_bytes());
+        compressed.extend((json.len() as u32).to_le_bytes());
+        for size in [1, 5, compressed.len()] {
+            let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+            let mut res: Vec<u8> = Vec::new();
+            for chunk in compressed.chunks(size) {
+                decoder.decode(chunk, |b| res.extend_from_slice(b)).unwrap();
+            }
+            assert_eq!(res, json);
+        }
+        // The header checksum is verified.
+        let len = 10 + 6 + 12 + 10;
+        compressed[len] ^= 1;
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        assert!(decoder.decode(&compressed, |_| {}).is_err());
+    }
+    #[test]
+    fn decode_raw_deflate() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"[1, 2, 3]").unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        assert!(decoder.decode(&compressed, |_| {}).is_err());
+        let mut decoder = Decoder::with_window_bits(&ContentEncoding::Gzip, None, -15).unwrap();
+        let mut res: Vec<u8> = Vec::new();
+        decoder
+            .decode(&compressed, |b| res.extend_from_slice(b))
+            .unwrap();
+        assert_eq!(res, b"[1, 2, 3]");
+        for invalid in [-16, -7, 7, 16, 48] {
+            assert!(Decoder::with_window_bits(&ContentEncoding::Gzip, None, invalid).is_err());
+        }
+    }
+    #[test]
+    fn decode_invalid_gzip() {
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        assert!(decoder.decode(b"[1, 2, 3]", |_| {}).is_err());
+
+        // A deflate block of the reserved type 3.
+        let mut compressed = gzip(b"[1, 2, 3]");
+        compressed[10] = 0b111;
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let err = decoder.decode(&compressed, |_| {}).unwrap_err();
+        assert_eq!(err.to_string(), "gzip inflate error: invalid block type");
+    }
+    #[test]
+    fn decode_truncated_gzip() {
+        let compressed = gzip(b"[1, 2, 3]");
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        decoder.decode(&compressed, |_| {}).unwrap();
+        decoder.finish(|_| {}).unwrap();
+
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        decoder
+            .decode(&compressed[..compressed.len() - 3], |_| {})
+            .unwrap();
+        let err = decoder.finish(|_| {}).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "gzip inflate error: unexpected end of the compressed body"
+        );
+    }
+    #[test]
+    fn decode_corrupted_gzip() {
+        let json = br#"[{"name": "Andorra"}]"#;
+        // Stored blocks, so that a corrupted byte still decodes to valid json.
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(json).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let pos = compressed.windows(7).position(|w| w == b"Andorra").unwrap();
+
+        let mut corrupted = compressed.clone();
+        corrupted[pos] = b'X';
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let mut res: Vec<u8> = Vec::new();
+        let err = decoder
+            .decode(&corrupted, |b| res.extend_from_slice(b))
+            .unwrap_err();
+        assert_eq!(res, br#"[{"name": "Xndorra"}]"#);
+        assert!(matches!(err, JsonStreamError::ChecksumMismatch(_)));
+        assert_eq!(err.to_string(), "gzip crc mismatch");
+        // The trailer is found across chunks too.
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let err = corrupted
+            .chunks(1)
+            .try_for_each(|chunk| decoder.decode(chunk, |_| {}))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "gzip crc mismatch");
+        // A block that cannot be inflated is not a checksum mismatch.
+        let mut corrupted = gzip(json);
+        corrupted[10] = 0xff;
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let err = decoder.decode(&corrupted, |_| {}).unwrap_err();
+        assert!(matches!(err, JsonStreamError::EncodingError(_)));
+
+        let mut corrupted = compressed;
+        let isize = corrupted.len() - 4;
+        corrupted[isize] ^= 1;
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let err = decoder.decode(&corrupted, |_| {}).unwrap_err();
+        assert!(matches!(err, JsonStreamError::ChecksumMismatch(_)));
+        assert_eq!(err.to_string(), "gzip length mismatch");
+
+        // The zlib header is detected too, its trailer holding an Adler-32.
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::none());
+        encoder.write_all(json).unwrap();
+        let mut corrupted = encoder.finish().unwrap();
+        let pos = corrupted.windows(7).position(|w| w == b"Andorra").unwrap();
+        corrupted[pos] = b'X';
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+        let err = decoder.decode(&corrupted, |_| {}).unwrap_err();
+        assert!(matches!(err, JsonStreamError::ChecksumMismatch(_)));
+        assert_eq!(err.to_string(), "zlib adler32 mismatch");
+    }
+    #[test]
+    fn decode_with_budget() {
+        let compressed = gzip(&[b'1'; 100_000]);
+        let budget = DecompressionBudget::new(1024);
+        assert!(Decoder::new(&ContentEncoding::Gzip, Some(&budget)).is_err());
+        assert_eq!(budget.used(), 0);
+
+        let budget = DecompressionBudget::new(1024 * 1024);
+        let mut first = Decoder::new(&ContentEncoding::Gzip, Some(&budget)).unwrap();
+        first.decode(&compressed, |_| {}).unwrap();
+        let used = budget.used();
+        assert!(used > 0);
+        let mut second = Decoder::new(&ContentEncoding::Gzip, Some(&budget)).unwrap();
+        second.decode(&compressed, |_| {}).unwrap();
+        assert_eq!(budget.used(), 2 * used);
+        drop(first);
+        drop(second);
+        assert_eq!(budget.used(), 0);
+
+        let budget = DecompressionBudget::new(used + used / 2);
+        let mut first = Decoder::new(&ContentEncoding::Gzip, Some(&budget)).unwrap();
+        first.decode(&compressed, |_| {}).unwrap();
+        let second = Decoder::new(&ContentEncoding::Gzip, Some(&budget));
+        let failed = second.and_then(|mut second| second.decode(&compressed, |_| {}));
+        assert!(failed.is_err());
+    }
+    #[test]
+    fn fallback() {
+        let json = b"[1, 2, 3]";
+        let mut deflated = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflated.write_all(json).unwrap();
+        let deflated = deflated.finish().unwrap();
+        for body in [&deflated[..], json] {
+            let mut decoder = Decoder::new(&ContentEncoding::Gzip, None)
+                .unwrap()
+                .fallback(true);
+            let mut res: Vec<u8> = Vec::new();
+            for byte in body {
+                decoder
+                    .decode(&[*byte], |b| res.extend_from_slice(b))
+                    .unwrap();
+            }
+            decoder.finish(|_| {}).unwrap();
+            assert_eq!(res, json);
+            let mut decoder = Decoder::new(&ContentEncoding::Gzip, None).unwrap();
+            assert!(decoder.decode(body, |_| {}).is_err());
+        }
+        // Not at the start of the body.
+        let long: String = (0..1000).map(|i| format!("{},", i)).collect();
+        let mut body = gzip(long.as_bytes());
+        body.truncate(body.len() / 2);
+        body.extend_from_slice(json);
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None)
+            .unwrap()
+            .fallback(true);
+        assert!(decoder.decode(&body, |_| {}).is_err());
+        let mut decoder = Decoder::new(&ContentEncoding::Gzip, None)
+            .unwrap()
+            .fallback(true);
+        assert!(decoder.decode(b"\xff\xff\xff", |_| {}).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use futures_core::stream::{FusedStream, Stream};
+use serde::de::DeserializeOwned;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A stream skipping the elements of a `JsonStream` whose key was seen recently, created
+/// by `JsonStream::dedup_by_key`.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct DedupJsonStream<T, K, F> {
+    stream: JsonStream<T>,
+    key_fn: F,
+    seen: RecentKeys<K>,
+}
+
+impl<T, K, F> DedupJsonStream<T, K, F> {
+    pub(crate) fn new(stream: JsonStream<T>, key_fn: F, capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "the capacity of the set of keys must be positive"
+        );
+        DedupJsonStream {
+            stream,
+            key_fn,
+            seen: RecentKeys {
+                capacity,
+                hasher: RandomState::new(),
+                buckets: HashMap::new(),
+                nodes: Vec::new(),
+                head: NONE,
+                tail: NONE,
+            },
+        }
+    }
+}
+
+/// The index of no node.
+const NONE: usize = usize::MAX;
+
+/// The `capacity` most recently seen keys, in a list linked by index from the most recent
+/// one. The keys are only stored in the nodes, they are looked up by hash.
+struct RecentKeys<K> {
+    capacity: usize,
+    hasher: RandomState,
+    /// The indices in `nodes` of the keys with a given hash.
+    buckets: HashMap<u64, Vec<usize>>,
+    nodes: Vec<Node<K>>,
+    /// The most and the least recently seen keys.
+    head: usize,
+    tail: usize,
+}
+
+struct Node<K> {
+    key: K,
+    prev: usize,
+    next: usize,
+}
+
+impl<K: Hash + Eq> RecentKeys<K> {
+    /// Mark `key` as the most recently seen, forgetting the least recently seen key if
+    /// there are too many. Returns whether `key` was already known.
+    fn insert(&mut self, key: K) -> bool {
+        let hash = self.hasher.hash_one(&key);
+        let found = self.buckets.get(&hash).and_then(|bucket| {
+            bucket
+                .iter()
+                .copied()
+                .find(|&index| self.nodes[index].key == key)
+        });
+        if let Some(index) = found {
+            self.unlink(index);
+            self.push_front(index);
+            return true;
+        }
+        let index = if self.nodes.len() < self.capacity {
+            self.nodes.push(Node {
+                key,
+                prev: NONE,
+                next: NONE,
+            });
+            self.nodes.len() - 1
+        } else {
+            let index = self.tail;
+            self.unlink(index);
+            let evicted = self.hasher.hash_one(&self.nodes[index].key);
+            if let Some(bucket) = self.buckets.get_mut(&evicted) {
+                bucket.retain(|&other| other != index);
+                if bucket.is_empty() {
+                    self.buckets.remove(&evicted);
+                }
+            }
+            self.nodes[index].key = key;
+            index
+        };
+        self.buckets.entry(hash).or_default().push(index);
+        self.push_front(index);
+        false
+    }
+    fn unlink(&mut self, index: usize) {
+        let Node { prev, next, .. } = self.nodes[index];
+        match prev {
+            NONE => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NONE => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].prev = NONE;
+        self.nodes[index].next = self.head;
+        match self.head {
+            NONE => self.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        self.head = index;
+    }
+}
+
+// The compiler adds T, K and F: Unpin bounds, but none of them is pinned.
+impl<T, K, F> Unpin for DedupJsonStream<T, K, F> {}
+
+impl<T, K, F> Stream for DedupJsonStream<T, K, F>
+where
+    T: DeserializeOwned,
+    K: Hash + Eq,
+    F: FnMut(&T) -> K,
+{
+    type Item = Result<T, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        let this = self.get_mut();
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => {
+                    let key = (this.key_fn)(&item);
+                    if this.seen.insert(key) {
+                        continue;
+                    }
+                    return Poll::Ready(Some(Ok(item)));
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T, K, F> FusedStream for DedupJsonStream<T, K, F>
+where
+    T: DeserializeOwned,
+    K: Hash + Eq,
+    F: FnMut(&T) -> K,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::json_stream::JsonStream;
+    use crate::util::test_server::{chunked, respond, City, CITIES};
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn dedup_by_key() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 64)).await, 1, 0);
+        let countries: Vec<String> = stream
+            .dedup_by_key(|city| city.country.clone(), 100)
+            .map(|city| city.unwrap().country)
+            .collect()
+            .await;
+        assert_eq!(countries, ["AD", "AE", "AF", "BE", "FR", "JP", "US"]);
+
+        // Only the 2 most recently seen keys are remembered, 1 being seen again before 3
+        // arrives, 2 is forgotten instead of it.
+        let stream: JsonStream<u32> = JsonStream::new(
+            respond(chunked(b"[1, 2, 1, 3, 1, 2, 2, 4, 3, 4]", 4)).await,
+            1,
+            0,
+        );
+        let res: Vec<u32> = stream
+            .dedup_by_key(|&item| item, 2)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(res, [1, 2, 3, 2, 4, 3]);
+    }
+}
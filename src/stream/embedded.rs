@@ -0,0 +1,334 @@
+use crate::stream::budget::DecompressionBudget;
+use crate::stream::decoder::Decoder;
+use crate::stream::encoding::ContentEncoding;
+use crate::stream::path::{JsonPath, PathTracker};
+use crate::util::JsonStreamError;
+
+/// Extracts the content of the json string at a path of a json document, unescaped, like
+/// the `data.payload` field of `{"data": {"payload": "H4sIAAAA..."}}`, as the document is
+/// received. The path is made of dot separated keys of nested objects, as for
+/// `JsonStream::at_path`. The rest of the document after the string is ignored.
+pub struct StringField {
+    path: String,
+    state: FieldState,
+}
+
+enum FieldState {
+    Scanning(PathTracker),
+    /// In the string, with the escape sequence being read, if any.
+    Inside(Vec<u8>),
+    Done,
+}
+
+impl StringField {
+    /// Extract the string at `path`.
+    pub fn new(path: &str) -> Self {
+        StringField {
+            path: path.to_string(),
+            state: FieldState::Scanning(PathTracker::new(JsonPath::new(path, &[])).for_string()),
+        }
+    }
+    /// Handle the next bytes of the document, handing the bytes of the string to `output`.
+    pub fn feed(
+        &mut self,
+        mut input: &[u8],
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        while !input.is_empty() {
+            match self.state {
+                FieldState::Scanning(ref mut tracker) => {
+                    let mut start = None;
+                    for (i, &byte) in input.iter().enumerate() {
+                        if tracker.feed(byte)? {
+                            start = Some(i + 1);
+                            break;
+                        }
+                    }
+                    let Some(start) = start else {
+                        return Ok(());
+                    };
+                    input = &input[start..];
+                    self.state = FieldState::Inside(Vec::new());
+                }
+                FieldState::Inside(ref mut escape) if escape.is_empty() => {
+                    let end = input
+                        .iter()
+                        .position(|&byte| byte == b'"' || byte == b'\\')
+                        .unwrap_or(input.len());
+                    output(&input[..end]);
+                    match input.get(end) {
+                        None => return Ok(()),
+                        Some(b'"') => self.state = FieldState::Done,
+                        Some(_) => escape.push(b'\\'),
+                    }
+                    input = &input[end + 1..];
+                }
+                FieldState::Inside(ref mut escape) => {
+                    escape.push(input[0]);
+                    input = &input[1..];
+                    if let Some(unescaped) = unescape(escape)? {
+                        output(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                        escape.clear();
+                    }
+                }
+                FieldState::Done => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+    /// Check at the end of the document that the string was found whole.
+    pub fn finish(&self) -> Result<(), JsonStreamError> {
+        match self.state {
+            FieldState::Scanning(ref tracker) => Err(tracker.not_found()),
+            FieldState::Inside(_) => Err(JsonStreamError::json(format!(
+                "unexpected end of the string at `{}`",
+                self.path
+            ))),
+            FieldState::Done => Ok(()),
+        }
+    }
+}
+
+/// The character of the escape sequence `escape`, or `None` if it is incomplete.
+fn unescape(escape: &[u8]) -> Result<Option<char>, JsonStreamError> {
+    let unescaped = match escape[1] {
+        b'"' => '"',
+        b'\\' => '\\',
+        b'/' => '/',
+        b'b' => '\u{8}',
+        b'f' => '\u{c}',
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'u' if escape.len() < 6 => return Ok(None),
+        b'u' => std::str::from_utf8(&escape[2..])
+            .ok()
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid_escape(escape))?,
+        _ => return Err(invalid_escape(escape)),
+    };
+    Ok(Some(unescaped))
+}
+
+fn invalid_escape(escape: &[u8]) -> JsonStreamError {
+    JsonStreamError::json(format!(
+        "invalid escape sequence in the string: {}",
+        String::from_utf8_lossy(escape)
+    ))
+}
+
+/// Decodes base64 as it is received, in the standard or the URL safe alphabet, with or
+/// without padding. Whitespace, like the line breaks of MIME, is skipped.
+#[derive(Debug, Default)]
+pub struct Base64Decoder {
+    /// The values of the characters of the incomplete group of 4.
+    group: [u8; 4],
+    len: usize,
+    padded: bool,
+}
+
+impl Base64Decoder {
+    /// Create a decoder at the start of the base64 text.
+    pub fn new() -> Self {
+        Base64Decoder::default()
+    }
+    /// Decode `input`, handing the decoded bytes to `output`. The characters of an
+    /// incomplete group are kept until the next call.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        let mut decoded = Vec::with_capacity(input.len() / 4 * 3 + 3);
+        for &byte in input {
+            if byte.is_ascii_whitespace() {
+                continue;
+            }
+            if byte == b'=' {
+                match self.len {
+                    0 if self.padded => {}
+                    2 | 3 => {
+                        self.flush(&mut decoded);
+                        self.padded = true;
+                    }
+                    _ => return Err(invalid_base64("misplaced padding")),
+                }
+                continue;
+            }
+            if self.padded {
+                return Err(invalid_base64("data after the padding"));
+            }
+            self.group[self.len] = sextet(byte).ok_or_else(|| {
+                invalid_base64(&format!("unexpected character {:?}", byte as char))
+            })?;
+            self.len += 1;
+            if self.len == 4 {
+                self.flush(&mut decoded);
+            }
+        }
+        output(&decoded);
+        Ok(())
+    }
+    /// Signal the end of the input, decoding the last group when it is not padded.
+    pub fn finish(&mut self, mut output: impl FnMut(&[u8])) -> Result<(), JsonStreamError> {
+        if self.len == 1 {
+            return Err(invalid_base64("truncated input"));
+        }
+        let mut decoded = Vec::new();
+        self.flush(&mut decoded);
+        output(&decoded);
+        Ok(())
+    }
+    /// Decode the group, complete or not.
+    fn flush(&mut self, decoded: &mut Vec<u8>) {
+        let [a, b, c, d] = self.group;
+        let bytes = [a << 2 | b >> 4, b << 4 | c >> 2, c << 6 | d];
+        decoded.extend_from_slice(&bytes[..self.len.saturating_sub(1)]);
+        self.group = [0; 4];
+        self.len = 0;
+    }
+}
+
+/// The value of a base64 character.
+fn sextet(byte: u8) -> Option<u8> {
+    match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    }
+}
+
+fn invalid_base64(msg: &str) -> JsonStreamError {
+    JsonStreamError::EncodingError(format!("Invalid base64: {}", msg))
+}
+
+/// The body embedded in a string of the received json document as base64, compressed or
+/// not, see `JsonStream::base64_field`: the string is extracted, then decoded from base64,
+/// then inflated.
+pub(crate) struct EmbeddedBody {
+    field: StringField,
+    base64: Base64Decoder,
+    /// Inflates the decoded bytes, or passes them as is when they look like json.
+    inner: Decoder,
+    /// The scratch buffers of the base64 and the decoded bytes.
+    text: Vec<u8>,
+    raw: Vec<u8>,
+}
+
+impl EmbeddedBody {
+    pub(crate) fn new(
+        path: &str,
+        budget: Option<&DecompressionBudget>,
+    ) -> Result<Self, JsonStreamError> {
+        Ok(EmbeddedBody {
+            field: StringField::new(path),
+            base64: Base64Decoder::new(),
+            inner: Decoder::new(&ContentEncoding::Gzip, budget)?.fallback(true),
+            text: Vec::new(),
+            raw: Vec::new(),
+        })
+    }
+    /// Handle the next bytes of the document, handing the bytes of the embedded body to
+    /// `output`. It is a trait object since the inner decoder could have stages of its own.
+    pub(crate) fn decode(
+        &mut self,
+        input: &[u8],
+        output: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        let (text, raw) = (&mut self.text, &mut self.raw);
+        text.clear();
+        self.field
+            .feed(input, |bytes| text.extend_from_slice(bytes))?;
+        raw.clear();
+        self.base64
+            .decode(text, |bytes| raw.extend_from_slice(bytes))?;
+        self.inner.decode(raw, output)
+    }
+    /// Whether the embedded body is inflated, rather than passed on as is.
+    pub(crate) fn is_decompressing(&self) -> bool {
+        self.inner.is_decompressing()
+    }
+    /// Check at the end of the document that the embedded body was whole.
+    pub(crate) fn finish(&mut self, output: &mut dyn FnMut(&[u8])) -> Result<(), JsonStreamError> {
+        self.field.finish()?;
+        let raw = &mut self.raw;
+        raw.clear();
+        self.base64.finish(|bytes| raw.extend_from_slice(bytes))?;
+        self.inner.decode(raw, &mut *output)?;
+        self.inner.finish(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Base64Decoder, StringField};
+
+    fn base64(text: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+        let mut decoder = Base64Decoder::new();
+        let mut out = Vec::new();
+        for chunk in text.chunks(chunk_size) {
+            decoder
+                .decode(chunk, |bytes| out.extend_from_slice(bytes))
+                .map_err(|err| err.to_string())?;
+        }
+        decoder
+            .finish(|bytes| out.extend_from_slice(bytes))
+            .map_err(|err| err.to_string())?;
+        Ok(out)
+    }
+
+    fn field(json: &[u8], path: &str, chunk_size: usize) -> Result<Vec<u8>, String> {
+        let mut field = StringField::new(path);
+        let mut out = Vec::new();
+        for chunk in json.chunks(chunk_size) {
+            field
+                .feed(chunk, |bytes| out.extend_from_slice(bytes))
+                .map_err(|err| err.to_string())?;
+        }
+        field.finish().map_err(|err| err.to_string())?;
+        Ok(out)
+    }
+
+    #[test]
+    fn decode_base64() {
+        for chunk_size in [1, 3, 100] {
+            assert_eq!(
+                base64(b"aGVsbG8gd29ybGQ=", chunk_size).unwrap(),
+                b"hello world"
+            );
+            assert_eq!(
+                base64(b"aGVsbG8gd29ybGQ", chunk_size).unwrap(),
+                b"hello world"
+            );
+            assert_eq!(base64(b"aGVs\r\nbG8h", chunk_size).unwrap(), b"hello!");
+            assert_eq!(base64(b"YQ==", chunk_size).unwrap(), b"a");
+            assert_eq!(base64(b"-_-_", chunk_size).unwrap(), [0xfb, 0xff, 0xbf]);
+            assert_eq!(base64(b"+/+/", chunk_size).unwrap(), [0xfb, 0xff, 0xbf]);
+            assert_eq!(base64(b"", chunk_size).unwrap(), b"");
+            assert!(base64(b"YQ==YQ==", chunk_size).is_err());
+            assert!(base64(b"Y===", chunk_size).is_err());
+            assert!(base64(b"aGVsb", chunk_size).is_err());
+            assert!(base64(b"aGV*", chunk_size).is_err());
+        }
+    }
+
+    #[test]
+    fn string_field() {
+        let json = r#"{"id": "x", "data": {"n": [1, "\""], "payload": "a\"b\\c\/dé\u00e9\n"}}"#;
+        let json = json.as_bytes();
+        for chunk_size in [1, 2, 7, json.len()] {
+            assert_eq!(
+                field(json, "data.payload", chunk_size).unwrap(),
+                "a\"b\\c/déé\n".as_bytes()
+            );
+        }
+        assert!(field(json, "data.n", 4).is_err());
+        assert!(field(json, "missing", 4).is_err());
+        assert!(field(br#"{"a": "abc"#, "a", 4).is_err());
+        assert!(field(br#"{"a": "\x"}"#, "a", 4).is_err());
+    }
+}
@@ -1,11 +1,20 @@
 use std::str::FromStr;
 
+use http::HeaderMap;
+
 use crate::JsonStreamError;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ContentEncoding {
+    /// No `Content-Encoding`, or an unsupported one.
+    #[default]
     None,
+    /// The server explicitly sent `Content-Encoding: identity`, decoded like `None`.
+    Identity,
     Gzip,
+    /// The LZW of the Unix `compress` tool. Legacy, only sent by very old servers.
+    #[cfg(feature = "compress")]
+    Compress,
 }
 
 impl FromStr for ContentEncoding {
@@ -14,7 +23,95 @@ impl FromStr for ContentEncoding {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "gzip" => Ok(ContentEncoding::Gzip),
+            "identity" => Ok(ContentEncoding::Identity),
+            #[cfg(feature = "compress")]
+            "compress" | "x-compress" => Ok(ContentEncoding::Compress),
             _ => Ok(ContentEncoding::None),
         }
     }
 }
+
+impl ContentEncoding {
+    /// The `Accept-Encoding` of a request, listing the encodings decoded with the
+    /// features enabled.
+    pub fn accept_encoding() -> &'static str {
+        #[cfg(feature = "compress")]
+        return "gzip, compress";
+        #[cfg(not(feature = "compress"))]
+        "gzip"
+    }
+    /// Detect the encoding from the `Content-Encoding` header of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(http::header::CONTENT_ENCODING)
+            .and_then(|content_encoding| content_encoding.to_str().ok())
+            .and_then(|content_encoding| ContentEncoding::from_str(content_encoding).ok())
+            .unwrap_or(ContentEncoding::None)
+    }
+    /// Detect the encoding from the `Content-Type` header of a response serving a gzip
+    /// file as is, like `application/gzip`.
+    pub fn from_content_type(headers: &HeaderMap) -> Self {
+        let media_type = headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| content_type.split(';').next())
+            .map(str::trim);
+        match media_type {
+            Some(media_type)
+                if media_type.eq_ignore_ascii_case("application/gzip")
+                    || media_type.eq_ignore_ascii_case("application/x-gzip") =>
+            {
+                ContentEncoding::Gzip
+            }
+            _ => ContentEncoding::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentEncoding;
+    use std::str::FromStr;
+
+    #[test]
+    fn from_content_type() {
+        let encoding = |content_type: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(http::header::CONTENT_TYPE, content_type.parse().unwrap());
+            ContentEncoding::from_content_type(&headers)
+        };
+        assert_eq!(encoding("application/gzip"), ContentEncoding::Gzip);
+        assert_eq!(
+            encoding("Application/X-Gzip; name=a.json.gz"),
+            ContentEncoding::Gzip
+        );
+        assert_eq!(encoding("application/json"), ContentEncoding::None);
+        assert_eq!(
+            ContentEncoding::from_content_type(&http::HeaderMap::new()),
+            ContentEncoding::None
+        );
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(
+            ContentEncoding::from_str("gzip").unwrap(),
+            ContentEncoding::Gzip
+        );
+        assert_eq!(
+            ContentEncoding::from_str("identity").unwrap(),
+            ContentEncoding::Identity
+        );
+        assert_eq!(
+            ContentEncoding::from_str("br").unwrap(),
+            ContentEncoding::None
+        );
+        #[cfg(feature = "compress")]
+        for compress in ["compress", "x-compress"] {
+            assert_eq!(
+                ContentEncoding::from_str(compress).unwrap(),
+                ContentEncoding::Compress
+            );
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use futures_core::stream::Stream;
+use hyper::body::{Body, Bytes};
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::body::ResponseBody;
+use crate::util::JsonStreamError;
+
+/// The body of an error response, streamed as it arrives instead of being collected in the
+/// `JsonStreamError::ApiError`. Taken with `JsonStream::take_error_body`.
+///
+/// The body is yielded as received, without decompressing it.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct ErrorBody {
+    body: ResponseBody,
+}
+
+impl ErrorBody {
+    pub(crate) fn new(body: ResponseBody) -> Self {
+        ErrorBody { body }
+    }
+}
+
+impl fmt::Debug for ErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ErrorBody")
+    }
+}
+
+impl Stream for ErrorBody {
+    type Item = Result<Bytes, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, JsonStreamError>>> {
+        let body = &mut self.get_mut().body;
+        loop {
+            match Pin::new(&mut *body).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(frame))) => {
+                    // Trailers carry no body bytes, they are skipped.
+                    if let Ok(data) = frame.into_data() {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => return Poll::Ready(None),
+            }
+        }
+    }
+}
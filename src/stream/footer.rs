@@ -0,0 +1,107 @@
+use futures_core::stream::{FusedStream, Stream};
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A `JsonStream` also parsing the footer following the array, created by
+/// `JsonStream::with_footer`. The footer is made of the members of the object enclosing
+/// the array that follow it, like the `checksum` and `count` of
+/// `{"items": [...], "checksum": "...", "count": 2}`.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct FooterJsonStream<T, F> {
+    stream: JsonStream<T>,
+    phantom: PhantomData<fn() -> F>,
+}
+
+impl<T, F> FooterJsonStream<T, F> {
+    pub(crate) fn new(stream: JsonStream<T>) -> Self {
+        FooterJsonStream {
+            stream,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned, F: DeserializeOwned> FooterJsonStream<T, F> {
+    /// The footer, once the object enclosing the array is closed, which is at the latest
+    /// when the stream yields `None`. The footer is deserialized on every call.
+    ///
+    /// Returns `None` before then, or if the stream failed before reaching the footer.
+    pub fn footer(&self) -> Option<Result<F, JsonStreamError>> {
+        let bytes = self.stream.footer_bytes()?;
+        Some(
+            serde_json::from_slice(bytes)
+                .map_err(|err| JsonStreamError::json(format!("Invalid footer, {}", err))),
+        )
+    }
+    /// The underlying stream, for instance for its `stats`.
+    pub fn get_ref(&self) -> &JsonStream<T> {
+        &self.stream
+    }
+}
+
+// The compiler adds T: Unpin and F: Unpin bounds, but a `JsonStream<T>` is Unpin and no F
+// is stored.
+impl<T, F> Unpin for FooterJsonStream<T, F> {}
+
+impl<T: DeserializeOwned, F> Stream for FooterJsonStream<T, F> {
+    type Item = Result<T, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        Pin::new(&mut self.get_mut().stream).poll_next(cx)
+    }
+}
+
+impl<T: DeserializeOwned, F> FusedStream for FooterJsonStream<T, F> {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::json_stream::JsonStream;
+    use crate::util::test_server::{chunked, respond, City, CITIES};
+    use futures_util::StreamExt;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Footer {
+        checksum: String,
+        count: usize,
+    }
+
+    #[tokio::test]
+    async fn footer() {
+        let mut json = br#"{"total": 12, "cities": "#.to_vec();
+        json.extend_from_slice(CITIES);
+        json.extend_from_slice(br#", "checksum": "abc", "count": 12}"#);
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(&json, 16)).await, 2, 0);
+        let mut stream = stream.with_footer::<Footer>();
+        assert!(stream.footer().is_none());
+        let mut count = 0;
+        while let Some(city) = stream.next().await {
+            city.unwrap();
+            count += 1;
+        }
+        let footer = stream.footer().unwrap().unwrap();
+        assert_eq!((footer.checksum.as_str(), footer.count), ("abc", count));
+
+        // The footer combines with the path of the array.
+        let json = br#"{"data": {"items": [1, 2], "count": 2}, "page": 1}"#;
+        let stream: JsonStream<u32> =
+            JsonStream::at_path_with_meta(respond(chunked(json, 4)).await, "data.items", &[]);
+        let mut stream = stream.with_footer::<serde_json::Value>();
+        assert_eq!(stream.by_ref().count().await, 2);
+        assert_eq!(
+            stream.footer().unwrap().unwrap(),
+            serde_json::json!({"count": 2})
+        );
+    }
+}
@@ -1,24 +1,72 @@
 use futures_core::stream::{FusedStream, Stream};
+#[cfg(feature = "sink")]
+use futures_sink::Sink;
 use http::response::Parts;
-use http::StatusCode;
-use serde::de::DeserializeOwned;
-use std::ffi::{c_int, c_uint};
-use std::future::Future;
+use http::{header, request, HeaderMap, Method, Request, Response, StatusCode, Version};
+use http_body_util::Empty;
+#[cfg(feature = "roundtrip")]
+use serde::de;
+use serde::de::{DeserializeOwned, DeserializeSeed, IgnoredAny};
+#[cfg(feature = "roundtrip")]
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::c_int;
+use std::future::{poll_fn, Future};
+use std::hash::Hash;
+#[cfg(feature = "roundtrip")]
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
 use std::pin::Pin;
-use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "sink")]
+use std::task::ready;
 use std::task::{Context, Poll};
+#[cfg(feature = "timeout")]
+use std::time::Duration;
+use std::time::{Instant, SystemTime};
 
-use crate::ffi::{zalloc, zfree};
-use crate::stream::partial_json::PartialJson;
-use crate::stream::ZType;
-use hyper::body::{Body, Incoming};
-use hyper_util::client::legacy::ResponseFuture;
-use libz_sys as zlib;
+use crate::stream::batched::Batched;
+use crate::stream::body::{BoxedBody, ResponseBody};
+use crate::stream::budget::DecompressionBudget;
+#[cfg(feature = "charset")]
+use crate::stream::charset::Transcoder;
+#[cfg(feature = "timeout")]
+use crate::stream::clock::{Clock, Timeout};
+use crate::stream::content_range::ContentRange;
+use crate::stream::decoder::Decoder;
+use crate::stream::dedup::DedupJsonStream;
+use crate::stream::embedded::EmbeddedBody;
+use crate::stream::error_body::ErrorBody;
+use crate::stream::footer::FooterJsonStream;
+use crate::stream::multipart::Multipart;
+#[cfg(feature = "roundtrip")]
+use crate::stream::partial_json::deserialize_raw;
+use crate::stream::partial_json::{
+    lock, seed_fn, Framing, Offloaded, PartialJson, RoundtripCheck, SeedFn, SkipHandler,
+    SpillHandler,
+};
+use crate::stream::path::JsonPath;
+use crate::stream::pause::PauseSignal;
+use crate::stream::pool::{BufferPool, SpareBuffer};
+use crate::stream::projection::Projection;
+use crate::stream::raw::RawJsonStream;
+use crate::stream::stats::StreamStats;
+use crate::stream::target::StreamTarget;
+#[cfg(feature = "test-util")]
+use crate::stream::throttle::ThrottledJsonStream;
+#[cfg(test)]
+use crate::stream::watchdog::Probe;
+use hyper::body::{Body, Bytes, Frame, Incoming};
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::{Client, ResponseFuture};
 use std::cmp;
 use std::io::ErrorKind;
-use std::{fmt, io, mem, ptr};
+use std::{fmt, io};
 
-use crate::util::{get_content_length, JsonStreamError};
+#[cfg(feature = "sink")]
+use crate::util::PipeError;
+use crate::util::{get_content_length, get_retry_after, JsonStreamError};
 
 use super::encoding::ContentEncoding;
 
@@ -27,57 +75,1296 @@ use super::encoding::ContentEncoding;
 #[must_use = "streams do nothing unless you poll them"]
 pub struct JsonStream<T> {
     state: State<T>,
+    config: Config,
+    hooks: Hooks<T>,
+    info: ResponseInfo,
+    stats: StreamStats,
+    /// When the stream was first polled.
+    started: Option<Instant>,
+    /// The error to return on the next poll, set by `abort`.
+    aborted: Option<JsonStreamError>,
+    /// The number of elements yielded since the stream last returned `Pending`.
+    ready_in_row: u32,
+    /// The number of polls in a row that returned `Pending` without any progress.
+    #[cfg(all(debug_assertions, feature = "tracing"))]
+    idle_polls: u32,
+    #[cfg(feature = "timeout")]
+    timeout: Timeout,
+    /// Calls the `on_idle` hook when the stream makes no progress for a while.
+    #[cfg(feature = "timeout")]
+    idle: Timeout,
+}
+/// What we know about the response, once connected.
+#[derive(Debug, Default)]
+struct ResponseInfo {
+    content_length: Option<usize>,
+    encoding: ContentEncoding,
+    content_range: Option<ContentRange>,
+    /// The head of the response, once handled.
+    parts: Option<Parts>,
+    /// The stream was made `from_body`, the parts are made up.
+    made_up_parts: bool,
+    not_modified: bool,
+    /// The headers of an error response, once its body is collected.
+    error_headers: Option<HeaderMap>,
+    /// The body of an error response, when it is streamed instead of collected.
+    error_body: Option<ResponseBody>,
+    /// The meta fields of `JsonStream::at_path_with_meta`, once the array is reached.
+    meta: Option<HashMap<String, serde_json::Value>>,
+    /// The footer of `JsonStream::with_footer` as an object, once captured.
+    footer: Option<Vec<u8>>,
+    /// The trailers ending the body, once received.
+    trailers: Option<HeaderMap>,
+    /// The `Content-Length` the body is checked against, with `verify_content_length`.
+    expected_length: Option<u64>,
+    /// Whether the decoder of the body decompressed it, as of its last poll.
+    decompressing: bool,
+}
+/// The largest initial allocation made by `auto_capacity`, whatever the announced
+/// `Content-Length`.
+const MAX_AUTO_CAPACITY: usize = 4 * 1024 * 1024;
+/// The expected size of a decompressed body relative to its compressed size.
+const EXPANSION_FACTOR: usize = 4;
+/// The expected minimal size of an element, to estimate the number of elements of a body.
+const MIN_ELEMENT_SIZE: usize = 16;
+/// The number of steps of the state machine in a single poll, like reading a frame of the
+/// body, before yielding to the executor. A body of tiny frames buffered faster than they
+/// complete an element would otherwise hold the task for as long as it keeps coming.
+const POLL_BUDGET: usize = 128;
+/// The number of polls in a row returning `Pending` without progress after which debug
+/// builds warn about a possibly missing waker, with the `tracing` feature.
+#[cfg(all(debug_assertions, feature = "tracing"))]
+const IDLE_POLLS_WARNING: u32 = 1000;
+/// The settings of a `JsonStream`.
+#[derive(Debug, Clone)]
+struct Config {
     capacity: usize,
     level: u32,
+    framing: Framing,
+    auto_capacity: bool,
+    lenient: bool,
+    unbounded_depth: bool,
+    #[cfg(feature = "json5")]
+    json5: bool,
+    scan_only: bool,
+    reject_duplicate_keys: bool,
+    verify_content_length: bool,
+    multi_array: bool,
+    max_garbage: usize,
+    max_total_bytes: Option<u64>,
+    /// Return `Pending` once after that many elements yielded in a row.
+    max_elements_per_poll: Option<u32>,
+    gzip_content_type: bool,
+    /// Pick the framing from the `Content-Type` of the response.
+    auto_framing: bool,
+    window_bits: Option<c_int>,
+    decode_fallback: bool,
+    /// Stream the json part of a `multipart/*` body.
+    multipart: bool,
+    /// The path of the string holding the body as base64, for `JsonStream::base64_field`.
+    base64_field: Option<String>,
+    allow_empty_body: bool,
+    stream_error_body: bool,
+    /// Keep the raw json of the elements, for `JsonStream::raw`.
+    keep_raw: bool,
+    /// The array to stream, instead of the first one found after `level` opening braces.
+    path: Option<JsonPath>,
+    /// Capture the members following the array, for `JsonStream::with_footer`.
+    footer: bool,
+    budget: Option<DecompressionBudget>,
+    /// Holds back the body while paused, see `JsonStream::with_pause_signal`.
+    pause: Option<PauseSignal>,
+    pool: Option<Arc<dyn BufferPool>>,
+    /// The buffer of the last body, used instead of allocating one without a `pool`.
+    spare: Arc<SpareBuffer>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            capacity: 0,
+            level: 1,
+            framing: Framing::Array,
+            auto_capacity: false,
+            lenient: false,
+            unbounded_depth: false,
+            #[cfg(feature = "json5")]
+            json5: false,
+            scan_only: false,
+            reject_duplicate_keys: false,
+            verify_content_length: false,
+            multi_array: true,
+            max_garbage: 0,
+            max_total_bytes: None,
+            max_elements_per_poll: None,
+            gzip_content_type: false,
+            auto_framing: false,
+            window_bits: None,
+            decode_fallback: false,
+            multipart: false,
+            base64_field: None,
+            allow_empty_body: false,
+            stream_error_body: false,
+            keep_raw: false,
+            path: None,
+            footer: false,
+            budget: None,
+            pause: None,
+            pool: None,
+            spare: Arc::default(),
+        }
+    }
+}
+impl Config {
+    /// The framing of a body sent with `headers`.
+    fn framing(&self, headers: &HeaderMap) -> Framing {
+        match self.framing {
+            Framing::Array | Framing::Lines | Framing::JsonSeq if self.auto_framing => {
+                Framing::from_content_type(headers).unwrap_or(self.framing)
+            }
+            framing => framing,
+        }
+    }
+    /// The initial capacity of the buffer for a body of `content_length` bytes.
+    fn initial_capacity(&self, content_length: usize, encoding: &ContentEncoding) -> usize {
+        if !self.auto_capacity {
+            return self.capacity;
+        }
+        let expected = match encoding {
+            ContentEncoding::None | ContentEncoding::Identity => content_length,
+            _ => content_length.saturating_mul(EXPANSION_FACTOR),
+        };
+        cmp::max(self.capacity, cmp::min(expected, MAX_AUTO_CAPACITY))
+    }
+}
+/// What `JsonStream::shutdown` drained of the body.
+#[cfg(feature = "timeout")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Drained {
+    /// The number of bytes of the body drained, as received.
+    pub bytes: u64,
+    /// The whole body was drained, or the stream was already done.
+    pub complete: bool,
+}
+/// User provided callbacks, handed over to the parser once the response arrives.
+struct Hooks<T> {
+    // Shared with the parser of every body, so that they outlive a `reset`.
+    on_skip: Option<Shared<SkipHandler>>,
+    on_spill: Option<(usize, Shared<SpillHandler>)>,
+    on_frame: Option<FrameHandler>,
+    on_state_change: Option<StateChangeHandler>,
+    #[cfg(feature = "timeout")]
+    on_idle: Option<Box<dyn FnMut() + Send>>,
+    seed: Option<SeedFn<T>>,
+    roundtrip: Option<RoundtripCheck<T>>,
+    /// The size above which the elements are deserialized by the function, off the task.
+    offload: Option<(usize, OffloadFn<T>)>,
+    retry: Option<Retry>,
+    /// Checks the order of the elements, see `JsonStream::assert_sorted_by`.
+    sort_check: Option<SortCheck<T>>,
+}
+/// Sends the request again when the connection fails, see `JsonStream::with_retry`.
+struct Retry {
+    attempts_left: u32,
+    send: Box<dyn FnMut() -> Result<ResponseFuture, http::Error> + Send>,
+}
+// Derived, it would require T: Default.
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Hooks {
+            on_skip: None,
+            on_spill: None,
+            on_frame: None,
+            on_state_change: None,
+            #[cfg(feature = "timeout")]
+            on_idle: None,
+            seed: None,
+            roundtrip: None,
+            offload: None,
+            retry: None,
+            sort_check: None,
+        }
+    }
 }
+type FrameHandler = Box<dyn FnMut(&Frame<Bytes>) + Send>;
+type Shared<F> = Arc<Mutex<F>>;
+type OffloadFn<T> = fn(Offloaded) -> ElementFuture<T>;
+type ElementFuture<T> = Pin<Box<dyn Future<Output = Result<T, JsonStreamError>> + Send>>;
+/// Returns the index of the element when it is out of order.
+type SortCheck<T> = Box<dyn FnMut(&T) -> Option<u64> + Send>;
+type StateChangeHandler = Box<dyn FnMut(&'static str, &'static str) + Send>;
+// The callbacks are not required to be Sync. No shared reference to the hooks is ever
+// handed out: `State::poll` and the builders only take them as `&mut Hooks`, so none of the
+// callbacks can be called from two threads at once.
+unsafe impl<T> Sync for Hooks<T> {}
 enum State<T> {
     Connecting(ResponseFuture),
+    Received(Parts, ResponseBody),
     Collecting {
-        body: Incoming,
-        json: PartialJson<T>,
-        encoding: ContentEncoding,
-        stream: *mut zlib::z_stream,
-        total_in: u64,
+        body: ResponseBody,
+        json: Box<PartialJson<T>>,
+        decoder: Decoder,
+        /// The element being deserialized off the task, yielded before any other.
+        offloaded: Option<ElementFuture<T>>,
     },
-    CollectingError(Parts, Incoming, Vec<u8>),
-    EncodingError(),
+    CollectingError(Parts, ResponseBody, Vec<u8>),
     Done(),
 }
-// The ResponseFuture does not implement Sync, but since it can only be accessed through
-// &mut methods, it is not possible to synchronously access it.
+// The ResponseFuture and the future of the offloaded element are not Sync, but they are only
+// polled through `&mut State`: the methods taking `&State`, like `name`, never touch them.
 unsafe impl<T> Sync for State<T> {}
-// The compiler adds a T: Send bound, but it is not needed as we don't store any Ts.
+// The compiler adds a T: Send bound, from the `PhantomData<T>` of `PartialJson`. No T is
+// stored by value: the offloaded element is a boxed `Send` future whatever the T, and an
+// element is yielded as soon as it is deserialized.
 unsafe impl<T> Send for State<T> {}
-// The compiler adds a T: Unpin bound, but it is not needed as we don't store any Ts.
+// The compiler adds a T: Unpin bound, but it is not needed as the future of the offloaded
+// element, the only one that may hold a T, is boxed.
 impl<T> Unpin for State<T> {}
 
+impl<T> State<T> {
+    /// The name of the state, without any of its data.
+    fn name(&self) -> &'static str {
+        match self {
+            State::Connecting(_) => "Connecting",
+            State::Received(_, _) => "Received",
+            State::Collecting { .. } => "Collecting",
+            State::CollectingError(_, _, _) => "CollectingError",
+            State::Done() => "Done",
+        }
+    }
+}
+
 impl<T> fmt::Debug for JsonStream<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.state {
-            State::Connecting(_) => f.pad("JsonStream(connecting)"),
-            State::Collecting { .. } => f.pad("JsonStream(receiving)"),
-            State::CollectingError(_, _, _) => f.pad("JsonStream(api error)"),
-            State::EncodingError() => f.pad("JsonStream(encoding error)"),
-            State::Done() => f.pad("JsonStream(done)"),
+        let state = match self.state {
+            State::Connecting(_) => "connecting",
+            State::Received(_, _) => "received",
+            State::Collecting { .. } => "receiving",
+            State::CollectingError(_, _, _) => "api error",
+            State::Done() => "done",
+        };
+        let mut debug = format!(
+            "JsonStream({}, elements: {}, bytes: {}",
+            state, self.stats.elements, self.stats.wire_bytes
+        );
+        // The encoding is known once the head of the response is received.
+        if self.info.content_length.is_some() {
+            debug.push_str(&format!(", encoding: {:?}", self.info.encoding));
+        }
+        if matches!(self.config.framing, Framing::Array | Framing::ObjectValues) {
+            debug.push_str(&format!(", level: {}", self.config.level));
         }
+        debug.push_str(&format!(", capacity: {})", self.config.capacity));
+        f.pad(&debug)
     }
 }
 
 impl<T: DeserializeOwned> JsonStream<T> {
     /// Create a new `JsonStream`. The `capacity` is the initial size of the allocation
     /// meant to hold the body of the response.
+    ///
+    /// The `level` is the number of opening braces to skip before reaching the elements,
+    /// see `StreamTarget` for the equivalent targets.
     pub fn new(resp: ResponseFuture, level: u32, capacity: usize) -> Self {
+        JsonStream::with_target(resp, StreamTarget::from_level(level), capacity)
+    }
+    /// Create a new `JsonStream` yielding the given `target` of the json document. The
+    /// `capacity` is the initial size of the allocation meant to hold the body of the
+    /// response.
+    pub fn with_target(resp: ResponseFuture, target: StreamTarget, capacity: usize) -> Self {
+        let (framing, level) = target.framing();
+        let mut stream = JsonStream::with_state(State::Connecting(resp), level, capacity);
+        stream.config.framing = framing;
+        stream
+    }
+    /// Create a new `JsonStream` reading a json list from a body, such as the body of a
+    /// request received by a hyper server. There is no response to check, so the only
+    /// errors are those of the body and of the parsing. The `capacity` is the initial
+    /// size of the allocation meant to hold the body.
+    pub fn from_body(body: Incoming, level: u32, capacity: usize) -> Self {
+        let (parts, ()) = Response::new(()).into_parts();
+        let mut stream =
+            JsonStream::with_state(State::Received(parts, body.into()), level, capacity);
+        stream.info.made_up_parts = true;
+        stream
+    }
+    /// Create a new `JsonStream` reading a json list from a body of any origin, like a
+    /// mocked one, as `from_body` does. The streams of hyper bodies and of boxed ones
+    /// have the same type, so they can be held together, in a `Vec` for instance.
+    ///
+    /// The boxed body costs an allocation, and a dynamic call for each of its frames,
+    /// which is small next to the parsing of the frame. The bodies of hyper are not boxed,
+    /// their frames are polled without any dynamic call.
+    pub fn from_boxed_body(body: BoxedBody, level: u32, capacity: usize) -> Self {
+        let (parts, ()) = Response::new(()).into_parts();
+        let body = ResponseBody::Boxed(Box::into_pin(body));
+        let mut stream = JsonStream::with_state(State::Received(parts, body), level, capacity);
+        stream.info.made_up_parts = true;
+        stream
+    }
+    /// Create a new `JsonStream` from a response already split in its head and its body,
+    /// for instance by a middleware. The response is handled like the one of a
+    /// `ResponseFuture`: an error status fails the stream with its body, and the body is
+    /// decompressed according to its `Content-Encoding`.
+    pub fn from_parts_and_body(parts: Parts, body: Incoming, level: u32, capacity: usize) -> Self {
+        JsonStream::with_state(State::Received(parts, body.into()), level, capacity)
+    }
+    /// Create a new `JsonStream` sending a GET request for `uri` with `client`. The request
+    /// accepts `application/json`, and the encodings decoded with the features enabled,
+    /// like gzip. An invalid `uri` is an error right away, instead of an item of the
+    /// stream.
+    pub fn get<C>(
+        client: &Client<C, Empty<Bytes>>,
+        uri: &str,
+        level: u32,
+        capacity: usize,
+    ) -> Result<Self, JsonStreamError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(uri)
+            .header(header::ACCEPT, "application/json")
+            .header(header::ACCEPT_ENCODING, ContentEncoding::accept_encoding())
+            .body(Empty::new())?;
+        Ok(JsonStream::new(client.request(req), level, capacity))
+    }
+    /// Create a new `JsonStream` sending its request with `client`, and sending it again,
+    /// up to `attempts` times in total, when it fails before any response is received,
+    /// like when the connection is refused or reset.
+    ///
+    /// The request of every attempt, the first one included, is built by `prepare` from a
+    /// new `Request::builder()`, right before being sent. This allows each attempt to
+    /// carry a fresh `Authorization` header instead of replaying an expired token. An
+    /// invalid request is the first item of the stream.
+    ///
+    /// The attempts follow each other without any delay. A response with an error status
+    /// is not retried, see `JsonStreamError::RateLimited`.
+    pub fn with_retry<C>(
+        client: Client<C, Empty<Bytes>>,
+        attempts: u32,
+        mut prepare: impl FnMut(request::Builder) -> request::Builder + Send + 'static,
+        level: u32,
+        capacity: usize,
+    ) -> Self
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let mut send = move || {
+            let req = prepare(Request::builder()).body(Empty::new())?;
+            Ok(client.request(req))
+        };
+        let mut stream = match send() {
+            Ok(resp) => JsonStream::new(resp, level, capacity),
+            Err(err) => {
+                let mut stream = JsonStream::with_state(State::Done(), level, capacity);
+                stream.end(JsonStreamError::HttpError(err));
+                return stream;
+            }
+        };
+        stream.hooks.retry = Some(Retry {
+            attempts_left: attempts.saturating_sub(1),
+            send: Box::new(send),
+        });
+        stream
+    }
+    /// Create a new `JsonStream` yielding the elements of the array at `items_path`, made
+    /// of the dot separated keys of the nested objects leading to it like `data.items`.
+    /// The fields at `meta_paths` found before the array, like the `total` of
+    /// `{"total": 10000, "items": [...]}`, are captured and available from `meta` once
+    /// the array is reached, for instance to size a progress bar.
+    ///
+    /// Only the objects on the way to the array are searched: the arrays before it and
+    /// the fields after it are skipped. A document without an array at `items_path` fails
+    /// with `JsonStreamError::MalformedJson`.
+    pub fn at_path_with_meta(resp: ResponseFuture, items_path: &str, meta_paths: &[&str]) -> Self {
+        let path = JsonPath::new(items_path, meta_paths);
+        let mut stream = JsonStream::with_state(State::Connecting(resp), path.level(), 0);
+        stream.config.path = Some(path);
+        stream
+    }
+    fn with_state(state: State<T>, level: u32, capacity: usize) -> Self {
         JsonStream {
-            state: State::Connecting(resp),
-            capacity,
-            level,
+            state,
+            config: Config {
+                capacity,
+                level,
+                ..Config::default()
+            },
+            hooks: Hooks::default(),
+            info: ResponseInfo::default(),
+            stats: StreamStats::default(),
+            started: None,
+            aborted: None,
+            ready_in_row: 0,
+            #[cfg(all(debug_assertions, feature = "tracing"))]
+            idle_polls: 0,
+            #[cfg(feature = "timeout")]
+            timeout: Timeout::default(),
+            #[cfg(feature = "timeout")]
+            idle: Timeout::default(),
+        }
+    }
+    /// Create a new `JsonStream` over newline delimited json (NDJSON / JSON Lines), where
+    /// every non blank line holds a json value. The `capacity` is the initial size of the
+    /// allocation meant to hold the body of the response.
+    pub fn ndjson(resp: ResponseFuture, capacity: usize) -> Self {
+        let mut stream = JsonStream::new(resp, 0, capacity);
+        stream.config.framing = Framing::Lines;
+        stream
+    }
+    /// Create a new `JsonStream` over a json text sequence (`application/json-seq`, RFC
+    /// 7464), where every value is preceded by an ASCII record separator and followed by a
+    /// newline. The `capacity` is the initial size of the allocation meant to hold the
+    /// body of the response.
+    pub fn json_seq(resp: ResponseFuture, capacity: usize) -> Self {
+        let mut stream = JsonStream::new(resp, 0, capacity);
+        stream.config.framing = Framing::JsonSeq;
+        stream
+    }
+    /// Create a new `JsonStream` over newline delimited json arrays, streaming the elements
+    /// of every line one after the other. A line that does not hold an array is an error,
+    /// which the stream continues after in lenient mode. The `capacity` is the initial size
+    /// of the allocation meant to hold the body of the response.
+    pub fn ndjson_flatten(resp: ResponseFuture, capacity: usize) -> Self {
+        let mut stream = JsonStream::new(resp, 1, capacity);
+        stream.config.framing = Framing::FlattenedLines;
+        stream
+    }
+    /// Grow the initial allocation from the `Content-Length` of the response, to avoid
+    /// reallocations for bodies known to be large. The `capacity` given at construction is
+    /// used as a minimum. For compressed bodies the `Content-Length` is multiplied by an
+    /// expansion factor of 4.
+    ///
+    /// The allocation is clamped to 4 MiB, so a huge `Content-Length` cannot cause a giant
+    /// allocation.
+    pub fn auto_capacity(mut self, auto_capacity: bool) -> Self {
+        self.config.auto_capacity = auto_capacity;
+        self
+    }
+    /// Enable the robust scanning mode, meant to recover partial data from corrupted feeds.
+    ///
+    /// Elements that cannot be deserialized and structurally invalid bytes are skipped
+    /// instead of ending the stream with an error. Every skipped range of bytes, relative
+    /// to the start of the decoded body, is reported to `on_skip`. Resynchronizing is a
+    /// best effort: the stream may skip valid elements surrounding the corrupted bytes.
+    ///
+    /// This silently drops data, so only use it when partial results are acceptable.
+    pub fn robust(mut self, on_skip: impl FnMut(Range<u64>) + Send + 'static) -> Self {
+        self.hooks.on_skip = Some(Arc::new(Mutex::new(Box::new(on_skip))));
+        self
+    }
+    /// Hand the raw json of the elements larger than `threshold` bytes to `on_chunk`,
+    /// instead of yielding them. The bytes of such an element are handed out as they
+    /// arrive, the flag being set on its last chunk, so that a huge element like an
+    /// embedded blob never needs a contiguous buffer.
+    ///
+    /// The oversized elements are not deserialized: doing so would require the whole
+    /// element in memory, which is what this avoids. For `StreamTarget::ObjectValues`, the
+    /// bytes include the key of the member.
+    pub fn spill_oversized(
+        mut self,
+        threshold: usize,
+        on_chunk: impl FnMut(&[u8], bool) + Send + 'static,
+    ) -> Self {
+        self.hooks.on_spill = Some((threshold, Arc::new(Mutex::new(Box::new(on_chunk)))));
+        self
+    }
+    /// Check that the elements are sorted by `key_fn` as they are yielded, for consumers
+    /// relying on the order of the feed like a merge join. `ordering` is the order of the
+    /// key of every element to the key of the next one: `Ordering::Less` for ascending
+    /// keys, `Ordering::Greater` for descending ones. Equal keys are always accepted.
+    ///
+    /// An element out of order is replaced by `JsonStreamError::MalformedJson("sort order
+    /// violated at index N")`, N counting from 0, and the stream then ends. Only the key of
+    /// the previous element is kept.
+    pub fn assert_sorted_by<K, F>(mut self, mut key_fn: F, ordering: cmp::Ordering) -> Self
+    where
+        K: Ord + Send + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
+        let mut previous: Option<K> = None;
+        let mut index = 0;
+        self.hooks.sort_check = Some(Box::new(move |item| {
+            let key = key_fn(item);
+            let sorted = !previous
+                .as_ref()
+                .is_some_and(|previous| match previous.cmp(&key) {
+                    cmp::Ordering::Equal => false,
+                    order => order != ordering,
+                });
+            index += 1;
+            previous = Some(key);
+            (!sorted).then_some(index - 1)
+        }));
+        self
+    }
+    /// Drive the stream to completion, collecting the elements in a map keyed by
+    /// `key_fn`. When several elements have the same key, the last one is kept. The map is
+    /// pre-sized from the `Content-Length` of the response.
+    ///
+    /// Returns the first error of the stream.
+    pub async fn collect_map<K: Eq + Hash>(
+        mut self,
+        key_fn: impl Fn(&T) -> K,
+    ) -> Result<HashMap<K, T>, JsonStreamError> {
+        let mut map = HashMap::new();
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut self).poll_next(cx)).await {
+            let item = item?;
+            if map.is_empty() {
+                map.reserve(self.estimated_len());
+            }
+            map.insert(key_fn(&item), item);
+        }
+        Ok(map)
+    }
+    /// Drive the stream to completion, folding the elements into an accumulator with `f`,
+    /// starting from `init`.
+    ///
+    /// Returns the first error of the stream or of `f`.
+    pub async fn try_fold_elements<B>(
+        mut self,
+        init: B,
+        mut f: impl FnMut(B, T) -> Result<B, JsonStreamError>,
+    ) -> Result<B, JsonStreamError> {
+        let mut acc = init;
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut self).poll_next(cx)).await {
+            acc = f(acc, item?)?;
+        }
+        Ok(acc)
+    }
+    /// Drive the stream to completion, pushing every element into `sink`, for pipelines
+    /// consuming the elements as they come.
+    ///
+    /// Returns the first error of the stream or of `sink`.
+    pub async fn drive_into(
+        self,
+        mut sink: impl FnMut(T) -> Result<(), JsonStreamError>,
+    ) -> Result<(), JsonStreamError> {
+        self.try_fold_elements((), |(), item| sink(item)).await
+    }
+    /// Drive the stream to completion, sending every element into `sink`, like the sender
+    /// of a channel feeding an actor. The next element is only read once the sink is
+    /// ready to take it, so a full sink holds back the body. The sink is flushed whenever
+    /// the stream waits for the body, and closed at the end of the stream.
+    ///
+    /// Returns the first error of the stream or of `sink`. On an error of the stream, the
+    /// sink is flushed first, so that the elements sent before it are not lost in its
+    /// buffer, but it is not closed.
+    #[cfg(feature = "sink")]
+    pub async fn pipe_to<S: Sink<T> + Unpin>(
+        mut self,
+        mut sink: S,
+    ) -> Result<(), PipeError<S::Error>> {
+        let mut done = false;
+        let mut failed = None;
+        poll_fn(|cx| loop {
+            if let Some(err) = failed.take() {
+                // The error of the stream is the one returned, even if the flush fails.
+                if Pin::new(&mut sink).poll_flush(cx).is_pending() {
+                    failed = Some(err);
+                    return Poll::Pending;
+                }
+                return Poll::Ready(Err(PipeError::Stream(err)));
+            }
+            if done {
+                return Pin::new(&mut sink).poll_close(cx).map_err(PipeError::Sink);
+            }
+            ready!(Pin::new(&mut sink).poll_ready(cx)).map_err(PipeError::Sink)?;
+            match Pin::new(&mut self).poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => {
+                    Pin::new(&mut sink)
+                        .start_send(item)
+                        .map_err(PipeError::Sink)?;
+                }
+                Poll::Ready(Some(Err(err))) => failed = Some(err),
+                Poll::Ready(None) => done = true,
+                Poll::Pending => {
+                    // Hand over the elements buffered by the sink while waiting.
+                    ready!(Pin::new(&mut sink).poll_flush(cx)).map_err(PipeError::Sink)?;
+                    return Poll::Pending;
+                }
+            }
+        })
+        .await
+    }
+    /// Wait for `delay` after each element before polling the next one, to test how
+    /// consumers handle slow feeds. The delay is measured with the timer of tokio, see
+    /// `ThrottledJsonStream::with_clock` for another `Clock`.
+    #[cfg(feature = "test-util")]
+    pub fn throttle(self, delay: Duration) -> ThrottledJsonStream<T> {
+        ThrottledJsonStream::new(self, delay)
+    }
+    /// Group the elements in batches of `size` elements, for instance to insert them in a
+    /// database in bulk. The last batch holds the remaining elements and may be smaller.
+    ///
+    /// An error drops the elements of the current batch and is yielded in its place.
+    ///
+    /// Panics if `size` is 0.
+    pub fn batched(self, size: usize) -> Batched<T> {
+        Batched::new(self, size)
+    }
+    /// Skip the elements whose key, given by `key_fn`, was seen recently, for feeds that
+    /// repeat some records. Errors are yielded as they come.
+    ///
+    /// To keep the memory bounded over huge streams, only the `capacity` most recently seen
+    /// keys are remembered, a key seen again becoming the most recent: the set holds
+    /// `capacity` keys at most, and a duplicate arriving after `capacity` other distinct
+    /// keys is yielded again. No element is ever skipped wrongly, so pick a `capacity`
+    /// covering the distance at which the feed repeats its records.
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn dedup_by_key<K, F>(self, key_fn: F, capacity: usize) -> DedupJsonStream<T, K, F>
+    where
+        K: Hash + Eq,
+        F: FnMut(&T) -> K,
+    {
+        DedupJsonStream::new(self, key_fn, capacity)
+    }
+    /// Yield the raw json of every element along with the element, for instance to store
+    /// the raw form while acting on the typed one. The bytes are kept while scanning the
+    /// element, so this avoids serializing it again.
+    pub fn raw(mut self) -> RawJsonStream<T> {
+        self.config.keep_raw = true;
+        RawJsonStream::new(self)
+    }
+    /// Create a `JsonStream` yielding the raw json of every element along with the
+    /// element, see `JsonStream::raw`.
+    pub fn with_raw(resp: ResponseFuture, level: u32, capacity: usize) -> RawJsonStream<T> {
+        JsonStream::new(resp, level, capacity).raw()
+    }
+    /// Also parse the members of the object enclosing the array that follow it as a
+    /// footer of type `F`, like the `{"checksum": "...", "count": 2}` of
+    /// `{"items": [...], "checksum": "...", "count": 2}`, for instance to check the
+    /// elements against it. The footer is available from `FooterJsonStream::footer` once
+    /// the stream ends.
+    ///
+    /// The array must be nested in an object, with a `level` of 2 or more or with
+    /// `at_path_with_meta`. The members before the array are not part of the footer, see
+    /// `at_path_with_meta` to capture them.
+    pub fn with_footer<F: DeserializeOwned>(mut self) -> FooterJsonStream<T, F> {
+        self.config.footer = true;
+        FooterJsonStream::new(self)
+    }
+    /// The footer captured for `FooterJsonStream`, as an object.
+    pub(crate) fn footer_bytes(&self) -> Option<&[u8]> {
+        self.info.footer.as_deref()
+    }
+    /// Take the raw json of the element just yielded, when it is kept.
+    pub(crate) fn take_raw(&mut self) -> Option<Bytes> {
+        match self.state {
+            State::Collecting { ref mut json, .. } => json.take_raw().map(Bytes::from),
+            _ => None,
         }
     }
+    /// A rough upper bound of the number of elements, from the `Content-Length`.
+    fn estimated_len(&self) -> usize {
+        let size = self.info.content_length.unwrap_or(0);
+        let size = match self.info.encoding {
+            ContentEncoding::None | ContentEncoding::Identity => size,
+            _ => size.saturating_mul(EXPANSION_FACTOR),
+        };
+        cmp::min(size, MAX_AUTO_CAPACITY) / MIN_ELEMENT_SIZE
+    }
+    /// Decompress the body of a response without `Content-Encoding` whose `Content-Type` is
+    /// `application/gzip` or `application/x-gzip`, as sent by the APIs serving a gzip file
+    /// as is. Off by default, such a body is then parsed as sent.
+    pub fn gzip_content_type(mut self, enabled: bool) -> Self {
+        self.config.gzip_content_type = enabled;
+        self
+    }
+    /// Pick the framing of the body from its `Content-Type`, for endpoints switching
+    /// formats: newline delimited json for `application/x-ndjson` and `application/jsonl`,
+    /// a json text sequence for `application/json-seq`, and an array for `application/json`.
+    /// The framing the stream was created with is kept for the other types. Only the
+    /// streams over an array, lines or a json text sequence switch. Off by default.
+    pub fn auto_framing(mut self, enabled: bool) -> Self {
+        self.config.auto_framing = enabled;
+        self
+    }
+    /// End the stream with `JsonStreamError::MalformedJson` once more than `max` bytes of
+    /// the body were received, as sent on the wire, to stop runaway feeds. The body is not
+    /// polled anymore. The default is no limit.
+    pub fn max_total_bytes(mut self, max: u64) -> Self {
+        self.config.max_total_bytes = Some(max);
+        self
+    }
+    /// Yield control back to the executor after `max` elements yielded in a row, for the
+    /// other tasks to run. A frame can hold thousands of small elements, which are then
+    /// all ready and yielded one after the other without ever returning `Pending`, so a
+    /// task consuming them in a loop would otherwise hold its thread until they are done.
+    ///
+    /// The stream returns `Pending` once instead of the next element, after waking its
+    /// task right away, and goes on from there on the next poll. The default is no limit,
+    /// for the best throughput.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    pub fn max_elements_per_poll(mut self, max: u32) -> Self {
+        assert!(max > 0, "the number of elements per poll must be positive");
+        self.config.max_elements_per_poll = Some(max);
+        self
+    }
+    /// Reserve the memory of the decompressor from `budget`, which caps the total
+    /// decompression memory of all the streams sharing it. If the budget is exhausted the
+    /// stream fails with `JsonStreamError::EncodingError`.
+    pub fn with_budget(mut self, budget: DecompressionBudget) -> Self {
+        self.config.budget = Some(budget);
+        self
+    }
+    /// Stop reading the body while `signal` is paused, to throttle many streams together
+    /// from outside, like during a backpressure event. The elements already received are
+    /// still yielded, after which the stream returns `Pending`.
+    ///
+    /// The waker of the task polling the stream is then registered with the signal, and
+    /// woken by `PauseSignal::resume`, so the stream goes on as soon as it is resumed
+    /// without being polled in the meantime. The `timeout` still runs while paused.
+    pub fn with_pause_signal(mut self, signal: PauseSignal) -> Self {
+        self.config.pause = Some(signal);
+        self
+    }
+    /// In lenient mode, an element that cannot be deserialized is reported as a
+    /// `JsonStreamError::InvalidElement` holding the raw bytes of the element, also given
+    /// by `JsonStreamError::raw_element`, and the stream continues with the next element
+    /// instead of ending. This allows quarantining bad records while processing the rest
+    /// of the feed. Without it, such an element ends the stream with a
+    /// `JsonStreamError::MalformedElement`, which holds the raw bytes too.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.config.lenient = lenient;
+        self
+    }
+    /// Deserialize the elements without the recursion limit of `serde_json`, which
+    /// otherwise fails on elements nested more than 128 levels deep.
+    ///
+    /// Only disable it for trusted sources: a deeply nested element can then overflow the
+    /// stack and abort the process.
+    pub fn disable_recursion_limit(mut self, disable: bool) -> Self {
+        self.config.unbounded_depth = disable;
+        self
+    }
+    /// Stream a json5 document, like a configuration file or a feed written by hand: the
+    /// comments are skipped, the strings may be single quoted, the last element of the
+    /// array may be followed by a comma, and the elements are deserialized with the
+    /// `json5` crate, allowing unquoted keys, hexadecimal numbers and the like. The seeds
+    /// of `with_seed` and `project`, `reject_duplicate_keys` and `strict_roundtrip` read
+    /// the elements as json5 too.
+    ///
+    /// The path to the array, as given to `at_path`, must still be plain json, and so must
+    /// the keys of the members streamed with `StreamTarget::ObjectValues`, which are
+    /// double quoted strings without a comment before them.
+    #[cfg(feature = "json5")]
+    pub fn json5(mut self, json5: bool) -> Self {
+        self.config.json5 = json5;
+        self
+    }
+    /// A summary of the elements and bytes received so far. Once the stream is done, the
+    /// elapsed time is the time it took from the first poll to its completion.
+    pub fn stats(&self) -> StreamStats {
+        let mut stats = self.stats;
+        match (self.started, &self.state) {
+            (_, State::Done()) | (None, _) => {}
+            (Some(started), _) => stats.elapsed = started.elapsed(),
+        }
+        stats
+    }
+    /// The number of opening braces skipped before reaching the elements, as given to
+    /// `new`. It is 1 for `StreamTarget::ObjectValues`, and 0 for the targets that are not
+    /// inside braces, like `StreamTarget::SingleValue` or newline delimited json.
+    pub fn level(&self) -> u32 {
+        self.config.level
+    }
+    /// The initial size of the buffer holding the body, as given to `new`. With
+    /// `auto_capacity`, the buffer may start larger.
+    pub fn capacity(&self) -> usize {
+        self.config.capacity
+    }
+    /// The number of bytes of the body received so far, as sent on the wire.
+    pub fn bytes_received(&self) -> u64 {
+        self.stats.wire_bytes
+    }
+    /// The number of bytes of the body once decompressed, so far. It equals
+    /// `bytes_received` for an uncompressed body, and their ratio is the compression ratio
+    /// otherwise.
+    pub fn decompressed_bytes(&self) -> u64 {
+        self.stats.decoded_bytes
+    }
+    /// Take the buffer holding the body from `pool` instead of allocating it, and give it
+    /// back once the stream is done with it. The decompressor allocates separately, see
+    /// `with_budget`.
+    pub fn with_buffer_pool(mut self, pool: Arc<dyn BufferPool>) -> Self {
+        self.config.pool = Some(pool);
+        self
+    }
+    /// End the stream, for instance when a watchdog finds it stalled. The next poll
+    /// returns `JsonStreamError::MalformedJson(reason)`, and the stream then ends.
+    ///
+    /// This is a cooperative abort: the response and its body are dropped right away, but
+    /// it cannot interrupt the stream while it is being polled, nor a read of the
+    /// connection that is already in progress.
+    pub fn abort(&mut self, reason: String) {
+        self.end(JsonStreamError::MalformedJson(reason));
+    }
+    /// Reuse the stream for the response of another request, like a similar request on a
+    /// pooled connection. The stream starts over with the same settings and hooks, but
+    /// takes the buffer of the previous body back instead of allocating a new one.
+    ///
+    /// The stream must be done, it is a bug to reset it while it is still reading a body.
+    pub fn reset(&mut self, resp: ResponseFuture) {
+        debug_assert!(
+            matches!(self.state, State::Done()),
+            "reset a JsonStream that is not done"
+        );
+        let old = self.state.name();
+        self.state = State::Connecting(resp);
+        if let Some(ref mut on_state_change) = self.hooks.on_state_change {
+            on_state_change(old, self.state.name());
+        }
+        self.info = ResponseInfo::default();
+        self.stats = StreamStats::default();
+        self.started = None;
+        self.aborted = None;
+        self.ready_in_row = 0;
+        #[cfg(all(debug_assertions, feature = "tracing"))]
+        {
+            self.idle_polls = 0;
+        }
+        #[cfg(feature = "timeout")]
+        {
+            self.timeout.reset();
+            self.idle.reset();
+        }
+    }
+    /// Move to `Done`, with `err` as the last item of the stream.
+    fn end(&mut self, err: JsonStreamError) {
+        let old = self.state.name();
+        self.state = State::Done();
+        self.aborted = Some(err);
+        if old != self.state.name() {
+            if let Some(ref mut on_state_change) = self.hooks.on_state_change {
+                on_state_change(old, self.state.name());
+            }
+            if let Some(started) = self.started {
+                self.stats.elapsed = started.elapsed();
+            }
+        }
+    }
+    /// Fail the stream with a `JsonStreamError::IOError` of kind `TimedOut` when it makes
+    /// no progress for `duration`: while waiting for the response, and then for every
+    /// frame of the body.
+    #[cfg(feature = "timeout")]
+    pub fn timeout(mut self, duration: Duration) -> Self {
+        self.timeout.set_duration(duration);
+        self
+    }
+    /// Measure the `timeout` with `clock` instead of the timer of tokio, for instance to
+    /// test timeouts deterministically.
+    #[cfg(feature = "timeout")]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.timeout.set_clock(clock.clone());
+        self.idle.set_clock(clock);
+        self
+    }
+    /// Call `on_idle` every `interval` during which the stream makes no progress, for
+    /// instance to keep a spinner alive while a slow upstream sends nothing. Unlike the
+    /// `timeout`, the stream goes on waiting.
+    ///
+    /// The countdown restarts on every frame of the body, like the one of the `timeout`.
+    /// It only runs while the stream is polled.
+    ///
+    /// # Panics
+    ///
+    /// If `interval` is zero.
+    #[cfg(feature = "timeout")]
+    pub fn on_idle(mut self, interval: Duration, on_idle: impl FnMut() + Send + 'static) -> Self {
+        assert!(!interval.is_zero(), "the idle interval must not be zero");
+        self.idle.set_duration(interval);
+        self.hooks.on_idle = Some(Box::new(on_idle));
+        self
+    }
+    /// The meta fields of `at_path_with_meta` found before the array, by their path, once
+    /// the array is reached. The fields that were not found are missing.
+    pub fn meta(&self) -> Option<&HashMap<String, serde_json::Value>> {
+        self.info.meta.as_ref()
+    }
+    /// The range of the resource served by a `206 Partial Content` response, once it has
+    /// arrived. This gives the size of the full resource, for instance to report the
+    /// progress of a download made of several ranges.
+    ///
+    /// With a `Content-Encoding`, the range is one of the compressed bytes, so a download
+    /// cannot be resumed at any offset: a range of a gzip body is only decoded if it
+    /// starts a gzip member, and the stream fails with `JsonStreamError::EncodingError`
+    /// otherwise, instead of yielding garbage.
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self.info.content_range
+    }
+    /// Whether a decoder actually decompresses the body, once the head of the response has
+    /// arrived: its `Content-Encoding` is one that is decoded, like gzip, or its
+    /// `Content-Type` is gzip with `gzip_content_type`, or the field of `base64_field`
+    /// holds a compressed body. False for a body sent without encoding or as `identity`,
+    /// and before the response arrives.
+    ///
+    /// This tells what the decoder does rather than what the response announced: once
+    /// `decode_fallback` finds a body to be plain json despite its `Content-Encoding`, it
+    /// is passed on as is, and this turns false.
+    pub fn is_decompressing(&self) -> bool {
+        match self.state {
+            State::Collecting { ref decoder, .. } => decoder.is_decompressing(),
+            _ => self.info.decompressing,
+        }
+    }
+    /// Drive the request until the head of the response is received, without reading its
+    /// body, for instance to set up the connection ahead of needing the data. The next
+    /// elements are then read right away, and `response_parts` is available.
+    ///
+    /// An error response is only reported by the stream, once its body is collected, as is
+    /// an error found while setting up the decoding of the body: only a request failing
+    /// before any response is an error here.
+    pub async fn connect(&mut self) -> Result<(), JsonStreamError> {
+        let res = poll_fn(|cx| self.poll_until(cx, true)).await;
+        match res {
+            Some(Err(err)) if self.response_parts().is_none() => Err(err),
+            Some(Err(err)) => {
+                self.aborted = Some(err);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Drive the request until the head of the response is received, like `connect`, and
+    /// return a copy of its headers, for instance to check a rate limit before reading the
+    /// body. The stream then yields the elements as usual.
+    ///
+    /// The headers of an error response are returned too: the `JsonStreamError::ApiError`
+    /// or `JsonStreamError::RateLimited` is yielded by the next call to `next`, as is an
+    /// error found while setting up the decoding of the body. Only a request failing
+    /// before any response is an error here. Once the head has arrived, the headers are
+    /// returned without reading further. The map is empty for a stream created with `from_body`.
+    pub async fn headers(&mut self) -> Result<HeaderMap, JsonStreamError> {
+        let res = poll_fn(|cx| self.poll_until(cx, true)).await;
+        let headers = self.response_parts().map(|parts| parts.headers.clone());
+        match res {
+            Some(Err(err)) if headers.is_none() => Err(err),
+            Some(Err(err)) => {
+                self.aborted = Some(err);
+                Ok(headers.unwrap_or_default())
+            }
+            _ => Ok(headers.unwrap_or_default()),
+        }
+    }
+    /// Drain what is left of the body, for the connection to be reused, but give up after
+    /// `max_drain` so that a huge or slow tail does not hold up the shutdown. The body is
+    /// then dropped, which closes its HTTP/2 stream or its HTTP/1 connection.
+    ///
+    /// Waits for the response first if it has not arrived yet, within the same delay. The
+    /// time is measured with the clock of `with_clock`.
+    #[cfg(feature = "timeout")]
+    pub async fn shutdown(mut self, max_drain: Duration) -> Drained {
+        let clock = self.timeout.clock();
+        let mut deadline = clock.sleep_until(clock.now() + max_drain);
+        let mut drained = Drained {
+            bytes: 0,
+            complete: false,
+        };
+        let mut body = match mem::replace(&mut self.state, State::Done()) {
+            State::Connecting(mut fut) => {
+                let resp = poll_fn(|cx| match Pin::new(&mut fut).poll(cx) {
+                    Poll::Ready(resp) => Poll::Ready(resp.ok()),
+                    Poll::Pending => deadline.as_mut().poll(cx).map(|()| None),
+                });
+                match resp.await {
+                    Some(resp) => resp.into_body().into(),
+                    None => return drained,
+                }
+            }
+            State::Received(_, body)
+            | State::Collecting { body, .. }
+            | State::CollectingError(_, body, _) => body,
+            State::Done() => {
+                drained.complete = true;
+                return drained;
+            }
+        };
+        poll_fn(|cx| loop {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(());
+            }
+            match Pin::new(&mut body).poll_frame(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(frame))) => {
+                    if let Some(data) = frame.data_ref() {
+                        drained.bytes += data.len() as u64;
+                    }
+                }
+                Poll::Ready(Some(Err(_))) => return Poll::Ready(()),
+                Poll::Ready(None) => {
+                    drained.complete = true;
+                    return Poll::Ready(());
+                }
+            }
+        })
+        .await;
+        drained
+    }
+    /// The status, headers and version of the response, once it has arrived. There are
+    /// none for a stream created with `from_body`.
+    pub fn response_parts(&self) -> Option<&Parts> {
+        match self.state {
+            State::CollectingError(ref parts, _, _) => Some(parts),
+            _ => self.info.parts.as_ref(),
+        }
+    }
+    /// Whether the response was a `304 Not Modified`, answering a conditional request like
+    /// one with `If-None-Match`. The stream is then empty and the caller should use its
+    /// cached copy of the data.
+    pub fn was_not_modified(&self) -> bool {
+        self.info.not_modified
+    }
+    /// The trailers sent after the body, as over HTTP/2 or with a chunked HTTP/1.1 body,
+    /// once the body is received. They end the body, whose last bytes are then decoded.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.info.trailers.as_ref()
+    }
+    /// The HTTP version of the response, once it has arrived. This helps explaining
+    /// differences of behavior between upstreams, like trailers only sent over HTTP/2.
+    /// There is none for a stream created with `from_body`.
+    pub fn http_version(&self) -> Option<Version> {
+        self.response_parts().map(|parts| parts.version)
+    }
+    /// Inflate compressed bodies with the given zlib `windowBits`, to decode deflate
+    /// variants that servers send as `Content-Encoding: gzip`. The conventions of zlib apply:
+    ///
+    /// * 8 to 15 for a zlib stream, or 0 to use the window size of its header,
+    /// * -8 to -15 for raw deflate, without header nor checksum,
+    /// * 16 added for a gzip stream only, and 32 added to detect a zlib or a gzip header.
+    ///
+    /// The default is 47, accepting both zlib and gzip streams. Any other value fails the
+    /// stream with a `JsonStreamError::EncodingError`.
+    pub fn window_bits(mut self, window_bits: i32) -> Self {
+        self.config.window_bits = Some(window_bits);
+        self
+    }
+    /// Salvage a body announced as gzip that cannot be inflated from its start: it is
+    /// parsed as is if it looks like json, or decoded as raw deflate otherwise, for servers
+    /// announcing another encoding than the one they use.
+    ///
+    /// The fallback only applies until the first bytes are decoded, as the body received
+    /// so far is kept to be decoded again: a body failing later on still fails the stream.
+    /// Off by default.
+    pub fn decode_fallback(mut self, enabled: bool) -> Self {
+        self.config.decode_fallback = enabled;
+        self
+    }
+    /// Stream the array of the `application/json` part of a `multipart/*` response, like
+    /// `multipart/mixed; boundary=...`, skipping its other parts. Only the first json part
+    /// is streamed, and a body without one fails the stream.
+    ///
+    /// The parts are split after the body is decompressed; a response whose
+    /// `Content-Type` is not multipart, or without a boundary, is streamed as usual. Off by
+    /// default.
+    pub fn multipart(mut self, enabled: bool) -> Self {
+        self.config.multipart = enabled;
+        self
+    }
+    /// Stream the array of a body embedded in the json document received, as a base64
+    /// string at `path`, like `{"data": {"payload": "H4sIAAAA..."}}` for `data.payload`.
+    /// The string is extracted, decoded from base64, then decompressed as gzip or zlib,
+    /// or taken as is when it already looks like json. `level` and the other settings
+    /// apply to the embedded body.
+    ///
+    /// The extraction and the decoding are also available on their own as `StringField`
+    /// and `Base64Decoder`. A document without a string at `path`, or an embedded body
+    /// that cannot be decoded, fails the stream.
+    pub fn base64_field(mut self, path: &str) -> Self {
+        self.config.base64_field = Some(path.to_string());
+        self
+    }
+    /// Stream the elements of several json documents concatenated in the body, like
+    /// `[1, 2][3]`, as a single stream. This is the default, and how the stream always read
+    /// such bodies: anything between the documents is skipped, like the `7` of `[1] 7 [2]`.
+    /// With `false`, anything but whitespace after the first document is an error, which
+    /// catches a body with unexpected data after its array.
+    pub fn multi_array(mut self, multi_array: bool) -> Self {
+        self.config.multi_array = multi_array;
+        self
+    }
+    /// Tolerate up to `max` bytes of unknown garbage before the json document starts, and
+    /// up to `max` bytes other than whitespace after it ends, like a stray byte injected by
+    /// a proxy. The garbage after the document is only an error with `multi_array(false)`.
+    ///
+    /// The garbage before the document is skipped without being interpreted, so it may
+    /// hold quotes or closing braces. Only the `[` or `{` opening the document ends it.
+    pub fn skip_garbage(mut self, max: usize) -> Self {
+        self.config.max_garbage = max;
+        self
+    }
+    /// The headers of the error response, once the stream yielded its
+    /// `JsonStreamError::ApiError` or `JsonStreamError::RateLimited`, or the error reading
+    /// its body. This gives access to headers like a request id, to correlate the failure
+    /// with the logs of the upstream.
+    pub fn last_error_headers(&self) -> Option<&HeaderMap> {
+        self.info.error_headers.as_ref()
+    }
+    /// Do not collect the body of error responses: the stream fails right away with a
+    /// `JsonStreamError::ApiError` or `JsonStreamError::RateLimited` holding an empty body,
+    /// and the body can then be streamed with `take_error_body`, for instance to save a
+    /// huge error page to a file. By default the body is collected in the error.
+    pub fn stream_error_body(mut self) -> Self {
+        self.config.stream_error_body = true;
+        self
+    }
+    /// The body of the error response, once the stream failed with it in the
+    /// `stream_error_body` mode.
+    pub fn take_error_body(&mut self) -> Option<ErrorBody> {
+        self.info.error_body.take().map(ErrorBody::new)
+    }
+    /// Accept a response whose body is completely empty as an empty list. By default an
+    /// empty body fails with `JsonStreamError::MalformedJson` naming the expected document,
+    /// as it is more likely a misconfigured upstream or a truncated response than an empty
+    /// list. This applies to every target and to json text sequences, but not to newline
+    /// delimited json, where an empty body holds no values.
+    pub fn allow_empty_body(mut self, allow_empty_body: bool) -> Self {
+        self.config.allow_empty_body = allow_empty_body;
+        self
+    }
+    /// Fail on the elements holding an object with the same key twice, as if they could not
+    /// be deserialized, with a `JsonStreamError::MalformedElement` naming the key. By default
+    /// `serde_json` keeps the last value, which parsers disagreeing on the value of such
+    /// an element can be abused with. Off by default, every element is then parsed twice.
+    pub fn reject_duplicate_keys(mut self, reject: bool) -> Self {
+        self.config.reject_duplicate_keys = reject;
+        self
+    }
+    /// Check at the end of the body that as many bytes were received as announced by its
+    /// `Content-Length`, and fail the stream with `JsonStreamError::MalformedJson`
+    /// otherwise, for instance when a proxy cut the body. This catches a truncated body
+    /// that still parses, like newline delimited json cut between two lines.
+    ///
+    /// Compressed bodies are not checked, and there is nothing to check without the
+    /// header, as with a chunked body. Off by default.
+    pub fn verify_content_length(mut self, verify: bool) -> Self {
+        self.config.verify_content_length = verify;
+        self
+    }
+    /// Inspect the frames of the body that do not carry data, such as trailers. Those
+    /// frames are handed to `on_frame` and skipped, instead of ending the stream with an
+    /// error. Trailers are accepted without it, see `trailers`.
+    pub fn on_frame(mut self, on_frame: impl FnMut(&Frame<Bytes>) + Send + 'static) -> Self {
+        self.hooks.on_frame = Some(Box::new(on_frame));
+        self
+    }
+    /// Call `on_state_change` with the names of the old and the new state on every state
+    /// transition, to diagnose where a stream hangs. The states are `Connecting`,
+    /// `Received`, `Collecting`, `CollectingError` and `Done`.
+    pub fn on_state_change(
+        mut self,
+        on_state_change: impl FnMut(&'static str, &'static str) + Send + 'static,
+    ) -> Self {
+        self.hooks.on_state_change = Some(Box::new(on_state_change));
+        self
+    }
+}
+impl<T> JsonStream<T> {
+    /// Deserialize the elements with the seeds made by `make_seed`, instead of
+    /// `T::deserialize`, for elements needing runtime context like an interner or an arena.
+    ///
+    /// A fresh seed is made for every element, right before deserializing it, and dropped
+    /// with it. State shared across the elements lives in `make_seed` itself, like an
+    /// `Arc<Mutex<Interner>>` cloned into every seed.
+    pub fn with_seed<S, F>(mut self, make_seed: F) -> Self
+    where
+        S: for<'de> DeserializeSeed<'de, Value = T>,
+        F: FnMut() -> S + Send + 'static,
+    {
+        self.hooks.seed = Some(seed_fn(make_seed));
+        self
+    }
+}
+#[cfg(feature = "roundtrip")]
+impl<T: Serialize> JsonStream<T> {
+    /// Check that every element survives a round-trip through `T`: the element, as
+    /// deserialized by `T` or by the seeds of `with_seed`, is serialized back and compared
+    /// to its raw json, structurally. An element that does not round-trip fails like an
+    /// element that cannot be deserialized. This catches a `T` or a seed that drops
+    /// fields, or changes values.
+    ///
+    /// The raw json of every element is parsed a second time and the element serialized,
+    /// so this is meant for tests, not for production.
+    pub fn strict_roundtrip(mut self) -> Self {
+        self.hooks.roundtrip = Some(check_roundtrip::<T>);
+        self
+    }
+}
+#[cfg(feature = "roundtrip")]
+fn check_roundtrip<T: Serialize>(value: &T, raw: &[u8], json5: bool) -> serde_json::Result<()> {
+    let original: serde_json::Value = deserialize_raw(PhantomData, raw, false, json5)?;
+    let reserialized = serde_json::to_value(value)?;
+    if reserialized == original {
+        Ok(())
+    } else {
+        Err(de::Error::custom(format!(
+            "the element does not round-trip, it became {}",
+            reserialized
+        )))
+    }
+}
+/// Count the elements of the json list of a response, found after `level` opening braces,
+/// without deserializing them. The body is still received and decompressed, and the
+/// structure of the json is checked as far as finding the boundaries of the elements
+/// requires, but the elements themselves are not validated. An empty element, like the
+/// ones of `[1,,2]` and `[1,]`, still fails.
+pub async fn count_elements(resp: ResponseFuture, level: u32) -> Result<u64, JsonStreamError> {
+    let mut stream: JsonStream<IgnoredAny> = JsonStream::new(resp, level, 0);
+    stream.config.scan_only = true;
+    let mut count = 0;
+    while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+        item?;
+        count += 1;
+    }
+    Ok(count)
+}
+/// Parse the json list of `bytes`, found after `level` opening braces, into a `Vec`: the
+/// non-streaming counterpart of `JsonStream`, for a body already in memory like a cached
+/// one. The elements are found by the same scanner, with the same errors, and an empty
+/// buffer fails like an empty body. As the whole document is at hand, a document cut
+/// before the end of the array fails too, instead of giving the elements before the cut.
+pub fn parse_array<T: DeserializeOwned>(
+    bytes: &[u8],
+    level: u32,
+) -> Result<Vec<T>, JsonStreamError> {
+    if bytes.is_empty() {
+        return Err(JsonStreamError::MalformedJson(
+            "empty body, expected JSON array".to_string(),
+        ));
+    }
+    let mut json = PartialJson::new(0, level);
+    json.push(bytes);
+    json.finish();
+    let mut elements = Vec::new();
+    while let Some(element) = json.next()? {
+        elements.push(element);
+    }
+    if !json.is_closed() {
+        return Err(JsonStreamError::MalformedJson(
+            "unexpected end of the document, expected the end of the JSON array".to_string(),
+        ));
+    }
+    Ok(elements)
+}
+impl JsonStream<serde_json::Map<String, serde_json::Value>> {
+    /// Deserialize only the top-level `fields` of the elements, which are objects, into a
+    /// map. The other fields are skipped without being allocated, which saves memory and
+    /// time on wide records of which only a few columns are used. The fields missing from
+    /// an element are missing from its map, and an element that is not an object fails.
+    pub fn project(self, fields: &[&str]) -> Self {
+        let projection = Projection::new(fields);
+        self.with_seed(move || projection.clone())
+    }
 }
 impl<T: DeserializeOwned> FusedStream for JsonStream<T> {
     /// Returns `true` if this stream has completed.
     fn is_terminated(&self) -> bool {
-        matches!(self.state, State::Done())
+        matches!(self.state, State::Done()) && self.aborted.is_none()
     }
 }
 impl<T: DeserializeOwned> Stream for JsonStream<T> {
@@ -87,179 +1374,623 @@ impl<T: DeserializeOwned> Stream for JsonStream<T> {
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<T, JsonStreamError>>> {
         let this = self.get_mut();
-        let cap = this.capacity;
-        let lvl = this.level;
-        let state_ref = &mut this.state;
-        loop {
-            if let Some(poll) = state_ref.poll(cx, lvl, cap) {
+        if this
+            .config
+            .max_elements_per_poll
+            .is_some_and(|max| this.ready_in_row >= max)
+        {
+            this.ready_in_row = 0;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        #[cfg(all(debug_assertions, feature = "tracing"))]
+        let progress = (this.state.name(), this.stats.wire_bytes);
+        let poll = this.poll_until(cx, false);
+        match poll {
+            Poll::Ready(Some(Ok(_))) => this.ready_in_row += 1,
+            Poll::Pending => this.ready_in_row = 0,
+            Poll::Ready(_) => {}
+        }
+        #[cfg(all(debug_assertions, feature = "tracing"))]
+        this.count_idle_poll(poll.is_pending(), progress);
+        if let (Poll::Ready(Some(Ok(item))), Some(check)) = (&poll, &mut this.hooks.sort_check) {
+            if let Some(index) = check(item) {
+                this.end(JsonStreamError::MalformedJson(format!(
+                    "sort order violated at index {index}"
+                )));
+                return Poll::Ready(this.aborted.take().map(Err));
+            }
+        }
+        poll
+    }
+}
+
+impl<T: DeserializeOwned> JsonStream<T> {
+    /// Count the polls returning `Pending` without progress since `progress`, the state
+    /// and the bytes received before the poll, and warn once there are too many of them.
+    ///
+    /// This is a debugging aid for the state machine, compiled in debug builds with the
+    /// `tracing` feature only. A stream that misses a waker is usually never polled again,
+    /// but one that keeps being polled for nothing, like with a waker registered on the
+    /// wrong future or a wake-up loop, shows up here.
+    #[cfg(all(debug_assertions, feature = "tracing"))]
+    fn count_idle_poll(&mut self, pending: bool, progress: (&'static str, u64)) {
+        if !pending || progress != (self.state.name(), self.stats.wire_bytes) {
+            self.idle_polls = 0;
+            return;
+        }
+        self.idle_polls += 1;
+        if self.idle_polls == IDLE_POLLS_WARNING {
+            tracing::warn!(
+                state = self.state.name(),
+                polls = self.idle_polls,
+                "JsonStream keeps returning Pending without progress, a waker may be missing"
+            );
+        }
+    }
+    /// Drive the request until the head of the response is handled, like `connect`.
+    pub(crate) fn poll_head(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        self.poll_until(cx, true)
+    }
+    /// Drive the state machine until it yields an item or needs to wait, or only until
+    /// the head of the response is handled with `head_only`.
+    fn poll_until(
+        &mut self,
+        cx: &mut Context<'_>,
+        head_only: bool,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        if let Some(err) = self.aborted.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        #[cfg(feature = "timeout")]
+        {
+            let progress = (self.state.name(), self.stats.wire_bytes);
+            let poll = self.poll_state(cx, head_only);
+            if poll.is_ready() || progress != (self.state.name(), self.stats.wire_bytes) {
+                self.timeout.reset();
+                self.idle.reset();
+            }
+            if poll.is_pending() && self.timeout.poll_expired(cx) {
+                self.end(JsonStreamError::IOError(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "No progress before the timeout",
+                )));
+                return Poll::Ready(self.aborted.take().map(Err));
+            }
+            // Polling the countdown again after a reset starts the next one.
+            while poll.is_pending() && self.idle.poll_expired(cx) {
+                if let Some(ref mut on_idle) = self.hooks.on_idle {
+                    on_idle();
+                }
+                self.idle.reset();
+            }
+            poll
+        }
+        #[cfg(not(feature = "timeout"))]
+        self.poll_state(cx, head_only)
+    }
+    /// Drive the state machine until it yields an item or needs to wait.
+    fn poll_state(
+        &mut self,
+        cx: &mut Context<'_>,
+        head_only: bool,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        let config = &self.config;
+        let state_ref = &mut self.state;
+        let hooks = &mut self.hooks;
+        let info = &mut self.info;
+        let stats = &mut self.stats;
+        let started = *self.started.get_or_insert_with(Instant::now);
+        for _ in 0..POLL_BUDGET {
+            // Checked before polling, so that once the head has arrived, the next element
+            // is left for `next` instead of being polled here.
+            if head_only && !matches!(state_ref, State::Connecting(_) | State::Received(_, _)) {
+                return Poll::Ready(None);
+            }
+            if let State::Collecting { ref decoder, .. } = state_ref {
+                // Kept for once the decoder is dropped with the body.
+                info.decompressing = decoder.is_decompressing();
+            }
+            let old = state_ref.name();
+            let poll = state_ref.poll(cx, config, hooks, info, stats);
+            let new = state_ref.name();
+            if old != new {
+                if let Some(ref mut on_state_change) = hooks.on_state_change {
+                    on_state_change(old, new);
+                }
+                if let State::Done() = state_ref {
+                    stats.elapsed = started.elapsed();
+                }
+                if old == "Connecting" {
+                    stats.time_to_head = Some(started.elapsed());
+                }
+            }
+            if stats.time_to_first_byte.is_none() && stats.wire_bytes > 0 {
+                stats.time_to_first_byte = Some(started.elapsed());
+            }
+            if stats.time_to_first_element.is_none() && stats.elements > 0 {
+                stats.time_to_first_element = Some(started.elapsed());
+            }
+            if let Some(poll) = poll {
                 return poll;
             }
         }
+        // Out of budget: let the other tasks run, and go on right after them.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: DeserializeOwned + Send + 'static> JsonStream<T> {
+    /// Deserialize the elements larger than `threshold` bytes on the blocking pool of
+    /// tokio, with `tokio::task::spawn_blocking`, so that a huge element does not block
+    /// the executor. Smaller elements are still deserialized inline, which is cheaper.
+    ///
+    /// The stream must be polled within a multi-threaded tokio runtime. The elements are
+    /// yielded in order: the stream waits for an offloaded element before going on.
+    pub fn offload_large(mut self, threshold: usize) -> Self {
+        self.hooks.offload = Some((threshold, offload::<T>));
+        self
+    }
+}
+
+/// Deserialize `element` on the blocking pool of tokio.
+#[cfg(feature = "tokio")]
+fn offload<T: DeserializeOwned + Send + 'static>(element: Offloaded) -> ElementFuture<T> {
+    let handle = tokio::task::spawn_blocking(move || element.deserialize());
+    Box::pin(async move {
+        match handle.await {
+            Ok(res) => res,
+            Err(err) => Err(JsonStreamError::IOError(io::Error::other(err))),
+        }
+    })
+}
+
+/// Whether the request failed before reaching the server, so that it can be sent again.
+fn failed_to_connect(err: &JsonStreamError) -> bool {
+    match err {
+        JsonStreamError::ClientError(e) if e.is_connect() => true,
+        err => err.is_retryable(),
+    }
+}
+
+/// Flush the decoder at the end of the body, and mark the end of the document. The body
+/// must be `expected_length` bytes long, when given.
+fn finish_body<T: DeserializeOwned>(
+    decoder: &mut Decoder,
+    json: &mut PartialJson<T>,
+    stats: &mut StreamStats,
+    expected_length: Option<u64>,
+) -> Result<(), JsonStreamError> {
+    if let Some(expected) = expected_length.filter(|&expected| expected != stats.wire_bytes) {
+        return Err(JsonStreamError::json(format!(
+            "short read: got {} of {} bytes",
+            stats.wire_bytes, expected
+        )));
+    }
+    #[cfg(feature = "profiling")]
+    let started = Instant::now();
+    decoder.finish(|bytes| {
+        stats.decoded_bytes += bytes.len() as u64;
+        json.push(bytes)
+    })?;
+    #[cfg(feature = "profiling")]
+    stats.record_decompress(started.elapsed());
+    json.finish();
+    Ok(())
+}
+
+/// The error of an error response with the given body.
+fn api_error(parts: &Parts, body: String) -> JsonStreamError {
+    match parts.status {
+        StatusCode::TOO_MANY_REQUESTS => JsonStreamError::RateLimited {
+            retry_after: get_retry_after(&parts.headers, SystemTime::now()),
+            body,
+        },
+        status => JsonStreamError::ApiError(status, body),
     }
 }
 
 impl<T: DeserializeOwned> State<T> {
+    /// End the collection of an error response, keeping its head.
+    fn error_done(&mut self, info: &mut ResponseInfo) {
+        if let State::CollectingError(parts, _, _) = mem::replace(self, State::Done()) {
+            info.parts = Some(parts);
+        }
+    }
+    /// Handle the head of a response, and move on to the collection of its body.
+    fn receive(
+        &mut self,
+        parts: Parts,
+        body: ResponseBody,
+        config: &Config,
+        hooks: &mut Hooks<T>,
+        info: &mut ResponseInfo,
+    ) -> Option<Poll<Option<Result<T, JsonStreamError>>>> {
+        let mut encoding = ContentEncoding::from_headers(&parts.headers);
+        if config.gzip_content_type && encoding == ContentEncoding::None {
+            encoding = ContentEncoding::from_content_type(&parts.headers);
+        }
+        info.content_length = Some(get_content_length(&parts));
+        if config.verify_content_length
+            && matches!(encoding, ContentEncoding::None | ContentEncoding::Identity)
+        {
+            info.expected_length = parts
+                .headers
+                .get(http::header::CONTENT_LENGTH)
+                .and_then(|size| size.to_str().ok())
+                .and_then(|size| size.parse().ok());
+        }
+        info.encoding = encoding.clone();
+        match parts.status {
+            StatusCode::OK | StatusCode::PARTIAL_CONTENT => {
+                if parts.status == StatusCode::PARTIAL_CONTENT {
+                    info.content_range = ContentRange::from_headers(&parts.headers);
+                }
+                let cap = config.initial_capacity(get_content_length(&parts), &encoding);
+                // The buffer is taken from the pool, or is the one of the last body.
+                let mut json = match config.framing(&parts.headers) {
+                    // An array found in place of the lines starts at the top level.
+                    Framing::Array if config.framing != Framing::Array => PartialJson::new(0, 1),
+                    Framing::Array => PartialJson::new(0, config.level),
+                    Framing::Lines => PartialJson::lines(0),
+                    Framing::JsonSeq => PartialJson::json_seq(0),
+                    Framing::FlattenedLines => PartialJson::flattened_lines(0),
+                    Framing::ObjectValues => PartialJson::object_values(0, config.level),
+                    Framing::Single => PartialJson::single(0),
+                };
+                match config.pool {
+                    Some(ref pool) => json.pool(pool.clone(), cap),
+                    None => json.pool(config.spare.clone(), cap),
+                }
+                json.lenient(config.lenient);
+                json.disable_recursion_limit(config.unbounded_depth);
+                #[cfg(feature = "json5")]
+                json.json5(config.json5);
+                json.multi_array(config.multi_array);
+                json.skip_garbage(config.max_garbage);
+                json.keep_raw(config.keep_raw);
+                if let Some(ref path) = config.path {
+                    json.at_path(path.clone());
+                }
+                if config.footer {
+                    json.capture_footer();
+                }
+                if config.scan_only {
+                    json.scan_only();
+                }
+                if let Some(check) = hooks.roundtrip {
+                    json.roundtrip(check);
+                }
+                if config.reject_duplicate_keys {
+                    json.reject_duplicate_keys();
+                }
+                if let Some(ref on_skip) = hooks.on_skip {
+                    let on_skip = on_skip.clone();
+                    json.robust(Box::new(move |range| (*lock(&on_skip))(range)));
+                }
+                if let Some((threshold, ref on_chunk)) = hooks.on_spill {
+                    let on_chunk = on_chunk.clone();
+                    json.spill_oversized(
+                        threshold,
+                        Box::new(move |bytes, last| (*lock(&on_chunk))(bytes, last)),
+                    );
+                }
+                if let Some(ref seed) = hooks.seed {
+                    json.seed(seed.clone());
+                }
+                if let Some((threshold, _)) = hooks.offload {
+                    json.offload_large(threshold);
+                }
+                let decoder = match config.window_bits {
+                    Some(bits) => {
+                        Decoder::with_window_bits(&encoding, config.budget.as_ref(), bits)
+                    }
+                    None => Decoder::new(&encoding, config.budget.as_ref()),
+                };
+                let multipart = config
+                    .multipart
+                    .then(|| Multipart::from_headers(&parts.headers))
+                    .flatten();
+                let decoder = decoder.and_then(|decoder| {
+                    let embedded = config
+                        .base64_field
+                        .as_deref()
+                        .map(|path| EmbeddedBody::new(path, config.budget.as_ref()))
+                        .transpose()?;
+                    let decoder = decoder
+                        .fallback(config.decode_fallback)
+                        .multipart(multipart)
+                        .embedded(embedded);
+                    match info.content_range {
+                        Some(range) => decoder.resumed_at(range.start),
+                        None => Ok(decoder),
+                    }
+                });
+                #[cfg(feature = "charset")]
+                let decoder = decoder
+                    .map(|decoder| decoder.charset(Transcoder::from_headers(&parts.headers)));
+                match decoder {
+                    Ok(decoder) => {
+                        *self = State::Collecting {
+                            body,
+                            json: Box::new(json),
+                            decoder,
+                            offloaded: None,
+                        }
+                    }
+                    Err(err) => {
+                        *self = State::Done();
+                        info.parts = Some(parts);
+                        return Some(Poll::Ready(Some(Err(err))));
+                    }
+                }
+            }
+            StatusCode::NO_CONTENT => *self = State::Done(),
+            // The cached copy of the caller is still valid, there is nothing to stream.
+            StatusCode::NOT_MODIFIED => {
+                info.not_modified = true;
+                *self = State::Done();
+            }
+            _ if config.stream_error_body => {
+                info.error_headers = Some(parts.headers.clone());
+                let err = api_error(&parts, String::new());
+                *self = State::Done();
+                info.parts = Some(parts);
+                info.error_body = Some(body);
+                return Some(Poll::Ready(Some(Err(err))));
+            }
+            _ => {
+                info.error_headers = Some(parts.headers.clone());
+                let size = cmp::min(get_content_length(&parts), 0x1000);
+                *self = State::CollectingError(parts, body, Vec::with_capacity(size));
+                return None;
+            }
+        }
+        info.parts = Some(parts);
+        None
+    }
+    /// Poll the state. In tests, this also asserts that a `Pending` registered the
+    /// waker of `cx`, see `assert_wakes_on_pending`.
     #[inline]
     fn poll(
         &mut self,
         cx: &mut Context<'_>,
-        lvl: u32,
-        cap: usize,
+        config: &Config,
+        hooks: &mut Hooks<T>,
+        info: &mut ResponseInfo,
+        stats: &mut StreamStats,
+    ) -> Option<Poll<Option<Result<T, JsonStreamError>>>> {
+        #[cfg(test)]
+        {
+            let (probe, waker) = Probe::new(cx);
+            let poll =
+                self.poll_inner(&mut Context::from_waker(&waker), config, hooks, info, stats);
+            drop(waker);
+            assert!(
+                !matches!(poll, Some(Poll::Pending)) || probe.registered(),
+                "State::poll returned Pending without registering the waker"
+            );
+            poll
+        }
+        #[cfg(not(test))]
+        self.poll_inner(cx, config, hooks, info, stats)
+    }
+    #[inline]
+    fn poll_inner(
+        &mut self,
+        cx: &mut Context<'_>,
+        config: &Config,
+        hooks: &mut Hooks<T>,
+        info: &mut ResponseInfo,
+        stats: &mut StreamStats,
     ) -> Option<Poll<Option<Result<T, JsonStreamError>>>> {
         match self {
-            State::Connecting(ref mut fut) => match Pin::new(fut).poll(cx) {
-                Poll::Pending => Some(Poll::Pending),
-                Poll::Ready(Ok(resp)) => {
-                    let (parts, body) = resp.into_parts();
-                    let content_encoding_opt = parts.headers.get("Content-Encoding");
-                    let encoding = if let Some(content_encoding) = content_encoding_opt {
-                        let content_encoding_str = content_encoding.to_str().unwrap();
-                        ContentEncoding::from_str(content_encoding_str).unwrap()
-                    } else {
-                        ContentEncoding::None
-                    };
-                    match parts.status {
-                        StatusCode::OK => {
-                            let json = PartialJson::new(cap, lvl);
-                            if encoding == ContentEncoding::Gzip {
-                                let stream = Box::into_raw(Box::new(zlib::z_stream {
-                                    next_in: ptr::null_mut(),
-                                    avail_in: 0,
-                                    total_in: 0,
-                                    next_out: ptr::null_mut(),
-                                    avail_out: 0,
-                                    total_out: 0,
-                                    msg: ptr::null_mut(),
-                                    adler: 0,
-                                    data_type: 0,
-                                    reserved: 0,
-                                    opaque: ptr::null_mut(),
-                                    state: ptr::null_mut(),
-                                    zalloc,
-                                    zfree,
-                                }));
-                                let res = unsafe {
-                                    zlib::inflateInit2_(
-                                        stream,
-                                        47,
-                                        zlib::zlibVersion(),
-                                        mem::size_of::<zlib::z_stream>() as c_int,
-                                    )
-                                };
-
-                                if res == zlib::Z_OK {
-                                    *self = State::Collecting {
-                                        body,
-                                        json,
-                                        encoding,
-                                        stream,
-                                        total_in: 0,
-                                    };
-                                } else {
-                                    *self = State::EncodingError();
+            State::Received(_, _) => match mem::replace(self, State::Done()) {
+                State::Received(parts, body) => {
+                    let poll = self.receive(parts, body, config, hooks, info);
+                    if info.made_up_parts {
+                        info.parts = None;
+                    }
+                    poll
+                }
+                _ => unreachable!(),
+            },
+            State::Connecting(ref mut fut) => {
+                let res = match Pin::new(fut).poll(cx) {
+                    Poll::Pending => return Some(Poll::Pending),
+                    Poll::Ready(res) => res,
+                };
+                // A completed future must not be polled again: it is dropped right away,
+                // before anything can fail or return early.
+                *self = State::Done();
+                match res {
+                    Ok(resp) => {
+                        let (parts, body) = resp.into_parts();
+                        self.receive(parts, body.into(), config, hooks, info)
+                    }
+                    Err(e) => {
+                        let err = JsonStreamError::from(e);
+                        match hooks.retry {
+                            Some(ref mut retry)
+                                if retry.attempts_left > 0 && failed_to_connect(&err) =>
+                            {
+                                retry.attempts_left -= 1;
+                                match (retry.send)() {
+                                    Ok(fut) => {
+                                        *self = State::Connecting(fut);
+                                        None
+                                    }
+                                    Err(err) => Some(Poll::Ready(Some(Err(err.into())))),
                                 }
-                            } else {
-                                *self = State::Collecting {
-                                    body,
-                                    json,
-                                    encoding,
-                                    stream: ptr::null_mut(),
-                                    total_in: 0,
-                                };
                             }
-                        }
-                        StatusCode::NO_CONTENT => *self = State::Done(),
-                        _ => {
-                            let size = cmp::min(get_content_length(&parts), 0x1000);
-                            *self = State::CollectingError(parts, body, Vec::with_capacity(size));
+                            _ => Some(Poll::Ready(Some(Err(err)))),
                         }
                     }
-                    None
-                }
-                Poll::Ready(Err(e)) => {
-                    *self = State::Done();
-                    Some(Poll::Ready(Some(Err(e.into()))))
                 }
-            },
+            }
             State::Collecting {
                 ref mut body,
                 ref mut json,
-                ref encoding,
-                ref stream,
-                ref mut total_in,
-                ..
-            } => match json.next() {
-                Ok(Some(value)) => Some(Poll::Ready(Some(Ok(value)))),
-                Ok(None) => match Pin::new(body).poll_frame(cx) {
-                    Poll::Pending => Some(Poll::Pending),
-                    Poll::Ready(Some(Ok(chunk))) => match chunk.into_data() {
-                        Ok(b) => {
-                            if *encoding == ContentEncoding::None {
-                                json.push(&b[..]);
-                            } else {
-                                let mut bytes_vec = b.to_vec();
-                                loop {
-                                    let mut output_buffer = [0; 1024];
-                                    let data = &mut bytes_vec[*total_in as usize..];
-                                    let inflate_res = unsafe {
-                                        (*(*stream)).next_in = data.as_mut_ptr();
-                                        (*(*stream)).avail_in =
-                                            cmp::min(b.len(), c_uint::MAX as usize) as c_uint;
-                                        (*(*stream)).total_in = (*total_in).z_type();
-                                        (*(*stream)).next_out = output_buffer.as_mut_ptr();
-                                        (*(*stream)).avail_out =
-                                            cmp::min(output_buffer.len(), c_uint::MAX as usize)
-                                                as c_uint;
-
-                                        zlib::inflate(*stream, zlib::Z_NO_FLUSH)
-                                    };
-
-                                    if inflate_res == zlib::Z_BUF_ERROR || inflate_res == zlib::Z_OK
-                                    {
-                                        unsafe {
-                                            *total_in = (*(*stream)).total_in as u64;
-                                            if (*(*stream)).total_in as usize >= b.len() {
-                                                break;
-                                            }
-                                        }
-
-                                        json.push(&output_buffer);
-                                    } else {
-                                        eprintln!("zlib::inflate returned {}", inflate_res);
-                                        return Some(Poll::Ready(Some(Err(
-                                            JsonStreamError::EncodingError(
-                                                "Failed to decode bytes".to_string(),
-                                            ),
-                                        ))));
-                                    }
-                                }
-
-                                *total_in = 0;
-                            }
-
-                            None
+                ref mut decoder,
+                ref mut offloaded,
+            } => {
+                if let Some(ref mut element) = offloaded {
+                    let res = match element.as_mut().poll(cx) {
+                        Poll::Pending => return Some(Poll::Pending),
+                        Poll::Ready(res) => res,
+                    };
+                    *offloaded = None;
+                    stats.record_element(json.last_element_size() as u64);
+                    if let Err(ref err) = res {
+                        if !matches!(err, JsonStreamError::InvalidElement(_, _)) {
+                            *self = State::Done();
+                        }
+                    }
+                    return Some(Poll::Ready(Some(res)));
+                }
+                #[cfg(feature = "profiling")]
+                let started = Instant::now();
+                let next = json.next();
+                #[cfg(feature = "profiling")]
+                stats.record_next(started.elapsed(), json.take_deserialize_time());
+                if let Some(meta) = json.take_meta() {
+                    info.meta = Some(meta);
+                }
+                if let Some(footer) = json.take_footer() {
+                    info.footer = Some(footer);
+                }
+                match next {
+                    Ok(Some(value)) => {
+                        stats.record_element(json.last_element_size() as u64);
+                        Some(Poll::Ready(Some(Ok(value))))
+                    }
+                    Ok(None) if json.is_offloaded() => {
+                        // Only offloaded when there is an offloader.
+                        if let (Some(element), Some((_, offload))) =
+                            (json.take_offloaded(), hooks.offload)
+                        {
+                            *offloaded = Some(offload(element));
                         }
-                        Err(fr) => {
-                            eprintln!("{:?}", fr);
-                            Some(Poll::Ready(Some(Err(JsonStreamError::IOError(
-                                io::Error::new(
-                                    ErrorKind::InvalidData,
-                                    "Could not get bytes from frame",
-                                ),
-                            )))))
+                        None
+                    }
+                    Ok(None) if json.is_finished() => {
+                        // A json document was expected, but the body was completely empty.
+                        let missing = json
+                            .framing()
+                            .expected()
+                            .filter(|_| !config.allow_empty_body && json.is_empty());
+                        *self = State::Done();
+                        if let Some(expected) = missing {
+                            return Some(Poll::Ready(Some(Err(JsonStreamError::MalformedJson(
+                                format!("empty body, expected {}", expected),
+                            )))));
+                        }
+                        Some(Poll::Ready(None))
+                    }
+                    Ok(None)
+                        if config
+                            .pause
+                            .as_ref()
+                            .is_some_and(|pause| pause.poll_resumed(cx).is_pending()) =>
+                    {
+                        Some(Poll::Pending)
+                    }
+                    Ok(None) => match Pin::new(body).poll_frame(cx) {
+                        Poll::Pending => Some(Poll::Pending),
+                        Poll::Ready(Some(Ok(chunk))) => match chunk.into_data() {
+                            Ok(b) => {
+                                stats.wire_bytes += b.len() as u64;
+                                if config
+                                    .max_total_bytes
+                                    .is_some_and(|max| stats.wire_bytes > max)
+                                {
+                                    *self = State::Done();
+                                    return Some(Poll::Ready(Some(Err(JsonStreamError::json(
+                                        "download size limit exceeded".to_string(),
+                                    )))));
+                                }
+                                if decoder.is_passthrough() {
+                                    // Nothing to decode, the frame is parsed without a copy.
+                                    stats.decoded_bytes += b.len() as u64;
+                                    json.push_bytes(b);
+                                    return None;
+                                }
+                                #[cfg(feature = "profiling")]
+                                let started = Instant::now();
+                                let decoded = decoder.decode(&b, |bytes| {
+                                    stats.decoded_bytes += bytes.len() as u64;
+                                    json.push(bytes)
+                                });
+                                #[cfg(feature = "profiling")]
+                                stats.record_decompress(started.elapsed());
+                                match decoded {
+                                    Ok(()) => None,
+                                    Err(err) => {
+                                        *self = State::Done();
+                                        Some(Poll::Ready(Some(Err(err))))
+                                    }
+                                }
+                            }
+                            Err(fr) => {
+                                if let Some(ref mut on_frame) = hooks.on_frame {
+                                    on_frame(&fr);
+                                }
+                                match fr.into_trailers() {
+                                    // The trailers end the body, the decoder is flushed
+                                    // for the last elements.
+                                    Ok(trailers) => {
+                                        info.trailers = Some(trailers);
+                                        match finish_body(
+                                            decoder,
+                                            json,
+                                            stats,
+                                            info.expected_length,
+                                        ) {
+                                            Ok(()) => None,
+                                            Err(err) => {
+                                                *self = State::Done();
+                                                Some(Poll::Ready(Some(Err(err))))
+                                            }
+                                        }
+                                    }
+                                    Err(_) if hooks.on_frame.is_some() => None,
+                                    Err(fr) => Some(Poll::Ready(Some(Err(
+                                        JsonStreamError::IOError(io::Error::new(
+                                            ErrorKind::InvalidData,
+                                            format!("Could not get bytes from frame {:?}", fr),
+                                        )),
+                                    )))),
+                                }
+                            }
+                        },
+                        Poll::Ready(None) => {
+                            match finish_body(decoder, json, stats, info.expected_length) {
+                                Ok(()) => None,
+                                Err(err) => {
+                                    *self = State::Done();
+                                    Some(Poll::Ready(Some(Err(err))))
+                                }
+                            }
+                        }
+                        Poll::Ready(Some(Err(e))) => {
+                            *self = State::Done();
+                            Some(Poll::Ready(Some(Err(e))))
                         }
                     },
-                    Poll::Ready(None) => Some(Poll::Ready(None)),
-                    Poll::Ready(Some(Err(e))) => {
+                    Err(err @ JsonStreamError::InvalidElement(_, _)) => {
+                        Some(Poll::Ready(Some(Err(err))))
+                    }
+                    Err(err) => {
                         *self = State::Done();
-                        Some(Poll::Ready(Some(Err(e.into()))))
+                        Some(Poll::Ready(Some(Err(err))))
                     }
-                },
-                Err(err) => {
-                    *self = State::Done();
-                    Some(Poll::Ready(Some(Err(err))))
                 }
-            },
+            }
             State::CollectingError(ref parts, ref mut body, ref mut bytes) => {
                 match Pin::new(body).poll_frame(cx) {
                     Poll::Pending => Some(Poll::Pending),
@@ -268,37 +1999,2218 @@ impl<T: DeserializeOwned> State<T> {
                             bytes.extend(b.as_ref());
                             None
                         }
-                        Err(fr) => {
-                            eprintln!("{:?}", fr);
-                            Some(Poll::Ready(Some(Err(JsonStreamError::IOError(
-                                io::Error::new(
-                                    ErrorKind::InvalidData,
-                                    "Could not get bytes from frame",
-                                ),
-                            )))))
+                        Err(fr) if fr.is_trailers() => {
+                            info.trailers = fr.into_trailers().ok();
+                            None
                         }
+                        Err(fr) => Some(Poll::Ready(Some(Err(JsonStreamError::IOError(
+                            io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Could not get bytes from frame {:?}", fr),
+                            ),
+                        ))))),
                     },
                     Poll::Ready(None) => match String::from_utf8(bytes.clone()) {
                         Ok(err_msg) => {
-                            let err = JsonStreamError::ApiError(parts.status, err_msg);
-                            *self = State::Done();
+                            let err = api_error(parts, err_msg);
+                            self.error_done(info);
                             Some(Poll::Ready(Some(Err(err))))
                         }
                         Err(err) => {
-                            *self = State::Done();
+                            self.error_done(info);
                             Some(Poll::Ready(Some(Err(err.into()))))
                         }
                     },
                     Poll::Ready(Some(Err(err))) => {
-                        *self = State::Done();
-                        Some(Poll::Ready(Some(Err(err.into()))))
+                        self.error_done(info);
+                        Some(Poll::Ready(Some(Err(err))))
                     }
                 }
             }
-            State::EncodingError() => Some(Poll::Ready(Some(Err(JsonStreamError::EncodingError(
-                "Failed to decode the payload with gzip".to_string(),
-            ))))),
             State::Done() => Some(Poll::Ready(None)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{count_elements, Config, JsonStream};
+    use crate::stream::encoding::ContentEncoding;
+    use crate::stream::pause::PauseSignal;
+    use crate::stream::pool::BufferPool;
+    use crate::stream::raw::RawJsonStream;
+    use crate::stream::target::StreamTarget;
+    use crate::util::test_server::{chunked, refused, respond, City, CITIES};
+    use crate::util::JsonStreamError;
+    use flate2::write::{DeflateEncoder, GzEncoder};
+    use flate2::Compression;
+    use futures_core::stream::FusedStream;
+    use futures_util::StreamExt;
+    use hyper::body::{Bytes, Frame};
+    use serde_json::json;
+    use static_assertions::assert_impl_all;
+    use std::cell::Cell;
+    use std::io::Write;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+    use std::task::Poll;
+
+    // `JsonStream` is Send, Sync and Unpin whatever the `T`, as a `T` is only ever held by
+    // the boxed `Send` future of an offloaded element.
+    // See the comments on the `unsafe impl`s of `State` and `Hooks` for why it is sound.
+    assert_impl_all!(JsonStream<u32>: Send, Sync, Unpin);
+    assert_impl_all!(JsonStream<serde_json::Value>: Send, Sync, Unpin);
+    assert_impl_all!(JsonStream<Rc<u32>>: Send, Sync, Unpin);
+    assert_impl_all!(JsonStream<Cell<u32>>: Send, Sync, Unpin);
+    #[cfg(feature = "test-util")]
+    assert_impl_all!(crate::stream::throttle::ThrottledJsonStream<Rc<u32>>: Send, Sync, Unpin);
+
+    #[tokio::test]
+    async fn gzip_stream() {
+        let json = format!(
+            "[{}]",
+            (0..500)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, (0..500).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "compress")]
+    #[tokio::test]
+    async fn compress_stream() {
+        let json = format!(
+            "[{}]",
+            (0..500)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let compressed = crate::stream::lzw::tests::compress(json.as_bytes(), 16);
+        let mut response = chunked(&compressed, 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "compress".parse().unwrap());
+        let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, (0..500).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn ndjson_stream() {
+        let response = chunked(b"{\"a\": 1}\n\n{\"a\": 2}\r\n{\"a\": 3}", 5);
+        let stream: JsonStream<serde_json::Value> = JsonStream::ndjson(respond(response).await, 0);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(
+            res,
+            [
+                serde_json::json!({"a": 1}),
+                serde_json::json!({"a": 2}),
+                serde_json::json!({"a": 3})
+            ]
+        );
+    }
+
+    #[test]
+    fn auto_capacity() {
+        let mut config = Config {
+            capacity: 100,
+            level: 1,
+            auto_capacity: false,
+            ..Config::default()
+        };
+        assert_eq!(config.initial_capacity(5000, &ContentEncoding::None), 100);
+        config.auto_capacity = true;
+        assert_eq!(config.initial_capacity(10, &ContentEncoding::None), 100);
+        assert_eq!(config.initial_capacity(5000, &ContentEncoding::None), 5000);
+        assert_eq!(config.initial_capacity(5000, &ContentEncoding::Gzip), 20000);
+        assert_eq!(
+            config.initial_capacity(usize::MAX, &ContentEncoding::Gzip),
+            4 * 1024 * 1024
+        );
+    }
+
+    #[tokio::test]
+    async fn on_frame_trailers() {
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        let response = http::Response::new(vec![
+            Frame::data(Bytes::from("[1, 2")),
+            Frame::data(Bytes::from(", 3]")),
+            Frame::trailers(trailers),
+        ]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0)
+            .on_frame(move |frame| sink.lock().unwrap().push(frame.trailers_ref().cloned()));
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3]);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].as_ref().unwrap()["x-checksum"], "abc");
+    }
+
+    #[tokio::test]
+    async fn gzip_trailers() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"1\n2\n3").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-checksum", "abc".parse().unwrap());
+        let mut frames: Vec<_> = gzipped
+            .chunks(5)
+            .map(|chunk| Frame::data(Bytes::copy_from_slice(chunk)))
+            .collect();
+        frames.push(Frame::trailers(trailers));
+        let mut response = http::Response::new(frames);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::ndjson(respond(response).await, 0);
+        let mut res = Vec::new();
+        while let Some(item) = stream.next().await {
+            res.push(item.unwrap());
+        }
+        // The last line has no newline, it is only flushed at the end of the body.
+        assert_eq!(res, [1, 2, 3]);
+        assert_eq!(stream.trailers().unwrap()["x-checksum"], "abc");
+    }
+
+    #[tokio::test]
+    async fn lenient_continues_after_invalid_element() {
+        #[derive(serde::Deserialize)]
+        struct Item {
+            a: u32,
+        }
+        let response = chunked(br#"[{"a": 1}, {"a": "two"}, {"a": 3}]"#, 7);
+        let stream: JsonStream<Item> = JsonStream::new(respond(response).await, 1, 0).lenient(true);
+        let res: Vec<_> = stream
+            .map(|item| match item {
+                Ok(item) => Ok(item.a),
+                Err(JsonStreamError::InvalidElement(_, raw)) => Err(raw),
+                Err(err) => panic!("{}", err),
+            })
+            .collect()
+            .await;
+        assert_eq!(res, [Ok(1), Err(br#"{"a": "two"}"#.to_vec()), Ok(3)]);
+    }
+
+    #[tokio::test]
+    async fn state_changes() {
+        let mut error = chunked(b"Not here", 4);
+        *error.status_mut() = http::StatusCode::NOT_FOUND;
+        for (response, expected) in [
+            (
+                chunked(b"[1, 2]", 2),
+                [("Connecting", "Collecting"), ("Collecting", "Done")],
+            ),
+            (
+                error,
+                [
+                    ("Connecting", "CollectingError"),
+                    ("CollectingError", "Done"),
+                ],
+            ),
+        ] {
+            let changes = Arc::new(Mutex::new(Vec::new()));
+            let sink = changes.clone();
+            let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0)
+                .on_state_change(move |old, new| sink.lock().unwrap().push((old, new)));
+            stream.collect::<Vec<_>>().await;
+            assert_eq!(changes.lock().unwrap()[..], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn budget_exhausted() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"[1, 2, 3]").unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0)
+            .with_budget(crate::DecompressionBudget::new(1024));
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 1);
+        assert!(matches!(res[0], Err(JsonStreamError::EncodingError(_))));
+    }
+
+    #[tokio::test]
+    async fn try_fold_elements() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let sum = stream
+            .try_fold_elements(0.0, |sum, city| {
+                let lat: f64 = city
+                    .lat
+                    .parse()
+                    .map_err(|_| JsonStreamError::MalformedJson(city.lat))?;
+                Ok(sum + lat)
+            })
+            .await
+            .unwrap();
+        assert!((sum - 484.6992).abs() < 1e-9, "{}", sum);
+
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let res = stream
+            .try_fold_elements(0, |count, city| match city.country.as_str() {
+                "AE" => Err(JsonStreamError::MalformedJson(city.name)),
+                _ => Ok(count + 1),
+            })
+            .await;
+        assert!(matches!(res, Err(JsonStreamError::MalformedJson(name)) if name == "Abu Dhabi"));
+    }
+
+    #[tokio::test]
+    async fn drive_into() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let mut names = Vec::new();
+        stream
+            .drive_into(|city| {
+                names.push(city.name);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(names.len(), 12);
+        assert_eq!(names[0], "Sant Julià de Lòria");
+
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let mut count = 0;
+        let res = stream
+            .drive_into(|city| match city.country.as_str() {
+                "AE" => Err(JsonStreamError::MalformedJson(city.name)),
+                _ => {
+                    count += 1;
+                    Ok(())
+                }
+            })
+            .await;
+        assert!(matches!(res, Err(JsonStreamError::MalformedJson(name)) if name == "Abu Dhabi"));
+        assert!(count > 0 && count < 12);
+
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1, x]", 2)).await, 1, 0);
+        let mut res = Vec::new();
+        let sink = |item| {
+            res.push(item);
+            Ok(())
+        };
+        assert!(stream.drive_into(sink).await.is_err());
+        assert_eq!(res, [1]);
+    }
+
+    #[cfg(feature = "sink")]
+    #[tokio::test]
+    async fn pipe_to() {
+        use crate::util::PipeError;
+        use futures_channel::mpsc;
+        use futures_sink::Sink;
+        use std::convert::Infallible;
+        use std::pin::Pin;
+        use std::task::Context;
+
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 16)).await, 1, 0);
+        // No room in the channel, the stream waits for the receiver after every element.
+        let (sender, mut receiver) = mpsc::channel::<City>(0);
+        let received = tokio::spawn(async move {
+            let mut names = Vec::new();
+            while let Some(city) = receiver.next().await {
+                names.push(city.name);
+                tokio::task::yield_now().await;
+            }
+            names
+        });
+        stream.pipe_to(sender).await.unwrap();
+        let names = received.await.unwrap();
+        assert_eq!(names.len(), 12);
+        assert_eq!(names[0], "Sant Julià de Lòria");
+
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1, x]", 2)).await, 1, 0);
+        let (sender, receiver) = mpsc::channel(4);
+        let res = stream.pipe_to(sender).await;
+        assert!(matches!(
+            res,
+            Err(PipeError::Stream(JsonStreamError::MalformedElement(_, _)))
+        ));
+        assert_eq!(receiver.collect::<Vec<_>>().await, [1]);
+
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1, 2]", 2)).await, 1, 0);
+        let (sender, receiver) = mpsc::channel(4);
+        drop(receiver);
+        match stream.pipe_to(sender).await {
+            Err(PipeError::Sink(err)) => assert!(err.is_disconnected()),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        /// Hands over the elements sent to it only when flushed.
+        #[derive(Default)]
+        struct Buffered {
+            buffer: Vec<u32>,
+            flushed: Vec<u32>,
+        }
+        impl Sink<u32> for &mut Buffered {
+            type Error = Infallible;
+            fn poll_ready(
+                self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+            ) -> Poll<Result<(), Infallible>> {
+                Poll::Ready(Ok(()))
+            }
+            fn start_send(self: Pin<&mut Self>, item: u32) -> Result<(), Infallible> {
+                self.get_mut().buffer.push(item);
+                Ok(())
+            }
+            fn poll_flush(
+                self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+            ) -> Poll<Result<(), Infallible>> {
+                let this = self.get_mut();
+                this.flushed.append(&mut this.buffer);
+                Poll::Ready(Ok(()))
+            }
+            fn poll_close(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<Result<(), Infallible>> {
+                self.poll_flush(cx)
+            }
+        }
+        // The elements before the error are flushed, even without waiting for the body.
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, x]", 9)).await, 1, 0);
+        let mut sink = Buffered::default();
+        assert!(matches!(
+            stream.pipe_to(&mut sink).await,
+            Err(PipeError::Stream(JsonStreamError::MalformedElement(_, _)))
+        ));
+        assert_eq!(sink.flushed, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn batched() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let sizes: Vec<usize> = stream
+            .batched(5)
+            .map(|batch| batch.unwrap().len())
+            .collect()
+            .await;
+        assert_eq!(sizes, [5, 5, 2]);
+
+        let json = b"[1, 2, 3, 4, 5, 6] 7";
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 3)).await, 1, 0).multi_array(false);
+        let mut batches = stream.batched(4);
+        assert_eq!(batches.next().await.unwrap().unwrap(), [1, 2, 3, 4]);
+        assert!(matches!(
+            batches.next().await,
+            Some(Err(JsonStreamError::MalformedJson(_)))
+        ));
+        assert!(batches.next().await.is_none());
+        assert!(batches.is_terminated());
+    }
+
+    #[tokio::test]
+    async fn collect_map() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 100)).await, 1, 0);
+        let map = stream
+            .collect_map(|city| city.country.clone())
+            .await
+            .unwrap();
+        assert_eq!(map.len(), 7);
+        // The last city of a country wins.
+        assert_eq!(map["AD"].name, "Ordino");
+        assert_eq!(map["FR"].name, "Lyon");
+        assert_eq!(map["US"].name, "New York City");
+    }
+
+    #[tokio::test]
+    async fn single_frame_body() {
+        let json = format!(
+            "[{}]",
+            (0..100)
+                .map(|i| format!("{{\"a\": {}}}", i))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        let response = chunked(json.as_bytes(), json.len());
+        assert_eq!(response.body().len(), 1);
+        let stream: JsonStream<serde_json::Value> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res.len(), 100);
+        assert_eq!(res[99]["a"], 99);
+    }
+
+    #[tokio::test]
+    async fn from_body() {
+        // Any `Incoming` will do: the status of the response is not looked at.
+        let mut response = chunked(CITIES, 64);
+        *response.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        let body = respond(response).await.await.unwrap().into_body();
+        let stream: JsonStream<City> = JsonStream::from_body(body, 1, 0);
+        let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res.len(), 12);
+        assert_eq!(res[0].country, "AD");
+    }
+
+    #[tokio::test]
+    async fn from_boxed_body() {
+        use http_body_util::StreamBody;
+
+        type BoxError = Box<dyn std::error::Error + Send + Sync>;
+        fn boxed(frames: Vec<Result<&'static [u8], BoxError>>) -> super::BoxedBody {
+            let frames = frames
+                .into_iter()
+                .map(|frame| frame.map(|bytes| Frame::data(Bytes::from_static(bytes))));
+            Box::new(StreamBody::new(futures_util::stream::iter(frames)))
+        }
+        let body = respond(chunked(b"[1, 2]", 2))
+            .await
+            .await
+            .unwrap()
+            .into_body();
+        let streams: Vec<JsonStream<u32>> = vec![
+            JsonStream::from_body(body, 1, 0),
+            JsonStream::from_boxed_body(boxed(vec![Ok(b"[3, "), Ok(b"4]")]), 1, 0),
+        ];
+        let mut res = Vec::new();
+        for stream in streams {
+            res.extend(stream.map(Result::unwrap).collect::<Vec<u32>>().await);
+        }
+        assert_eq!(res, [1, 2, 3, 4]);
+
+        let err = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        let mut stream: JsonStream<u32> =
+            JsonStream::from_boxed_body(boxed(vec![Ok(b"[5, 6"), Err(err.into())]), 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 5);
+        match stream.next().await {
+            Some(Err(JsonStreamError::IOError(err))) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn get() {
+        use crate::util::test_server::listen;
+        use hyper_util::client::legacy::Client;
+        use hyper_util::rt::TokioExecutor;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let addr = listen(response, false).await;
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http();
+        let stream: JsonStream<City> =
+            JsonStream::get(&client, &format!("http://{}/cities", addr), 1, 0).unwrap();
+        let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res.len(), 12);
+
+        assert!(matches!(
+            JsonStream::<City>::get(&client, "http://in valid", 1, 0),
+            Err(JsonStreamError::HttpError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn connection_error() {
+        let mut stream: JsonStream<u32> = JsonStream::new(refused().await, 1, 0);
+        match stream.next().await {
+            Some(Err(err @ JsonStreamError::ClientError(_))) => {
+                assert!(err
+                    .to_string()
+                    .starts_with("client error (Connect): tcp connect error: "))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Polling the completed `ResponseFuture` again would panic.
+        for _ in 0..5 {
+            assert!(stream.next().await.is_none());
+            assert!(stream.is_terminated());
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn offload_large() {
+        let json = br#"[[1], [2, 3, 4, 5, 6, 7, 8, 9, 10], [11], [12, 13, 14, 15, 16, "x"], [17]]"#;
+        let stream: JsonStream<Vec<u32>> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0).offload_large(16);
+        let offloaded: Vec<_> = stream
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect()
+            .await;
+        let stream: JsonStream<Vec<u32>> = JsonStream::new(respond(chunked(json, 7)).await, 1, 0);
+        let inline: Vec<_> = stream
+            .map(|res| res.map_err(|err| err.to_string()))
+            .collect()
+            .await;
+        assert_eq!(offloaded.len(), 4);
+        assert_eq!(offloaded[1], Ok((2..=10).collect::<Vec<_>>()));
+        assert!(offloaded[3].is_err());
+        // Offloading changes nothing but where the elements are deserialized.
+        assert_eq!(offloaded, inline);
+    }
+
+    #[tokio::test]
+    async fn dns_error() {
+        use hyper_util::client::legacy::connect::dns::Name;
+        use hyper_util::client::legacy::connect::HttpConnector;
+        use std::future::{ready, Ready};
+        use std::net::SocketAddr;
+
+        /// A resolver failing for every name, instead of asking a real DNS server.
+        #[derive(Clone)]
+        struct NoDns;
+        impl tower_service::Service<Name> for NoDns {
+            type Response = std::vec::IntoIter<SocketAddr>;
+            type Error = std::io::Error;
+            type Future = Ready<Result<Self::Response, Self::Error>>;
+            fn poll_ready(
+                &mut self,
+                _: &mut std::task::Context<'_>,
+            ) -> Poll<Result<(), Self::Error>> {
+                Poll::Ready(Ok(()))
+            }
+            fn call(&mut self, name: Name) -> Self::Future {
+                let err = std::io::Error::new(std::io::ErrorKind::NotFound, name.as_str());
+                ready(Err(err))
+            }
+        }
+
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .build::<_, http_body_util::Empty<Bytes>>(HttpConnector::new_with_resolver(NoDns));
+        let resp = client.get("http://bogus.invalid".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(resp, 1, 0);
+        let err = stream.next().await.unwrap().unwrap_err();
+        // The wrapped resolver error tells what went wrong.
+        assert_eq!(
+            err.to_string(),
+            "client error (Connect): dns error: bogus.invalid"
+        );
+    }
+
+    #[tokio::test]
+    async fn raw() {
+        let json = br#"{"a": [1, {"b": "x" }, "c"]}"#;
+        let stream: RawJsonStream<serde_json::Value> =
+            JsonStream::with_raw(respond(chunked(json, 5)).await, 2, 0);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(
+            res,
+            [
+                (Bytes::from_static(b"1"), json!(1)),
+                (Bytes::from_static(br#"{"b": "x" }"#), json!({"b": "x"})),
+                (Bytes::from_static(br#""c""#), json!("c")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "timeout")]
+    #[tokio::test(start_paused = true)]
+    async fn shutdown() {
+        use super::Drained;
+        use crate::util::test_server::respond_stalled;
+        use std::time::Duration;
+
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3, 4]", 4)).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        let wire_bytes = stream.bytes_received();
+        let drained = stream.shutdown(Duration::from_secs(5)).await;
+        assert_eq!(
+            drained,
+            Drained {
+                bytes: 12 - wire_bytes,
+                complete: true
+            }
+        );
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond_stalled(chunked(b"[1, 2, 3, 4]", 4)).await, 1, 0);
+        let drained = stream.shutdown(Duration::from_secs(5)).await;
+        assert_eq!(
+            drained,
+            Drained {
+                bytes: 12,
+                complete: false
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn level_and_capacity() {
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[]", 1)).await, 3, 128);
+        assert_eq!((stream.level(), stream.capacity()), (3, 128));
+        let stream: JsonStream<u32> = JsonStream::with_target(
+            respond(chunked(b"1", 1)).await,
+            StreamTarget::SingleValue,
+            64,
+        );
+        assert_eq!((stream.level(), stream.capacity()), (0, 64));
+    }
+
+    #[tokio::test]
+    async fn max_total_bytes() {
+        let json = b"[1, 2, 3, 4, 5, 6, 7, 8, 9]";
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 4)).await, 1, 0).max_total_bytes(12);
+        let mut res = Vec::new();
+        let err = loop {
+            match stream.next().await.unwrap() {
+                Ok(item) => res.push(item),
+                Err(err) => break err,
+            }
+        };
+        // The elements of the first 12 bytes.
+        assert_eq!(res, [1, 2, 3, 4]);
+        assert_eq!(err.to_string(), "download size limit exceeded");
+        assert_eq!(stream.bytes_received(), 16);
+        assert!(stream.next().await.is_none());
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 4)).await, 1, 0).max_total_bytes(27);
+        assert_eq!(stream.count().await, 9);
+    }
+
+    #[tokio::test]
+    async fn with_retry() {
+        use crate::util::test_server::{closed_addr, listen};
+        use hyper_util::client::legacy::Client;
+        use hyper_util::rt::TokioExecutor;
+
+        let client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http();
+        let addrs = [
+            closed_addr().await,
+            listen(chunked(b"[1, 2]", 3), false).await,
+        ];
+        let tokens = Arc::new(Mutex::new(Vec::new()));
+        let sent = tokens.clone();
+        let stream: JsonStream<u32> = JsonStream::with_retry(
+            client.clone(),
+            3,
+            move |req| {
+                let mut sent = sent.lock().unwrap();
+                let token = format!("Bearer {}", sent.len());
+                let addr = addrs[sent.len()];
+                sent.push(token.clone());
+                req.uri(format!("http://{}", addr))
+                    .header("Authorization", token)
+            },
+            1,
+            0,
+        );
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2]);
+        assert_eq!(*tokens.lock().unwrap(), ["Bearer 0", "Bearer 1"]);
+
+        let addr = closed_addr().await;
+        let mut attempts = 0;
+        let mut stream: JsonStream<u32> = JsonStream::with_retry(
+            client.clone(),
+            2,
+            move |req| {
+                attempts += 1;
+                assert!(attempts <= 2);
+                req.uri(format!("http://{}", addr))
+            },
+            1,
+            0,
+        );
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::ClientError(_)))
+        ));
+        assert!(stream.next().await.is_none());
+
+        let mut stream: JsonStream<u32> =
+            JsonStream::with_retry(client, 2, |req| req.uri("not a uri"), 1, 0);
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::HttpError(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn gzip_content_type() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"[1, 2, 3]").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let response = || {
+            let mut response = chunked(&gzipped, 8);
+            response
+                .headers_mut()
+                .insert("Content-Type", "application/gzip".parse().unwrap());
+            response
+        };
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(response()).await, 1, 0).gzip_content_type(true);
+        assert!(!stream.is_decompressing());
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert!(stream.is_decompressing());
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [2, 3]);
+        assert!(stream.is_decompressing());
+
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response()).await, 1, 0);
+        stream.connect().await.unwrap();
+        assert!(!stream.is_decompressing());
+        // The gzip bytes are parsed as json.
+        let res: Vec<u32> = stream.filter_map(|res| async { res.ok() }).collect().await;
+        assert_ne!(res, [1, 2, 3]);
+
+        let mut response = chunked(b"[1]", 8);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "identity".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert!(!stream.is_decompressing());
+
+        // Announced as gzip, but sent as is.
+        let mut response = chunked(b"[1, 2]", 2);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(response).await, 1, 0).decode_fallback(true);
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2]);
+        assert!(!stream.is_decompressing());
+    }
+
+    #[tokio::test]
+    async fn with_seed() {
+        /// Tags every element with its position, counted by the seed factory.
+        struct Indexed(usize);
+        impl<'de> serde::de::DeserializeSeed<'de> for Indexed {
+            type Value = (usize, String);
+            fn deserialize<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                let name = serde::Deserialize::deserialize(deserializer)?;
+                Ok((self.0, name))
+            }
+        }
+        let mut next = 0;
+        let stream: JsonStream<(usize, String)> = JsonStream::new(
+            respond(chunked(br#"["Paris", "Lyon", 3, "Nice"]"#, 3)).await,
+            1,
+            0,
+        )
+        .with_seed(move || {
+            next += 1;
+            Indexed(next - 1)
+        });
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res[0].as_ref().unwrap(), &(0, "Paris".to_string()));
+        assert_eq!(res[1].as_ref().unwrap(), &(1, "Lyon".to_string()));
+        assert!(res[2].is_err());
+        assert_eq!(res.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn from_parts_and_body() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let (parts, body) = respond(response).await.await.unwrap().into_parts();
+        let mut stream: JsonStream<City> = JsonStream::from_parts_and_body(parts, body, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap().country, "AD");
+        assert_eq!(
+            stream.response_parts().unwrap().status,
+            hyper::StatusCode::OK
+        );
+        assert_eq!(stream.count().await, 11);
+
+        let mut response = chunked(b"bad request", 4);
+        *response.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        let (parts, body) = respond(response).await.await.unwrap().into_parts();
+        let mut stream: JsonStream<City> = JsonStream::from_parts_and_body(parts, body, 1, 0);
+        match stream.next().await {
+            Some(Err(JsonStreamError::ApiError(status, body))) => {
+                assert_eq!(status, hyper::StatusCode::BAD_REQUEST);
+                assert_eq!(body, "bad request");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let json = br#"[{"a": 1}, {"a": 22}, {"a": 333}]"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let mut response = chunked(&gzipped, 8);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let mut stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(response).await, 1, 0);
+        assert_eq!(stream.stats(), Default::default());
+        while let Some(item) = stream.next().await {
+            item.unwrap();
+        }
+        let stats = stream.stats();
+        assert_eq!(stats.elements, 3);
+        assert_eq!(stats.wire_bytes, gzipped.len() as u64);
+        assert_eq!(stats.decoded_bytes, json.len() as u64);
+        assert_eq!(stats.min_element_size, 8);
+        assert_eq!(stats.max_element_size, 11);
+        assert_eq!(stats.avg_element_size(), 29.0 / 3.0);
+        assert!(stats.elapsed > std::time::Duration::ZERO);
+        assert_eq!(stream.stats().elapsed, stats.elapsed);
+        let time_to_head = stats.time_to_head.unwrap();
+        let time_to_first_byte = stats.time_to_first_byte.unwrap();
+        let time_to_first_element = stats.time_to_first_element.unwrap();
+        assert!(time_to_head <= time_to_first_byte);
+        assert!(time_to_first_byte <= time_to_first_element);
+        assert!(time_to_first_element <= stats.elapsed);
+        #[cfg(feature = "profiling")]
+        {
+            assert!(stats.decompress_ns > 0);
+            assert!(stats.scan_ns > 0);
+            assert!(stats.deserialize_ns > 0);
+            let profiled = stats.decompress_ns + stats.scan_ns + stats.deserialize_ns;
+            assert!(profiled <= stats.elapsed.as_nanos() as u64);
+        }
+        assert_eq!(stream.bytes_received(), gzipped.len() as u64);
+        assert_eq!(stream.decompressed_bytes(), json.len() as u64);
+
+        let mut stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 8)).await, 1, 0);
+        assert!(stream.next().await.is_some());
+        assert!(stream.bytes_received() > 0);
+        assert_eq!(stream.decompressed_bytes(), stream.bytes_received());
+        while stream.next().await.is_some() {}
+        assert_eq!(stream.bytes_received(), json.len() as u64);
+        assert_eq!(stream.decompressed_bytes(), json.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn buffer_pool() {
+        #[derive(Default)]
+        struct Pool {
+            buffers: Mutex<Vec<Vec<u8>>>,
+            allocations: Mutex<usize>,
+        }
+        impl BufferPool for Pool {
+            fn get(&self, capacity: usize) -> Vec<u8> {
+                self.buffers.lock().unwrap().pop().unwrap_or_else(|| {
+                    *self.allocations.lock().unwrap() += 1;
+                    Vec::with_capacity(capacity)
+                })
+            }
+            fn put(&self, buffer: Vec<u8>) {
+                assert!(buffer.is_empty());
+                self.buffers.lock().unwrap().push(buffer);
+            }
+        }
+        let pool = Arc::new(Pool::default());
+        for _ in 0..3 {
+            let stream: JsonStream<City> =
+                JsonStream::new(respond(chunked(CITIES, 64)).await, 1, 0)
+                    .with_buffer_pool(pool.clone());
+            assert_eq!(stream.count().await, 12);
+        }
+        assert_eq!(*pool.allocations.lock().unwrap(), 1);
+        let buffers = pool.buffers.lock().unwrap();
+        assert_eq!(buffers.len(), 1);
+        assert!(buffers[0].capacity() > 0);
+    }
+
+    #[tokio::test]
+    async fn abort() {
+        let mut stream: JsonStream<City> =
+            JsonStream::new(respond(chunked(CITIES, 64)).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap().country, "AD");
+        stream.abort("stalled".to_string());
+        assert!(!stream.is_terminated());
+        match stream.next().await {
+            Some(Err(JsonStreamError::MalformedJson(reason))) => assert_eq!(reason, "stalled"),
+            other => panic!(
+                "unexpected {:?}",
+                other.map(|res| res.map(|city| city.name))
+            ),
+        }
+        assert!(stream.is_terminated());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn count_elements_without_deserializing() {
+        // The elements are not valid json, but their boundaries are.
+        let response = chunked(br#"{"items": [{"a": 1}, {b}, [1 2], nul, "x"]}"#, 5);
+        assert_eq!(count_elements(respond(response).await, 2).await.unwrap(), 5);
+        assert_eq!(
+            count_elements(respond(chunked(CITIES, 64)).await, 1)
+                .await
+                .unwrap(),
+            12
+        );
+        let response = chunked(b"[1, 2]], 3]", 5);
+        assert!(count_elements(respond(response).await, 1).await.is_err());
+        // An empty element fails like when it is deserialized.
+        for json in [&b"[1,,2]"[..], b"[1,]", b"[1, ]", b"[ ,1]"] {
+            for chunk_size in [1, 5, 64] {
+                let response = chunked(json, chunk_size);
+                match count_elements(respond(response).await, 1).await {
+                    Err(JsonStreamError::MalformedElement(msg, _)) => {
+                        assert!(msg.starts_with("EOF while parsing a value"), "{}", msg)
+                    }
+                    res => panic!("{:?}", res),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_error_body() {
+        let page = "<html>oops</html>".repeat(100);
+        let mut response = chunked(page.as_bytes(), 256);
+        *response.status_mut() = hyper::StatusCode::BAD_GATEWAY;
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(response).await, 1, 0).stream_error_body();
+        assert!(stream.take_error_body().is_none());
+        match stream.next().await {
+            Some(Err(JsonStreamError::ApiError(status, body))) => {
+                assert_eq!(status, hyper::StatusCode::BAD_GATEWAY);
+                assert!(body.is_empty());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(stream.next().await.is_none());
+        let chunks: Vec<_> = stream
+            .take_error_body()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), page.as_bytes());
+        assert!(stream.take_error_body().is_none());
+    }
+
+    #[tokio::test]
+    async fn error_headers() {
+        let mut response = chunked(b"not found", 4);
+        *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+        response
+            .headers_mut()
+            .insert("X-Request-Id", "abc-123".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        assert!(stream.last_error_headers().is_none());
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::ApiError(
+                hyper::StatusCode::NOT_FOUND,
+                _
+            )))
+        ));
+        let headers = stream.last_error_headers().unwrap();
+        assert_eq!(headers["X-Request-Id"], "abc-123");
+
+        // Still recorded when the body of the error cannot be collected.
+        let mut response = chunked(b"not \xff found", 4);
+        *response.status_mut() = hyper::StatusCode::BAD_GATEWAY;
+        response
+            .headers_mut()
+            .insert("X-Request-Id", "def-456".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::MalformedJson(_)))
+        ));
+        let headers = stream.last_error_headers().unwrap();
+        assert_eq!(headers["X-Request-Id"], "def-456");
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let mut response = chunked(b"slow down", 4);
+        *response.status_mut() = hyper::StatusCode::TOO_MANY_REQUESTS;
+        response
+            .headers_mut()
+            .insert("Retry-After", "7".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        match stream.next().await {
+            Some(Err(JsonStreamError::RateLimited { retry_after, body })) => {
+                assert_eq!(retry_after, Some(std::time::Duration::from_secs(7)));
+                assert_eq!(body, "slow down");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_targets() {
+        let json = br#"{"count": 2, "cities": [{"country": "FR", "name": "Paris"}]}"#;
+        let targets = [
+            (
+                StreamTarget::ObjectValues,
+                vec![json!(2), json!([{"country": "FR", "name": "Paris"}])],
+            ),
+            (
+                StreamTarget::NestedArray(2),
+                vec![json!({"country": "FR", "name": "Paris"})],
+            ),
+            (
+                StreamTarget::SingleValue,
+                vec![serde_json::from_slice(json).unwrap()],
+            ),
+        ];
+        for (target, expected) in targets {
+            let stream: JsonStream<serde_json::Value> =
+                JsonStream::with_target(respond(chunked(json, 7)).await, target, 0);
+            let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+            assert_eq!(res, expected, "{:?}", target);
+        }
+
+        // No brace to skip, the document is the value.
+        assert_eq!(StreamTarget::from_level(0), StreamTarget::SingleValue);
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 7)).await, 0, 0);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(
+            res,
+            [serde_json::from_slice::<serde_json::Value>(json).unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_content() {
+        let mut response = chunked(b"[1, 2, 3]", 4);
+        *response.status_mut() = hyper::StatusCode::PARTIAL_CONTENT;
+        response
+            .headers_mut()
+            .insert("Content-Range", "bytes 0-8/20".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        assert_eq!(stream.content_range(), None);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        let range = stream.content_range().unwrap();
+        assert_eq!(
+            (range.start, range.end, range.complete_length),
+            (0, 8, Some(20))
+        );
+        assert_eq!(stream.count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn partial_gzip_content() {
+        let gzip = |json: &[u8]| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(json).unwrap();
+            encoder.finish().unwrap()
+        };
+        // A body of two gzip members, as appended by a log shipper.
+        let (first, second) = (gzip(b"1\n2\n"), gzip(b"3\n4\n"));
+        let total = first.len() + second.len();
+        let response = |body: &[u8], start: usize| {
+            let mut response = chunked(body, 3);
+            *response.status_mut() = hyper::StatusCode::PARTIAL_CONTENT;
+            let end = start + body.len() - 1;
+            let range = format!("bytes {}-{}/{}", start, end, total);
+            let headers = response.headers_mut();
+            headers.insert("Content-Range", range.parse().unwrap());
+            headers.insert("Content-Encoding", "gzip".parse().unwrap());
+            response
+        };
+        // The range starts in the middle of the first member.
+        let mut stream: JsonStream<u32> =
+            JsonStream::ndjson(respond(response(&first[5..], 5)).await, 0).decode_fallback(true);
+        match stream.next().await {
+            Some(Err(JsonStreamError::EncodingError(msg))) => {
+                assert!(
+                    msg.contains("cannot resume gzip at arbitrary offset 5"),
+                    "{}",
+                    msg
+                )
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The range starts with the second member.
+        let stream: JsonStream<u32> =
+            JsonStream::ndjson(respond(response(&second, first.len())).await, 0);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [3, 4]);
+    }
+
+    #[cfg(feature = "charset")]
+    #[tokio::test]
+    async fn gzip_latin1() {
+        let json = b"[\"Z\xfcrich\", \"S\xe3o Paulo\", \"K\xf8benhavn\"]";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 7);
+        let headers = response.headers_mut();
+        headers.insert("Content-Encoding", "gzip".parse().unwrap());
+        headers.insert(
+            "Content-Type",
+            "application/json; charset=ISO-8859-1".parse().unwrap(),
+        );
+        let stream: JsonStream<String> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<String> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, ["Zürich", "São Paulo", "København"]);
+    }
+
+    #[cfg(feature = "json5")]
+    #[tokio::test]
+    async fn json5() {
+        let json = b"{cities: [ // The largest ones, [sic].\n\
+            {country: 'JP', name: 'Tokyo', lat: '35.69'},\n\
+            /* ] */ {country: 'IN', name: \"Delhi\", lat: '28.61',},\n], }";
+        let stream: JsonStream<City> =
+            JsonStream::new(respond(chunked(json, 5)).await, 2, 0).json5(true);
+        let res: Vec<String> = stream.map(|city| city.unwrap().name).collect().await;
+        assert_eq!(res, ["Tokyo", "Delhi"]);
+
+        // The seeds and the checks read json5 too.
+        let json = b"[{a: 1, b: 'x'}, /* two */ {b: [1,], a: 0x2,},]";
+        let stream = JsonStream::new(respond(chunked(json, 5)).await, 1, 0)
+            .json5(true)
+            .project(&["a"]);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(
+            res,
+            [json!({"a": 1}), json!({"a": 2})].map(|v| v.as_object().unwrap().clone())
+        );
+        let json = b"[{a: 1}, {a: 2, 'a': 3}]";
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 5)).await, 1, 0)
+                .json5(true)
+                .reject_duplicate_keys(true);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res[0].as_ref().unwrap()["a"], 1);
+        assert!(
+            matches!(res[1], Err(JsonStreamError::MalformedElement(ref msg, _)) if msg.contains("duplicate key 'a'"))
+        );
+    }
+
+    #[tokio::test]
+    async fn spill_oversized() {
+        let spilled = Arc::new(Mutex::new(Vec::new()));
+        let sink = spilled.clone();
+        let json = br#"[{"a": 1}, {"blob": "aGVsbG8gd29ybGQsIGhlbGxvIHdvcmxk"}, {"a": 2}]"#;
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 5)).await, 1, 0)
+                .spill_oversized(16, move |bytes, last| {
+                    sink.lock().unwrap().push((bytes.to_vec(), last))
+                });
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [json!({"a": 1}), json!({"a": 2})]);
+        let spilled = spilled.lock().unwrap();
+        assert!(spilled.len() > 1);
+        assert!(spilled.iter().rev().skip(1).all(|(_, last)| !last));
+        assert!(spilled.last().unwrap().1);
+        assert_eq!(
+            spilled
+                .iter()
+                .flat_map(|(bytes, _)| bytes.clone())
+                .collect::<Vec<u8>>(),
+            br#"{"blob": "aGVsbG8gd29ybGQsIGhlbGxvIHdvcmxk"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn not_modified() {
+        let mut response = chunked(b"", 1);
+        *response.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+        response
+            .headers_mut()
+            .insert("ETag", "\"33a64df5\"".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        assert!(!stream.was_not_modified());
+        assert!(stream.next().await.is_none());
+        assert!(stream.was_not_modified());
+        assert!(stream.is_terminated());
+
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1]", 1)).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert!(!stream.was_not_modified());
+    }
+
+    #[tokio::test]
+    async fn connect() {
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3]", 2)).await, 1, 0);
+        assert!(stream.response_parts().is_none());
+        stream.connect().await.unwrap();
+        assert_eq!(stream.stats().wire_bytes, 0);
+        let parts = stream.response_parts().unwrap();
+        assert_eq!(parts.status, hyper::StatusCode::OK);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3]);
+
+        // Once the head has arrived, connecting again does not consume an element.
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3]", 2)).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        stream.connect().await.unwrap();
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [2, 3]);
+
+        let mut response = chunked(b"not found", 4);
+        *response.status_mut() = hyper::StatusCode::NOT_FOUND;
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        stream.connect().await.unwrap();
+        assert_eq!(
+            stream.response_parts().unwrap().status,
+            hyper::StatusCode::NOT_FOUND
+        );
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::ApiError(_, _)))
+        ));
+        assert_eq!(
+            stream.response_parts().unwrap().status,
+            hyper::StatusCode::NOT_FOUND
+        );
+
+        // An error setting up the decoder is left for `next`.
+        let response = || {
+            let mut response = chunked(b"\x1f\x8b\x08\xff\x00", 2);
+            response
+                .headers_mut()
+                .insert("Content-Encoding", "gzip".parse().unwrap());
+            response
+        };
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(response()).await, 1, 0).window_bits(3);
+        stream.connect().await.unwrap();
+        assert!(stream.response_parts().is_some());
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::EncodingError(_)))
+        ));
+        assert!(stream.next().await.is_none());
+
+        // So is a corrupt gzip header, found once the body is read.
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response()).await, 1, 0);
+        stream.connect().await.unwrap();
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(JsonStreamError::EncodingError(msg))) if msg.contains("unknown header flags")
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn headers() {
+        let mut response = chunked(b"[1, 2, 3]", 2);
+        response
+            .headers_mut()
+            .insert("X-RateLimit-Remaining", "42".parse().unwrap());
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        let headers = stream.headers().await.unwrap();
+        assert_eq!(headers["X-RateLimit-Remaining"], "42");
+        assert_eq!(stream.stats().wire_bytes, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        // Asked again mid-stream, the headers do not cost an element.
+        let headers = stream.headers().await.unwrap();
+        assert_eq!(headers["X-RateLimit-Remaining"], "42");
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [2, 3]);
+
+        for stream_error_body in [false, true] {
+            let mut response = chunked(b"slow down", 4);
+            *response.status_mut() = hyper::StatusCode::SERVICE_UNAVAILABLE;
+            response
+                .headers_mut()
+                .insert("X-RateLimit-Remaining", "0".parse().unwrap());
+            let mut stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+            if stream_error_body {
+                stream = stream.stream_error_body();
+            }
+            let headers = stream.headers().await.unwrap();
+            assert_eq!(headers["X-RateLimit-Remaining"], "0");
+            assert!(matches!(
+                stream.next().await,
+                Some(Err(JsonStreamError::ApiError(_, _)))
+            ));
+            assert!(stream.next().await.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn http_version() {
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1]", 1)).await, 1, 0);
+        assert_eq!(stream.http_version(), None);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(stream.http_version(), Some(hyper::Version::HTTP_2));
+    }
+
+    #[cfg(feature = "roundtrip")]
+    #[tokio::test]
+    async fn strict_roundtrip() {
+        #[derive(Debug, serde::Deserialize, serde::Serialize)]
+        struct Item {
+            a: u32,
+        }
+        let json = br#"[{"a": 1}, {"a": 2, "b": 3}, {"a": 4}]"#;
+        let stream: JsonStream<Item> = JsonStream::new(respond(chunked(json, 7)).await, 1, 0)
+            .strict_roundtrip()
+            .lenient(true);
+        let res: Vec<_> = stream
+            .map(|item| match item {
+                Ok(item) => Ok(item.a),
+                Err(JsonStreamError::InvalidElement(err, raw)) => {
+                    assert!(err.to_string().contains(r#"it became {"a":2}"#));
+                    Err(raw)
+                }
+                Err(err) => panic!("{}", err),
+            })
+            .collect()
+            .await;
+        assert_eq!(res, [Ok(1), Err(br#"{"a": 2, "b": 3}"#.to_vec()), Ok(4)]);
+
+        // The elements made by the seeds are checked.
+        struct WithoutB;
+        impl<'de> serde::de::DeserializeSeed<'de> for WithoutB {
+            type Value = serde_json::Value;
+            fn deserialize<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                let mut value: serde_json::Value = serde::Deserialize::deserialize(deserializer)?;
+                value.as_object_mut().map(|object| object.remove("b"));
+                Ok(value)
+            }
+        }
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0)
+                .with_seed(|| WithoutB)
+                .strict_roundtrip()
+                .lenient(true);
+        let res: Vec<_> = stream.map(|item| item.is_ok()).collect().await;
+        assert_eq!(res, [true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn reject_duplicate_keys() {
+        let json = br#"[{"a": 1, "b": {"c": 2}}, {"a": 3, "b": [{"c": 4, "c": 5}]}]"#;
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0).reject_duplicate_keys(true);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].as_ref().unwrap()["b"]["c"], 2);
+        match res[1] {
+            Err(JsonStreamError::MalformedElement(ref msg, _)) => {
+                assert!(msg.contains("duplicate key 'c'"), "{}", msg)
+            }
+            ref res => panic!("{:?}", res),
+        }
+
+        // The keys are compared unescaped.
+        let json = br#"[{"a": 1, "\u0061": 2}]"#;
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0).reject_duplicate_keys(true);
+        let res: Vec<_> = stream.collect().await;
+        assert!(matches!(
+            res[..],
+            [Err(JsonStreamError::MalformedElement(_, _))]
+        ));
+
+        let json = br#"[{"a": 1, "a": 2}]"#;
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0);
+        let res: Vec<_> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [serde_json::json!({"a": 2})]);
+    }
+
+    #[tokio::test]
+    async fn project() {
+        let json = br#"[{"id": 1, "name": "a", "tags": ["x"]}, {"name": "b", "id": 2}, {"id": 3}]"#;
+        let stream =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0).project(&["id", "name"]);
+        let res: Vec<serde_json::Value> = stream
+            .map(|item| serde_json::Value::Object(item.unwrap()))
+            .collect()
+            .await;
+        assert_eq!(
+            res,
+            [
+                serde_json::json!({"id": 1, "name": "a"}),
+                serde_json::json!({"id": 2, "name": "b"}),
+                serde_json::json!({"id": 3}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_content_length() {
+        let stream = |body: &[u8], encoding: &'static str, announced: &'static str| {
+            let response = chunked(body, 3);
+            async move {
+                let (mut parts, body) = respond(response).await.await.unwrap().into_parts();
+                // A body cut by a proxy.
+                for (name, value) in [
+                    ("Content-Length", announced),
+                    ("Content-Type", "application/x-ndjson"),
+                    ("Content-Encoding", encoding),
+                ] {
+                    parts.headers.insert(name, value.parse().unwrap());
+                }
+                JsonStream::<u32>::from_parts_and_body(parts, body, 0, 0)
+                    .auto_framing(true)
+                    .verify_content_length(true)
+            }
+        };
+        let res: Vec<u32> = stream(b"1\n2\n", "identity", "4")
+            .await
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(res, [1, 2]);
+        let res: Vec<_> = stream(b"1\n2\n", "identity", "9").await.collect().await;
+        match res[..] {
+            [Ok(1), Ok(2), Err(JsonStreamError::MalformedJson(ref msg))] => {
+                assert_eq!(msg, "short read: got 4 of 9 bytes")
+            }
+            ref res => panic!("{:?}", res),
+        }
+
+        // The header does not describe a compressed body.
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"1\n2\n").unwrap();
+        let res: Vec<u32> = stream(&encoder.finish().unwrap(), "gzip", "4")
+            .await
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(res, [1, 2]);
+
+        // Without the header, there is nothing to check.
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2]", 3)).await, 1, 0).verify_content_length(true);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn raw_element() {
+        let json = br#"[1, {"a": 2}, 3, "four"]"#;
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 5)).await, 1, 0).lenient(true);
+        let (mut res, mut dead_letters) = (Vec::new(), Vec::new());
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(item) => res.push(item),
+                Err(err) => dead_letters.push(err.raw_element().unwrap().to_vec()),
+            }
+        }
+        assert_eq!(res, [1, 3]);
+        assert_eq!(
+            dead_letters,
+            [br#"{"a": 2}"#.to_vec(), br#""four""#.to_vec()]
+        );
+
+        // Without lenient mode, the element ending the stream is kept too.
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(json, 5)).await, 1, 0);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 2);
+        assert!(matches!(
+            res[1],
+            Err(JsonStreamError::MalformedElement(_, _))
+        ));
+        let raw = res[1].as_ref().unwrap_err().raw_element();
+        assert_eq!(raw, Some(&br#"{"a": 2}"#[..]));
+
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1, x]", 5)).await, 1, 0);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res[1].as_ref().unwrap_err().raw_element(), Some(&b"x"[..]));
+
+        // Not about a single element.
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"[1] ]", 5)).await, 1, 0);
+        let res: Vec<_> = stream.collect().await;
+        assert!(res[1].as_ref().unwrap_err().raw_element().is_none());
+    }
+
+    #[tokio::test]
+    async fn large_numbers() {
+        let json = b"[18446744073709551616, 340282366920938463463374607431768211455]";
+        let stream: JsonStream<u128> = JsonStream::new(respond(chunked(json, 7)).await, 1, 0);
+        let res: Vec<u128> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [u64::MAX as u128 + 1, u128::MAX]);
+
+        let stream: JsonStream<serde_json::Number> =
+            JsonStream::new(respond(chunked(json, 7)).await, 1, 0);
+        let res: Vec<String> = stream.map(|item| item.unwrap().to_string()).collect().await;
+        #[cfg(feature = "arbitrary-precision")]
+        assert_eq!(
+            res,
+            [
+                "18446744073709551616",
+                "340282366920938463463374607431768211455"
+            ]
+        );
+        // Without the feature, the numbers are rounded to a float.
+        #[cfg(not(feature = "arbitrary-precision"))]
+        assert_eq!(res, ["1.8446744073709552e+19", "3.402823669209385e+38"]);
+    }
+
+    #[tokio::test]
+    async fn decode_fallback() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let deflated = encoder.finish().unwrap();
+        for body in [&deflated[..], CITIES] {
+            let response = || {
+                let mut response = chunked(body, 16);
+                response
+                    .headers_mut()
+                    .insert("Content-Encoding", "gzip".parse().unwrap());
+                response
+            };
+            let stream: JsonStream<City> =
+                JsonStream::new(respond(response()).await, 1, 0).decode_fallback(true);
+            let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+            assert_eq!(res.len(), 12);
+
+            let mut stream: JsonStream<City> = JsonStream::new(respond(response()).await, 1, 0);
+            assert!(matches!(
+                stream.next().await,
+                Some(Err(JsonStreamError::EncodingError(_)))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn multipart() {
+        let mut body = b"--sep\r\nContent-Type: text/plain\r\n\r\n[\"not\", \"this\"]\r\n\
+            --sep\r\nContent-Type: application/json\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(CITIES);
+        body.extend_from_slice(b"\r\n--sep--\r\n");
+        let response = |body: &[u8]| {
+            let mut response = chunked(body, 7);
+            response.headers_mut().insert(
+                "Content-Type",
+                "multipart/mixed; boundary=sep".parse().unwrap(),
+            );
+            response
+        };
+        let stream: JsonStream<City> =
+            JsonStream::new(respond(response(&body)).await, 1, 0).multipart(true);
+        let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res.len(), 12);
+
+        let body = b"--sep\r\nContent-Type: text/plain\r\n\r\n[1]\r\n--sep--\r\n";
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(response(body)).await, 1, 0).multipart(true);
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn pause_signal() {
+        let signal = PauseSignal::new();
+        signal.pause();
+        let mut stream: JsonStream<City> =
+            JsonStream::new(respond(chunked(CITIES, 64)).await, 1, 0)
+                .with_pause_signal(signal.clone());
+        // Nothing is read while paused.
+        let poll = futures_util::poll!(stream.next());
+        assert!(poll.is_pending());
+        assert_eq!(stream.stats().wire_bytes, 0);
+
+        let resumed = signal.clone();
+        let resume = tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            resumed.resume();
+        });
+        // Woken by the resume, it would hang otherwise.
+        assert_eq!(stream.next().await.unwrap().unwrap().country, "AD");
+        resume.await.unwrap();
+        signal.pause();
+        // Only the elements already received are yielded.
+        let mut received = 1;
+        while let Poll::Ready(Some(city)) = futures_util::poll!(stream.next()) {
+            city.unwrap();
+            received += 1;
+        }
+        assert!(received < 12);
+        signal.resume();
+        assert_eq!(stream.count().await + received, 12);
+    }
+
+    #[tokio::test]
+    async fn base64_field() {
+        fn base64(bytes: &[u8]) -> String {
+            const ALPHABET: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+            let mut text = String::new();
+            for group in bytes.chunks(3) {
+                let n = group
+                    .iter()
+                    .enumerate()
+                    .fold(0, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+                for i in 0..4 {
+                    text.push(match i <= group.len() {
+                        true => ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char,
+                        false => '=',
+                    });
+                }
+            }
+            text
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        for embedded in [&gzipped[..], CITIES] {
+            // Escaped line breaks every 76 characters, like MIME.
+            let text = base64(embedded).into_bytes();
+            let lines: Vec<&str> = text
+                .chunks(76)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .collect();
+            let body = format!(
+                r#"{{"status": "ok", "data": {{"count": 12, "payload": "{}"}}, "next": null}}"#,
+                lines.join("\\r\\n")
+            );
+            let stream: JsonStream<City> =
+                JsonStream::new(respond(chunked(body.as_bytes(), 16)).await, 1, 0)
+                    .base64_field("data.payload");
+            let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+            assert_eq!(res.len(), 12);
+        }
+
+        let body = br#"{"data": {"payload": 12}}"#;
+        let mut stream: JsonStream<City> =
+            JsonStream::new(respond(chunked(body, 16)).await, 1, 0).base64_field("data.payload");
+        assert!(stream.next().await.unwrap().is_err());
+        let body = br#"{"data": {"payload": "H4sI*"}}"#;
+        let mut stream: JsonStream<City> =
+            JsonStream::new(respond(chunked(body, 16)).await, 1, 0).base64_field("data.payload");
+        assert!(stream.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn max_elements_per_poll() {
+        let body = b"[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]";
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(body, body.len())).await, 1, 0)
+                .max_elements_per_poll(3);
+        // The whole body is buffered once the first element is found.
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        let mut polls = Vec::new();
+        for _ in 0..8 {
+            polls.push(match futures_util::poll!(stream.next()) {
+                Poll::Ready(item) => item.unwrap().unwrap(),
+                Poll::Pending => 0,
+            });
+        }
+        assert_eq!(polls, [2, 3, 0, 4, 5, 6, 0, 7]);
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(body, 4)).await, 1, 0).max_elements_per_poll(2);
+        let res = crate::stream::watchdog::assert_wakes_on_pending(stream).await;
+        assert_eq!(res.into_iter().map(Result::unwrap).sum::<u32>(), 55);
+    }
+
+    #[test]
+    fn parse_array() {
+        let res: Vec<u32> = super::parse_array(b" \n[1,\n 2 , 3 ]\n", 1).unwrap();
+        assert_eq!(res, [1, 2, 3]);
+        let json = br#"{"a": {"b": [[1], {"c": [2]}]}}"#;
+        let res: Vec<serde_json::Value> = super::parse_array(json, 3).unwrap();
+        assert_eq!(res, [serde_json::json!([1]), serde_json::json!({"c": [2]})]);
+        let res: Vec<City> = super::parse_array(CITIES, 1).unwrap();
+        assert_eq!(res.len(), 12);
+        assert!(super::parse_array::<u32>(b"[1, 2", 1).is_err());
+        assert!(super::parse_array::<u32>(br#"{"a": [1, 2]"#, 2).is_err());
+        assert!(super::parse_array::<u32>(b"[1, \"a\"]", 1).is_err());
+        assert!(super::parse_array::<u32>(b"", 1).is_err());
+        assert!(super::parse_array::<u32>(b"[]", 1).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn multi_array() {
+        let json = b"[1, 2]\n [3, 4, 5][6]";
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 3)).await, 1, 0).multi_array(true);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3, 4, 5, 6]);
+
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(json, 3)).await, 1, 0);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3, 4, 5, 6]);
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 3)).await, 1, 0).multi_array(false);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 3);
+        assert!(matches!(res[2], Err(JsonStreamError::MalformedJson(_))));
+
+        // By default, what is not an array between the documents is skipped.
+        for json in [&b"[1][2]"[..], b"[1] 7 [2]", b"[1]x[2]"] {
+            let stream: JsonStream<u32> = JsonStream::new(respond(chunked(json, 3)).await, 1, 0);
+            let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+            assert_eq!(res, [1, 2]);
+
+            let stream: JsonStream<u32> =
+                JsonStream::new(respond(chunked(json, 3)).await, 1, 0).multi_array(false);
+            let res: Vec<_> = stream.collect().await;
+            assert!(
+                matches!(res[..], [Ok(1), Err(JsonStreamError::MalformedJson(ref msg))]
+                if msg.starts_with("Trailing characters after the json document"))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn skip_garbage() {
+        let json = b"\0[1, 2, 3]\n \x01garbage";
+        let stream: JsonStream<u32> = JsonStream::new(respond(chunked(json, 3)).await, 1, 0)
+            .multi_array(false)
+            .skip_garbage(16);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3]);
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(json, 3)).await, 1, 0).multi_array(false);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 4);
+        assert!(matches!(res[3], Err(JsonStreamError::MalformedJson(_))));
+    }
+
+    #[tokio::test]
+    async fn empty_body() {
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(chunked(b"", 1)).await, 1, 0);
+        match stream.next().await {
+            Some(Err(JsonStreamError::MalformedJson(msg))) => {
+                assert_eq!(msg, "empty body, expected JSON array")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(stream.next().await.is_none());
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"", 1)).await, 1, 0).allow_empty_body(true);
+        assert_eq!(stream.count().await, 0);
+        let stream: JsonStream<u32> = JsonStream::ndjson(respond(chunked(b"", 1)).await, 0);
+        assert_eq!(stream.count().await, 0);
+        let stream: JsonStream<u32> = JsonStream::ndjson_flatten(respond(chunked(b"", 1)).await, 0);
+        assert_eq!(stream.count().await, 0);
+
+        for (target, expected) in [
+            (StreamTarget::SingleValue, "JSON value"),
+            (StreamTarget::ObjectValues, "JSON object"),
+            (StreamTarget::NestedArray(2), "JSON array"),
+        ] {
+            let mut stream: JsonStream<u32> =
+                JsonStream::with_target(respond(chunked(b"", 1)).await, target, 0);
+            match stream.next().await {
+                Some(Err(JsonStreamError::MalformedJson(msg))) => {
+                    assert_eq!(msg, format!("empty body, expected {}", expected))
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+            let stream: JsonStream<u32> =
+                JsonStream::with_target(respond(chunked(b"", 1)).await, target, 0)
+                    .allow_empty_body(true);
+            assert_eq!(stream.count().await, 0);
+        }
+        let mut stream: JsonStream<u32> = JsonStream::json_seq(respond(chunked(b"", 1)).await, 0);
+        match stream.next().await {
+            Some(Err(JsonStreamError::MalformedJson(msg))) => {
+                assert_eq!(msg, "empty body, expected JSON text sequence")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Empty whatever its encoding, rather than a truncated compressed body.
+        let gzipped = || {
+            let mut response = chunked(b"", 1);
+            response
+                .headers_mut()
+                .insert("Content-Encoding", "gzip".parse().unwrap());
+            response
+        };
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(gzipped()).await, 1, 0).allow_empty_body(true);
+        assert_eq!(stream.count().await, 0);
+        let mut stream: JsonStream<u32> = JsonStream::new(respond(gzipped()).await, 1, 0);
+        match stream.next().await {
+            Some(Err(JsonStreamError::MalformedJson(msg))) => {
+                assert_eq!(msg, "empty body, expected JSON array")
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "timeout")]
+    #[tokio::test]
+    async fn timeout_with_clock() {
+        use crate::stream::clock::{Clock, Sleep};
+        use crate::util::test_server::respond_stalled;
+        use std::task::{Poll, Waker};
+        use std::time::{Duration, Instant};
+
+        /// A clock that only moves when told to.
+        struct ManualClock {
+            start: Instant,
+            elapsed: Mutex<Duration>,
+            sleepers: Mutex<Vec<Waker>>,
+        }
+        impl ManualClock {
+            fn advance(&self, duration: Duration) {
+                *self.elapsed.lock().unwrap() += duration;
+                self.sleepers
+                    .lock()
+                    .unwrap()
+                    .drain(..)
+                    .for_each(Waker::wake);
+            }
+        }
+        impl Clock for Arc<ManualClock> {
+            fn now(&self) -> Instant {
+                self.start + *self.elapsed.lock().unwrap()
+            }
+            fn sleep_until(&self, deadline: Instant) -> Sleep {
+                let clock = self.clone();
+                Box::pin(std::future::poll_fn(move |cx| {
+                    if clock.now() >= deadline {
+                        return Poll::Ready(());
+                    }
+                    clock.sleepers.lock().unwrap().push(cx.waker().clone());
+                    Poll::Pending
+                }))
+            }
+        }
+
+        let clock = Arc::new(ManualClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+            sleepers: Mutex::new(Vec::new()),
+        });
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond_stalled(chunked(b"[1, 2, 3", 4)).await, 1, 0)
+                .timeout(Duration::from_secs(10))
+                .with_clock(Arc::new(clock.clone()));
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+        // Wait for the stalled stream to start its countdown.
+        clock.sleepers.lock().unwrap().clear();
+        let next = tokio::spawn(async move { stream.next().await });
+        while clock.sleepers.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_secs(9));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!next.is_finished());
+        clock.advance(Duration::from_secs(1));
+        match next.await.unwrap() {
+            Some(Err(JsonStreamError::IOError(err))) => {
+                assert_eq!(err.kind(), std::io::ErrorKind::TimedOut)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "timeout")]
+    #[tokio::test(start_paused = true)]
+    async fn on_idle() {
+        use crate::util::test_server::respond_stalled;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let beats = Arc::new(AtomicUsize::new(0));
+        let counter = beats.clone();
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond_stalled(chunked(b"[1, 2, 3", 4)).await, 1, 0)
+                .timeout(Duration::from_secs(17))
+                .on_idle(Duration::from_secs(5), move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 2);
+        assert_eq!(beats.load(Ordering::SeqCst), 0);
+        // Beats after 5, 10 and 15 seconds without progress, then times out.
+        assert!(stream.next().await.unwrap().is_err());
+        assert_eq!(beats.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn send_with_non_send_element() {
+        #[derive(serde::Deserialize)]
+        struct NotSend {
+            #[serde(skip)]
+            _marker: std::marker::PhantomData<*const ()>,
+        }
+        let stream: JsonStream<NotSend> =
+            JsonStream::new(respond(chunked(b"[{}, {}, {}]", 4)).await, 1, 0);
+        let count = tokio::spawn(stream.count()).await.unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn assert_sorted_by() {
+        use std::cmp::Ordering;
+
+        let json = br#"[{"id": 1}, {"id": 3}, {"id": 3}, {"id": 2}, {"id": 4}]"#;
+        let stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json, 4)).await, 1, 0);
+        let stream = stream.assert_sorted_by(|item| item["id"].as_u64(), Ordering::Less);
+        let res: Vec<_> = stream.collect().await;
+        assert_eq!(res.len(), 4);
+        assert!(res[..3].iter().all(Result::is_ok));
+        assert!(matches!(
+            &res[3],
+            Err(JsonStreamError::MalformedJson(reason)) if reason == "sort order violated at index 3"
+        ));
+
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[3, 2, 2, 1]", 4)).await, 1, 0);
+        let stream = stream.assert_sorted_by(|&item| item, Ordering::Greater);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [3, 2, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn tiny_frames_yield() {
+        use std::future::poll_fn;
+        use std::task::Poll;
+        use std::time::Duration;
+
+        let json = format!("[\"{}\", 1]", "a".repeat(4000));
+        let mut stream: JsonStream<serde_json::Value> =
+            JsonStream::new(respond(chunked(json.as_bytes(), 1)).await, 1, 0);
+        // Let the whole body be buffered, so that every frame is ready right away.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut res = Vec::new();
+        let mut polls = 0;
+        loop {
+            let before = stream.stats().wire_bytes;
+            let poll = poll_fn(|cx| Poll::Ready(stream.poll_next_unpin(cx))).await;
+            polls += 1;
+            assert!(stream.stats().wire_bytes - before <= super::POLL_BUDGET as u64);
+            match poll {
+                Poll::Ready(Some(item)) => res.push(item.unwrap()),
+                Poll::Ready(None) => break,
+                Poll::Pending => tokio::task::yield_now().await,
+            }
+        }
+        assert_eq!(res, [json!("a".repeat(4000)), json!(1)]);
+        assert!(polls > json.len() / super::POLL_BUDGET);
+    }
+
+    #[tokio::test]
+    async fn at_path_with_meta() {
+        let json =
+            br#"{"total": 3, "tags": [7], "page": {"next": "b"}, "items": [1, 2, 3], "after": 1}"#;
+        let mut stream: JsonStream<u32> = JsonStream::at_path_with_meta(
+            respond(chunked(json, 5)).await,
+            "items",
+            &["total", "page.next"],
+        );
+        assert!(stream.meta().is_none());
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        let meta = stream.meta().unwrap();
+        assert_eq!(
+            (&meta["total"], &meta["page.next"]),
+            (&json!(3), &json!("b"))
+        );
+        assert_eq!(stream.count().await, 2);
+
+        let stream: JsonStream<u32> = JsonStream::at_path_with_meta(
+            respond(chunked(b"{\"total\": 0}", 5)).await,
+            "items",
+            &["total"],
+        );
+        let res: Vec<_> = stream.collect().await;
+        assert!(matches!(&res[..], [Err(JsonStreamError::MalformedJson(_))]));
+    }
+
+    #[tokio::test]
+    async fn reset() {
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3]", 4)).await, 1, 0);
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3]);
+        assert!(stream.is_terminated());
+
+        stream.reset(respond(chunked(b"[4, 5]", 4)).await);
+        assert!(!stream.is_terminated());
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [4, 5]);
+        assert_eq!(stream.stats().elements, 2);
+
+        /// Doubles the elements.
+        struct Double;
+        impl<'de> serde::de::DeserializeSeed<'de> for Double {
+            type Value = u32;
+            fn deserialize<D: serde::Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<u32, D::Error> {
+                <u32 as serde::Deserialize>::deserialize(deserializer).map(|n| n * 2)
+            }
+        }
+        // The seed and the handlers are kept for the next response.
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let on_skip = skipped.clone();
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, x, 2]", 4)).await, 1, 0)
+                .with_seed(|| Double)
+                .robust(move |range| on_skip.lock().unwrap().push(range));
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [2, 4]);
+        stream.reset(respond(chunked(b"[3, y]", 4)).await);
+        let res: Vec<u32> = stream.by_ref().map(Result::unwrap).collect().await;
+        assert_eq!(res, [6]);
+        assert_eq!(skipped.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn gzip_truncated() {
+        let json: String = format!(
+            "[{}]",
+            (0..2000)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let mut response = chunked(&gzipped[..gzipped.len() / 2], 64);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let stream: JsonStream<u32> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<_> = stream.collect().await;
+        let (last, elements) = res.split_last().unwrap();
+        assert!(elements.len() > 1);
+        assert!(elements.iter().all(Result::is_ok));
+        assert!(matches!(
+            last,
+            Err(JsonStreamError::EncodingError(msg)) if msg.starts_with("gzip inflate error: ")
+        ));
+    }
+
+    #[tokio::test]
+    async fn gzip_tiny_frames() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let mut response = chunked(&gzipped, 1);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let stream: JsonStream<City> = JsonStream::new(respond(response).await, 1, 0);
+        let res: Vec<City> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res.len(), 12);
+    }
+
+    #[cfg(all(debug_assertions, feature = "tracing", feature = "timeout"))]
+    #[tokio::test]
+    async fn idle_polls() {
+        use crate::util::test_server::respond_stalled;
+        use futures_util::task::noop_waker_ref;
+        use std::task::Context;
+
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond_stalled(chunked(b"[1, 2", 1)).await, 1, 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        // Wait for the rest of the frames, the body then stalls.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let mut cx = Context::from_waker(noop_waker_ref());
+        while stream.poll_next_unpin(&mut cx).is_ready() {}
+        for _ in 0..super::IDLE_POLLS_WARNING {
+            assert!(stream.poll_next_unpin(&mut cx).is_pending());
+        }
+        assert!(stream.idle_polls >= super::IDLE_POLLS_WARNING);
+    }
+
+    #[tokio::test]
+    async fn auto_framing() {
+        let response = |body: &[u8], content_type: &str| {
+            let mut response = chunked(body, 3);
+            response
+                .headers_mut()
+                .insert("Content-Type", content_type.parse().unwrap());
+            response
+        };
+        let bodies: [(&[u8], &str); 4] = [
+            (b"[1, 2, 3]", "application/json; charset=utf-8"),
+            (b"1\n2\n3\n", "application/x-ndjson"),
+            (b"1\n2\n3", "application/jsonl"),
+            (b"\x1e1\n\x1e2\n\x1e3\n", "application/json-seq"),
+        ];
+        for (body, content_type) in bodies {
+            let stream: JsonStream<u32> =
+                JsonStream::new(respond(response(body, content_type)).await, 1, 0)
+                    .auto_framing(true);
+            let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+            assert_eq!(res, [1, 2, 3], "{}", content_type);
+        }
+        // An array served to a stream over lines.
+        let stream: JsonStream<u32> =
+            JsonStream::ndjson(respond(response(b"[1, 2, 3]", "application/json")).await, 0)
+                .auto_framing(true);
+        let res: Vec<u32> = stream.map(Result::unwrap).collect().await;
+        assert_eq!(res, [1, 2, 3]);
+        // Without the flag, the lines are parsed as an array.
+        let stream: JsonStream<u32> = JsonStream::new(
+            respond(response(
+                b"{\"a\": 1}\n{\"a\": 2}\n",
+                "application/x-ndjson",
+            ))
+            .await,
+            1,
+            0,
+        );
+        let res: Vec<_> = stream.collect().await;
+        match &res[..] {
+            // The members of the first line are taken for the elements of an array.
+            [Err(JsonStreamError::MalformedElement(msg, _))] => assert!(
+                msg.starts_with(r#"invalid type: string "a", expected u32"#),
+                "{}",
+                msg
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn debug() {
+        let mut stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3]", 4)).await, 1, 16);
+        assert_eq!(
+            format!("{:?}", stream),
+            "JsonStream(connecting, elements: 0, bytes: 0, level: 1, capacity: 16)"
+        );
+        assert_eq!(stream.next().await.unwrap().unwrap(), 1);
+        assert_eq!(
+            format!("{:?}", stream),
+            "JsonStream(receiving, elements: 1, bytes: 4, encoding: None, level: 1, capacity: 16)"
+        );
+        while stream.next().await.is_some() {}
+        assert!(format!("{:?}", stream).starts_with("JsonStream(done, elements: 3, bytes: 9,"));
+    }
+}
@@ -0,0 +1,428 @@
+use crate::stream::budget::Budget;
+use crate::stream::decoder::budget_exhausted;
+use crate::util::JsonStreamError;
+use std::sync::Arc;
+
+/// The magic bytes starting a `compress` stream.
+const MAGIC: [u8; 2] = [0x1f, 0x9d];
+/// The mask of the maximum code width in the flags of the header.
+const BITS_MASK: u8 = 0x1f;
+/// The flag of the header set when the stream may reset its table with `CLEAR`.
+const BLOCK_MODE: u8 = 0x80;
+/// The width of the codes at the start of the stream and after a `CLEAR`.
+const INIT_BITS: u32 = 9;
+/// The widest codes supported, as in `compress`.
+const MAX_BITS: u32 = 16;
+/// The code resetting the table in block mode.
+const CLEAR: u16 = 256;
+/// The first code defined by the stream, the table only holds the codes from there.
+const FIRST: usize = 256;
+
+/// A streaming decoder of the LZW of the legacy Unix `compress` tool, the
+/// `Content-Encoding: compress` of HTTP.
+pub(crate) struct Lzw {
+    /// The three bytes of the header, until they are all received.
+    header: Vec<u8>,
+    max_bits: u32,
+    block_mode: bool,
+    /// The current width of the codes.
+    n_bits: u32,
+    /// The next free entry of the table.
+    free_ent: u32,
+    /// The code of the string each code extends, and the byte extending it, from `FIRST`.
+    prefix: Vec<u16>,
+    suffix: Vec<u8>,
+    /// The previous code, none at the start of the stream and after a `CLEAR`.
+    old_code: Option<u16>,
+    /// The first byte of the string of the previous code.
+    fin_char: u8,
+    /// Received bits not consumed yet, the oldest in the lowest bits.
+    bits: u32,
+    n_in_bits: u32,
+    /// The number of bits consumed since the codes switched to their current width.
+    group_bits: u64,
+    /// The bits to drop to reach the start of the next group of codes.
+    skip_bits: u64,
+    /// The string of a code, last byte first.
+    stack: Vec<u8>,
+    budget: Option<Arc<Budget>>,
+    /// The bytes reserved from the budget for the table and the stack.
+    reserved: usize,
+}
+
+impl Lzw {
+    pub(crate) fn new(budget: Option<Arc<Budget>>) -> Self {
+        Lzw {
+            header: Vec::with_capacity(3),
+            max_bits: MAX_BITS,
+            block_mode: false,
+            n_bits: INIT_BITS,
+            free_ent: 256,
+            prefix: Vec::new(),
+            suffix: Vec::new(),
+            old_code: None,
+            fin_char: 0,
+            bits: 0,
+            n_in_bits: 0,
+            group_bits: 0,
+            skip_bits: 0,
+            stack: Vec::new(),
+            budget,
+            reserved: 0,
+        }
+    }
+    /// Decode `input`, handing the decoded bytes to `output`. A code split between two
+    /// calls is kept until the next one.
+    pub(crate) fn decode(
+        &mut self,
+        input: &[u8],
+        mut output: impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        for &byte in input {
+            if self.header.len() < 3 {
+                self.read_header(byte)?;
+                continue;
+            }
+            self.bits |= (byte as u32) << self.n_in_bits;
+            self.n_in_bits += 8;
+            loop {
+                if self.skip_bits > 0 {
+                    let skipped = self.skip_bits.min(self.n_in_bits as u64) as u32;
+                    self.consume(skipped);
+                    self.skip_bits -= skipped as u64;
+                    if self.skip_bits > 0 {
+                        break;
+                    }
+                }
+                if self.n_in_bits < self.n_bits {
+                    break;
+                }
+                let code = (self.bits & ((1 << self.n_bits) - 1)) as u16;
+                self.consume(self.n_bits);
+                self.group_bits += self.n_bits as u64;
+                self.next_code(code, &mut output)?;
+            }
+        }
+        Ok(())
+    }
+    fn read_header(&mut self, byte: u8) -> Result<(), JsonStreamError> {
+        self.header.push(byte);
+        if self.header.len() == 2 && self.header != MAGIC {
+            return Err(JsonStreamError::EncodingError(
+                "Invalid compress header".to_string(),
+            ));
+        }
+        if self.header.len() == 3 {
+            self.max_bits = (byte & BITS_MASK) as u32;
+            self.block_mode = byte & BLOCK_MODE != 0;
+            if !(INIT_BITS..=MAX_BITS).contains(&self.max_bits) {
+                return Err(JsonStreamError::EncodingError(format!(
+                    "Unsupported compress code width: {}",
+                    self.max_bits
+                )));
+            }
+            self.free_ent = if self.block_mode { 257 } else { 256 };
+        }
+        Ok(())
+    }
+    fn consume(&mut self, n: u32) {
+        self.bits = self.bits.checked_shr(n).unwrap_or(0);
+        self.n_in_bits -= n;
+    }
+    /// Skip to the start of the next group of codes. `compress` writes the codes in groups
+    /// of 8, and pads the current group when it changes their width.
+    fn realign(&mut self) {
+        let group = self.n_bits as u64 * 8;
+        self.skip_bits = (group - self.group_bits % group) % group;
+        self.group_bits = 0;
+    }
+    fn next_code(
+        &mut self,
+        code: u16,
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        if code == CLEAR && self.block_mode {
+            self.realign();
+            self.n_bits = INIT_BITS;
+            self.free_ent = 257;
+            self.old_code = None;
+            return Ok(());
+        }
+        let old_code = match self.old_code {
+            Some(old_code) => old_code,
+            None => {
+                if code > 255 {
+                    return Err(invalid_data());
+                }
+                self.old_code = Some(code);
+                self.fin_char = code as u8;
+                output(&[self.fin_char]);
+                return Ok(());
+            }
+        };
+        self.stack.clear();
+        let mut cur = code;
+        if code as u32 >= self.free_ent {
+            // The code being defined, made of the previous string and its first byte.
+            if code as u32 > self.free_ent {
+                return Err(invalid_data());
+            }
+            self.stack.push(self.fin_char);
+            cur = old_code;
+        }
+        while cur > 255 {
+            self.stack.push(self.suffix[cur as usize - FIRST]);
+            cur = self.prefix[cur as usize - FIRST];
+        }
+        self.fin_char = cur as u8;
+        self.stack.push(self.fin_char);
+        self.stack.reverse();
+        output(&self.stack);
+        if self.free_ent < 1 << self.max_bits {
+            let entry = self.free_ent as usize - FIRST;
+            if entry < self.prefix.len() {
+                self.prefix[entry] = old_code;
+                self.suffix[entry] = self.fin_char;
+            } else {
+                self.grow()?;
+                // In block mode, the code of `CLEAR` is never defined.
+                self.prefix.resize(entry, 0);
+                self.suffix.resize(entry, 0);
+                self.prefix.push(old_code);
+                self.suffix.push(self.fin_char);
+            }
+            self.free_ent += 1;
+        }
+        self.old_code = Some(code);
+        if self.free_ent > (1 << self.n_bits) - 1 && self.n_bits < self.max_bits {
+            self.realign();
+            self.n_bits += 1;
+        }
+        Ok(())
+    }
+    /// Make room for more codes in the table, reserving the memory from the budget first.
+    /// The table grows as the stream defines codes rather than being allocated for the
+    /// widest codes up front, and the stack grows with it as a string is at most one byte
+    /// longer than the number of codes.
+    fn grow(&mut self) -> Result<(), JsonStreamError> {
+        if self.prefix.len() < self.prefix.capacity() {
+            return Ok(());
+        }
+        let len = self.prefix.len();
+        let additional = len.max(FIRST).min((1 << self.max_bits) - FIRST - len);
+        // Two bytes of prefix, one of suffix and one of stack per code.
+        let size = additional * 4;
+        if let Some(ref budget) = self.budget {
+            if !budget.acquire(size) {
+                return Err(budget_exhausted());
+            }
+        }
+        self.reserved += size;
+        self.prefix.reserve_exact(additional);
+        self.suffix.reserve_exact(additional);
+        let stack = len + additional + 2;
+        self.stack
+            .reserve_exact(stack.saturating_sub(self.stack.len()));
+        Ok(())
+    }
+}
+
+impl Drop for Lzw {
+    fn drop(&mut self) {
+        if let Some(ref budget) = self.budget {
+            budget.release(self.reserved);
+        }
+    }
+}
+
+fn invalid_data() -> JsonStreamError {
+    JsonStreamError::EncodingError("Invalid compress data".to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Lzw, BLOCK_MODE, CLEAR, INIT_BITS, MAGIC};
+    use crate::stream::budget::DecompressionBudget;
+    use std::collections::HashMap;
+
+    /// `items()` compressed with 10 bit codes the way ncompress does it: unlike `compress`
+    /// below, it keeps using a full table and only clears it once the compression ratio
+    /// drops.
+    const ITEMS_Z: &[u8] = include_bytes!("../../tests/fixtures/items.json.Z");
+
+    fn items() -> Vec<u8> {
+        let mut seed = 1u32;
+        let items: String = (0..900)
+            .map(|i| {
+                if i < 300 {
+                    format!("{{\"id\": {}, \"name\": \"item\"}}, ", i % 10)
+                } else {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    format!("{{\"id\": {}, \"hash\": \"{:08x}\"}}, ", i, seed)
+                }
+            })
+            .collect();
+        format!("[{}{{}}]", items).into_bytes()
+    }
+
+    /// Writes the codes like `compress`, in groups of 8 codes padded when their width
+    /// changes.
+    struct Codes {
+        out: Vec<u8>,
+        bits: u64,
+        n_in_bits: u32,
+        group_bits: u64,
+        n_bits: u32,
+    }
+    impl Codes {
+        fn write(&mut self, code: u16) {
+            self.bits |= (code as u64) << self.n_in_bits;
+            self.n_in_bits += self.n_bits;
+            self.group_bits += self.n_bits as u64;
+            self.flush_bytes();
+        }
+        fn pad_group(&mut self) {
+            let group = self.n_bits as u64 * 8;
+            for _ in 0..(group - self.group_bits % group) % group {
+                self.n_in_bits += 1;
+                self.flush_bytes();
+            }
+            self.group_bits = 0;
+        }
+        fn flush_bytes(&mut self) {
+            while self.n_in_bits >= 8 {
+                self.out.push(self.bits as u8);
+                self.bits >>= 8;
+                self.n_in_bits -= 8;
+            }
+        }
+    }
+
+    /// Compress `input` like the `compress` tool, clearing the table once it is full. The
+    /// output decompresses with `gzip -d`.
+    pub(crate) fn compress(input: &[u8], max_bits: u32) -> Vec<u8> {
+        let mut codes = Codes {
+            out: vec![MAGIC[0], MAGIC[1], max_bits as u8 | BLOCK_MODE],
+            bits: 0,
+            n_in_bits: 0,
+            group_bits: 0,
+            n_bits: INIT_BITS,
+        };
+        let mut table = HashMap::new();
+        let mut free_ent = 257u32;
+        let output = |codes: &mut Codes, code: u16, free_ent: u32, clear: bool| {
+            codes.write(code);
+            if clear {
+                codes.pad_group();
+                codes.n_bits = INIT_BITS;
+            } else if free_ent > (1 << codes.n_bits) - 1 && codes.n_bits < max_bits {
+                codes.pad_group();
+                codes.n_bits += 1;
+            }
+        };
+        let mut bytes = input.iter();
+        let mut ent = match bytes.next() {
+            Some(&byte) => byte as u16,
+            None => return codes.out,
+        };
+        for &byte in bytes {
+            if let Some(&code) = table.get(&(ent, byte)) {
+                ent = code;
+                continue;
+            }
+            output(&mut codes, ent, free_ent, false);
+            if free_ent < 1 << max_bits {
+                table.insert((ent, byte), free_ent as u16);
+                free_ent += 1;
+            } else {
+                table.clear();
+                free_ent = 257;
+                output(&mut codes, CLEAR, free_ent, true);
+            }
+            ent = byte as u16;
+        }
+        output(&mut codes, ent, free_ent, false);
+        if codes.n_in_bits > 0 {
+            codes.out.push(codes.bits as u8);
+        }
+        codes.out
+    }
+
+    fn decompress(input: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+        let mut lzw = Lzw::new(None);
+        let mut out = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            lzw.decode(chunk, |bytes| out.extend_from_slice(bytes))
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let json: Vec<u8> = (0..5000)
+            .map(|i| format!("{{\"id\": {}, \"name\": \"item {}\"}}, ", i, i % 77))
+            .collect::<String>()
+            .into_bytes();
+        for max_bits in [12, 16] {
+            let compressed = compress(&json, max_bits);
+            assert!(compressed.len() < json.len());
+            for chunk_size in [1, 7, 4096] {
+                assert_eq!(decompress(&compressed, chunk_size).unwrap(), json);
+            }
+        }
+        assert_eq!(decompress(&compress(b"", 16), 1).unwrap(), b"");
+        assert_eq!(
+            decompress(&compress(b"abababab", 16), 1).unwrap(),
+            b"abababab"
+        );
+    }
+
+    #[test]
+    fn ncompress() {
+        for chunk_size in [1, 7, 4096] {
+            assert_eq!(decompress(ITEMS_Z, chunk_size).unwrap(), items());
+        }
+    }
+
+    #[test]
+    fn budget() {
+        let decode = |budget: &DecompressionBudget, input: &[u8]| {
+            let mut lzw = Lzw::new(Some(budget.inner.clone()));
+            let res = lzw.decode(input, |_| {});
+            (res, budget.used())
+        };
+        // The 10 bit table holds 768 codes.
+        let budget = DecompressionBudget::new(3072);
+        let (res, used) = decode(&budget, ITEMS_Z);
+        assert!(res.is_ok());
+        assert_eq!(used, 3072);
+        assert_eq!(budget.used(), 0);
+
+        let budget = DecompressionBudget::new(3071);
+        let (res, _) = decode(&budget, ITEMS_Z);
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "The decompression budget is exhausted"
+        );
+        assert_eq!(budget.used(), 0);
+
+        // The table grows with the codes defined, up to 4 bytes each.
+        let compressed = compress(&items(), 16);
+        let budget = DecompressionBudget::new(1 << 20);
+        let (res, used) = decode(&budget, &compressed);
+        assert!(res.is_ok());
+        assert!(used > 3072 && used < 4 * (1 << 16));
+        let budget = DecompressionBudget::new(used - 1);
+        assert!(decode(&budget, &compressed).0.is_err());
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(decompress(b"\x1f\x8b\x08", 1).is_err());
+        assert!(decompress(b"\x1f\x9d\x91", 1).is_err());
+        // The first code must be a byte.
+        assert!(decompress(b"\x1f\x9d\x90\xff\x01", 1).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use futures_core::stream::{FusedStream, Stream};
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A stream yielding the elements of several `JsonStream`s one after the other, like the
+/// range-partitioned parts of a large dataset, while connecting the next ones ahead.
+///
+/// All the elements of the first stream are yielded, then all the elements of the second
+/// one, and so on: the order of the streams is kept whatever the order their responses
+/// arrive in. An error of a stream is yielded in its place, and the merged stream goes on
+/// with the next one once it ends.
+///
+/// Up to `concurrency` streams are in flight at once: while the current one is read, the
+/// next ones are driven until the head of their response is received, so that there is no
+/// stall at the boundaries. Their bodies are only read once they are current, which bounds
+/// the memory used to what the connections buffer.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct MergedJsonStream<T> {
+    partitions: VecDeque<Partition<T>>,
+    concurrency: usize,
+}
+
+struct Partition<T> {
+    stream: JsonStream<T>,
+    /// The head of the response was received, or the stream failed before.
+    connected: bool,
+    /// The failure of the stream while connecting ahead, yielded once it is current.
+    error: Option<JsonStreamError>,
+}
+
+impl<T> MergedJsonStream<T> {
+    /// Merge `streams` in order, with up to `concurrency` of them in flight at once.
+    ///
+    /// Panics if `concurrency` is 0.
+    pub fn new(streams: impl IntoIterator<Item = JsonStream<T>>, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "the concurrency must be positive");
+        MergedJsonStream {
+            partitions: streams
+                .into_iter()
+                .map(|stream| Partition {
+                    stream,
+                    connected: false,
+                    error: None,
+                })
+                .collect(),
+            concurrency,
+        }
+    }
+}
+
+// The compiler adds a T: Unpin bound, but a `JsonStream<T>` is Unpin.
+impl<T> Unpin for MergedJsonStream<T> {}
+
+impl<T: DeserializeOwned> Stream for MergedJsonStream<T> {
+    type Item = Result<T, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        let this = self.get_mut();
+        loop {
+            for next in this.partitions.iter_mut().take(this.concurrency).skip(1) {
+                if next.connected {
+                    continue;
+                }
+                match next.stream.poll_head(cx) {
+                    Poll::Pending => {}
+                    Poll::Ready(Some(Err(err))) => {
+                        next.error = Some(err);
+                        next.connected = true;
+                    }
+                    Poll::Ready(_) => next.connected = true,
+                }
+            }
+            let current = match this.partitions.front_mut() {
+                Some(current) => current,
+                None => return Poll::Ready(None),
+            };
+            if let Some(err) = current.error.take() {
+                return Poll::Ready(Some(Err(err)));
+            }
+            match Pin::new(&mut current.stream).poll_next(cx) {
+                Poll::Ready(None) => {
+                    this.partitions.pop_front();
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FusedStream for MergedJsonStream<T> {
+    fn is_terminated(&self) -> bool {
+        self.partitions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MergedJsonStream;
+    use crate::stream::json_stream::JsonStream;
+    use crate::util::test_server::{chunked, respond};
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn in_order() {
+        for concurrency in [1, 2, 5] {
+            let mut streams: Vec<JsonStream<u32>> = Vec::new();
+            for body in [&b"[1, 2, 3]"[..], b"[]", b"[4]", b"[5, 6]"] {
+                streams.push(JsonStream::new(respond(chunked(body, 2)).await, 1, 0));
+            }
+            let merged = MergedJsonStream::new(streams, concurrency);
+            let res: Vec<u32> = merged.map(Result::unwrap).collect().await;
+            assert_eq!(res, [1, 2, 3, 4, 5, 6]);
+        }
+    }
+
+    #[cfg(feature = "timeout")]
+    #[tokio::test]
+    async fn connects_ahead() {
+        use crate::util::test_server::respond_stalled;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let log = states.clone();
+        let first: JsonStream<u32> =
+            JsonStream::new(respond_stalled(chunked(b"[1, 2", 2)).await, 1, 0);
+        let second: JsonStream<u32> = JsonStream::new(respond(chunked(b"[3]", 2)).await, 1, 0)
+            .on_state_change(move |_, new| log.lock().unwrap().push(new));
+        let mut merged = MergedJsonStream::new([first, second], 2);
+        assert_eq!(merged.next().await.unwrap().unwrap(), 1);
+        // The first stream never ends, but the second one is connected meanwhile.
+        let next = tokio::time::timeout(Duration::from_millis(200), merged.next()).await;
+        assert!(next.is_err());
+        assert_eq!(*states.lock().unwrap(), ["Collecting"]);
+    }
+}
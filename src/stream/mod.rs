@@ -1,18 +1,35 @@
+pub mod array_decoder;
+pub mod batched;
+pub(crate) mod body;
+pub mod budget;
+#[cfg(feature = "charset")]
+pub(crate) mod charset;
+#[cfg(feature = "timeout")]
+pub mod clock;
+pub mod content_range;
+#[cfg(feature = "tokio")]
+pub mod decoded_body;
+pub mod decoder;
+pub mod dedup;
+pub mod embedded;
 pub mod encoding;
-#[allow(clippy::unnecessary_cast)]
+pub mod error_body;
+pub mod footer;
 pub mod json_stream;
+#[cfg(feature = "compress")]
+pub(crate) mod lzw;
+pub mod merged;
+pub(crate) mod multipart;
 pub mod partial_json;
-
-pub trait ZType<T> {
-    fn z_type(self) -> T;
-}
-impl ZType<u32> for u64 {
-    fn z_type(self) -> u32 {
-        self as u32
-    }
-}
-impl ZType<u64> for u64 {
-    fn z_type(self) -> u64 {
-        self
-    }
-}
+pub(crate) mod path;
+pub mod pause;
+pub mod pool;
+pub mod projection;
+pub mod raw;
+pub(crate) mod scanner;
+pub mod stats;
+pub mod target;
+#[cfg(feature = "test-util")]
+pub mod throttle;
+#[cfg(any(test, feature = "test-util"))]
+pub mod watchdog;
@@ -0,0 +1,198 @@
+use http::HeaderMap;
+
+use crate::util::JsonStreamError;
+
+enum Section {
+    /// Before the first delimiter.
+    Preamble,
+    /// The end of the line of a delimiter, telling whether it is the last one.
+    Delimiter,
+    /// The headers of a part.
+    Headers { json: bool },
+    /// The body of a part, passed on for the json one.
+    Body { json: bool },
+    /// The json part is over, or the last delimiter was found.
+    Epilogue,
+}
+
+/// Extracts the body of the `application/json` part of a `multipart/*` body, like
+/// `multipart/mixed`, skipping the other parts. Only the first json part is extracted.
+pub(crate) struct Multipart {
+    /// The delimiter of the parts, `\r\n--` and the boundary.
+    delimiter: Vec<u8>,
+    section: Section,
+    /// The bytes received but not handled yet, too few to tell whether they start a
+    /// delimiter, or an incomplete line.
+    pending: Vec<u8>,
+    found: bool,
+}
+
+impl Multipart {
+    /// The extractor of a body whose `Content-Type` is `multipart/*` with a boundary.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(http::header::CONTENT_TYPE)?.to_str().ok()?;
+        let mut params = content_type.split(';');
+        let media_type = params.next()?.trim();
+        if !media_type
+            .get(..10)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("multipart/"))
+        {
+            return None;
+        }
+        let boundary = params.find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("boundary")
+                .then(|| value.trim().trim_matches('"'))
+        })?;
+        Some(Multipart {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            section: Section::Preamble,
+            // The first delimiter may start the body, without a line break before it.
+            pending: b"\r\n".to_vec(),
+            found: false,
+        })
+    }
+    /// Handle `input`, handing the bytes of the body of the json part to `output`.
+    pub(crate) fn feed(
+        &mut self,
+        input: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), JsonStreamError> {
+        if let Section::Epilogue = self.section {
+            return Ok(());
+        }
+        self.pending.extend_from_slice(input);
+        let mut pos = 0;
+        loop {
+            let rest = &self.pending[pos..];
+            match self.section {
+                Section::Preamble | Section::Body { .. } => {
+                    let json = matches!(self.section, Section::Body { json: true });
+                    match find(rest, &self.delimiter) {
+                        Some(at) => {
+                            if json {
+                                output(&rest[..at]);
+                                self.section = Section::Epilogue;
+                                break;
+                            }
+                            pos += at + self.delimiter.len();
+                            self.section = Section::Delimiter;
+                        }
+                        None => {
+                            // Keep what may be the start of a delimiter.
+                            let keep = rest.len().min(self.delimiter.len() - 1);
+                            if json {
+                                output(&rest[..rest.len() - keep]);
+                            }
+                            pos += rest.len() - keep;
+                            break;
+                        }
+                    }
+                }
+                Section::Delimiter | Section::Headers { .. } => {
+                    let Some(end) = find(rest, b"\r\n") else {
+                        break;
+                    };
+                    let line = &rest[..end];
+                    pos += end + 2;
+                    self.section = match self.section {
+                        Section::Delimiter if line.starts_with(b"--") => Section::Epilogue,
+                        Section::Delimiter => Section::Headers { json: false },
+                        Section::Headers { json } if line.is_empty() => {
+                            self.found |= json;
+                            Section::Body { json }
+                        }
+                        Section::Headers { json } => Section::Headers {
+                            json: json || is_json_content_type(line),
+                        },
+                        _ => unreachable!(),
+                    };
+                }
+                Section::Epilogue => break,
+            }
+        }
+        match self.section {
+            Section::Epilogue => self.pending = Vec::new(),
+            _ => {
+                self.pending.drain(..pos);
+            }
+        }
+        Ok(())
+    }
+    /// Check at the end of the body that the json part was found whole.
+    pub(crate) fn finish(&self) -> Result<(), JsonStreamError> {
+        match self.section {
+            Section::Epilogue if self.found => Ok(()),
+            Section::Body { json: true } => Err(JsonStreamError::json(
+                "unexpected end of the multipart body in the application/json part".to_string(),
+            )),
+            _ => Err(JsonStreamError::json(
+                "no application/json part in the multipart body".to_string(),
+            )),
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Whether the header `line` is a `Content-Type` of json.
+fn is_json_content_type(line: &[u8]) -> bool {
+    let Ok(line) = std::str::from_utf8(line) else {
+        return false;
+    };
+    let Some((name, value)) = line.split_once(':') else {
+        return false;
+    };
+    let media_type = value.split(';').next().unwrap_or_default().trim();
+    name.trim().eq_ignore_ascii_case("content-type")
+        && (media_type.eq_ignore_ascii_case("application/json")
+            || media_type.to_ascii_lowercase().ends_with("+json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Multipart;
+
+    fn extract(body: &[u8], chunk_size: usize) -> Result<Vec<u8>, String> {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            "Content-Type",
+            "multipart/mixed; boundary=\"sep\"".parse().unwrap(),
+        );
+        let mut multipart = Multipart::from_headers(&headers).unwrap();
+        let mut out = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            multipart
+                .feed(chunk, &mut |bytes| out.extend_from_slice(bytes))
+                .map_err(|err| err.to_string())?;
+        }
+        multipart.finish().map_err(|err| err.to_string())?;
+        Ok(out)
+    }
+
+    #[test]
+    fn json_part() {
+        let body = b"preamble\r\n--sep\r\nContent-Type: text/plain\r\n\r\nhello\r\n--sep \r\n\
+            content-type: application/json; charset=utf-8\r\nX-Id: 2\r\n\r\n[1, 2,\r\n 3]\r\n\
+            --sep--\r\nepilogue";
+        for chunk_size in [1, 2, 5, body.len()] {
+            assert_eq!(extract(body, chunk_size).unwrap(), b"[1, 2,\r\n 3]");
+        }
+        let body = b"--sep\r\nContent-Type: application/json\r\n\r\n[1]\r\n--sep--";
+        assert_eq!(extract(body, 3).unwrap(), b"[1]");
+    }
+
+    #[test]
+    fn missing_json_part() {
+        let body = b"--sep\r\nContent-Type: text/plain\r\n\r\nhello\r\n--sep--\r\n";
+        assert!(extract(body, 4).is_err());
+        let body = b"--sep\r\nContent-Type: application/json\r\n\r\n[1, 2";
+        assert!(extract(body, 4).is_err());
+        assert!(Multipart::from_headers(&http::HeaderMap::new()).is_none());
+    }
+}
@@ -1,106 +1,1387 @@
-use serde::de::DeserializeOwned;
-use std::collections::VecDeque;
+use serde::de::value::UnitDeserializer;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IgnoredAny};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::iter;
 use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "profiling")]
+use std::time::{Duration, Instant};
 
-use serde_json::{from_reader, from_slice};
+use hyper::body::Bytes;
+use serde_json::de::{IoRead, SliceRead};
+use serde_json::Deserializer;
 use std::io::{Cursor, Read};
 
+use crate::stream::path::{JsonPath, PathTracker};
+use crate::stream::pool::BufferPool;
+use crate::stream::scanner::{Lexeme, Strings};
 use crate::util::JsonStreamError;
 
+/// Callback receiving the byte ranges skipped by the robust scanning mode. The offsets
+/// are relative to the start of the decoded json document.
+pub type SkipHandler = Box<dyn FnMut(Range<u64>) + Send>;
+
+/// Callback receiving the raw bytes of the oversized elements, chunk by chunk. The flag is
+/// set on the last chunk of an element.
+pub type SpillHandler = Box<dyn FnMut(&[u8], bool) + Send>;
+
+/// Deserializes an element from its raw bytes with a seed made for it. The flags lift the
+/// recursion limit of `serde_json`, and tell that the element is json5. It is shared by
+/// the parsers of the successive bodies of a stream.
+pub(crate) type SeedFn<T> =
+    Arc<Mutex<dyn FnMut(&[u8], bool, bool) -> serde_json::Result<T> + Send>>;
+
+/// Lock a function shared with the parsers, which a panic in it does not make unusable.
+pub(crate) fn lock<F: ?Sized>(shared: &Mutex<F>) -> MutexGuard<'_, F> {
+    shared.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Checks the raw bytes of an element, the flag telling that the element is json5.
+type RawCheck = fn(&[u8], bool) -> serde_json::Result<()>;
+
+/// Checks that an element round-trips: its deserialized value against its raw bytes. The
+/// flag tells that the element is json5.
+pub type RoundtripCheck<T> = fn(&T, &[u8], bool) -> serde_json::Result<()>;
+
+/// How the elements are delimited in the json document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// The elements of an array, found after `level` opening braces.
+    Array,
+    /// Newline delimited json values.
+    Lines,
+    /// Json text sequences (RFC 7464), where every value is preceded by a record
+    /// separator.
+    JsonSeq,
+    /// Newline delimited json arrays, whose elements are streamed one after the other.
+    FlattenedLines,
+    /// The values of the members of an object, found after `level` opening braces.
+    ObjectValues,
+    /// The whole document as a single value.
+    Single,
+}
+
+impl Framing {
+    /// Detect the framing from the `Content-Type` header of a response: newline delimited
+    /// json for `application/x-ndjson` and `application/jsonl`, json text sequences for
+    /// `application/json-seq`, and an array for `application/json` and the `+json` types.
+    pub(crate) fn from_content_type(headers: &http::HeaderMap) -> Option<Self> {
+        let media_type = headers
+            .get(http::header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?
+            .split(';')
+            .next()?
+            .trim()
+            .to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/x-ndjson"
+            | "application/ndjson"
+            | "application/jsonl"
+            | "application/x-jsonlines"
+            | "application/jsonlines" => Some(Framing::Lines),
+            "application/json-seq" => Some(Framing::JsonSeq),
+            "application/json" => Some(Framing::Array),
+            media_type if media_type.ends_with("+json") => Some(Framing::Array),
+            _ => None,
+        }
+    }
+    /// The document a body holds with this framing, for the errors about an empty body.
+    /// None for the newline delimited framings, where an empty body holds no values.
+    pub(crate) fn expected(self) -> Option<&'static str> {
+        match self {
+            Framing::Array => Some("JSON array"),
+            Framing::JsonSeq => Some("JSON text sequence"),
+            Framing::ObjectValues => Some("JSON object"),
+            Framing::Single => Some("JSON value"),
+            Framing::Lines | Framing::FlattenedLines => None,
+        }
+    }
+}
+
+/// The ASCII record separator starting each record of a json text sequence.
+const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// How many bytes before and after the failure point are quoted in the errors.
+const CONTEXT_SIZE: usize = 32;
+
+/// Quote the bytes around `bytes[at]`, the failure point found at `offset` in the document,
+/// escaping the non-printable ones.
+fn context(bytes: &[u8], at: usize, offset: u64) -> String {
+    let at = cmp::min(at, bytes.len());
+    let start = at.saturating_sub(CONTEXT_SIZE);
+    let end = cmp::min(bytes.len(), at + CONTEXT_SIZE);
+    format!(
+        "near byte {}: \"{}\"",
+        offset,
+        bytes[start..end].escape_ascii()
+    )
+}
+
+/// The position in `bytes` of the line and column reported by `err`.
+fn error_position(bytes: &[u8], err: &serde_json::Error) -> usize {
+    if err.line() == 0 {
+        return 0;
+    }
+    let line_start: usize = bytes
+        .split(|&b| b == b'\n')
+        .take(err.line() - 1)
+        .map(|line| line.len() + 1)
+        .sum();
+    line_start + err.column().saturating_sub(1)
+}
+
+/// The error of an element that failed to deserialize, made of the first bytes of `raw`
+/// and starting at `offset` in the document. The value starts at `start`, after its key.
+fn element_error(
+    json_err: serde_json::Error,
+    raw: &[u8],
+    start: usize,
+    offset: u64,
+    lenient: bool,
+) -> JsonStreamError {
+    if lenient {
+        return JsonStreamError::InvalidElement(json_err, raw.trim_ascii().to_vec());
+    }
+    // The position of the error is relative to the value, after the key.
+    let at = start + error_position(&raw[start..], &json_err);
+    JsonStreamError::MalformedElement(
+        format!("{}, {}", json_err, context(raw, at, offset + at as u64)),
+        raw.trim_ascii().to_vec(),
+    )
+}
+
+/// The raw bytes of an element too large to be deserialized inline, taken out of the
+/// parser to be deserialized elsewhere.
+pub(crate) struct Offloaded {
+    raw: Vec<u8>,
+    /// Where the value starts in `raw`, after its key.
+    start: usize,
+    /// Where `raw` starts in the document.
+    offset: u64,
+    unbounded_depth: bool,
+    lenient: bool,
+    json5: bool,
+}
+
+impl Offloaded {
+    /// Deserialize the element, failing like the parser would have.
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn deserialize<T: DeserializeOwned>(self) -> Result<T, JsonStreamError> {
+        deserialize_raw(
+            PhantomData,
+            &self.raw[self.start..],
+            self.unbounded_depth,
+            self.json5,
+        )
+        .map_err(|json_err| {
+            element_error(json_err, &self.raw, self.start, self.offset, self.lenient)
+        })
+    }
+}
+
+/// Stand in for the value of an element in scan-only mode, which is not deserialized, made
+/// of the bytes of `raw` in order. An element of whitespace only, like the one between the
+/// commas of `[1,,2]`, still fails like when it is deserialized.
+fn scanned<T: DeserializeOwned>(raw: [&[u8]; 2]) -> serde_json::Result<T> {
+    if raw
+        .iter()
+        .all(|part| part.iter().all(u8::is_ascii_whitespace))
+    {
+        return deserialize(SliceRead::new(&raw.concat()), false);
+    }
+    T::deserialize(UnitDeserializer::new())
+}
+
+/// Deserialize a single value from `read`, like `serde_json::from_slice` does.
+fn deserialize<'de, T: DeserializeOwned, R: serde_json::de::Read<'de>>(
+    read: R,
+    unbounded_depth: bool,
+) -> serde_json::Result<T> {
+    deserialize_seed(PhantomData, read, unbounded_depth)
+}
+
+/// Deserialize a single value from `read` with `seed`.
+fn deserialize_seed<'de, S: DeserializeSeed<'de>, R: serde_json::de::Read<'de>>(
+    seed: S,
+    read: R,
+    unbounded_depth: bool,
+) -> serde_json::Result<S::Value> {
+    let mut de = Deserializer::new(read);
+    if unbounded_depth {
+        de.disable_recursion_limit();
+    }
+    let value = seed_value(seed, &mut de)?;
+    de.end()?;
+    Ok(value)
+}
+
+/// Deserialize a value from `de` with `seed`.
+fn seed_value<'de, S: DeserializeSeed<'de>, D: de::Deserializer<'de>>(
+    seed: S,
+    de: D,
+) -> Result<S::Value, D::Error> {
+    #[cfg(not(feature = "path-errors"))]
+    return seed.deserialize(de);
+    // Prefix the error with the path of the field that failed, like `address.zip`.
+    #[cfg(feature = "path-errors")]
+    {
+        let mut track = serde_path_to_error::Track::new();
+        seed.deserialize(serde_path_to_error::Deserializer::new(de, &mut track))
+            .map_err(|err| {
+                let path = track.path();
+                if path.iter().next().is_none() {
+                    return err;
+                }
+                de::Error::custom(format!("{}: {}", path, err))
+            })
+    }
+}
+
+/// Deserialize the raw bytes of an element with `seed`, as json or as json5.
+pub(crate) fn deserialize_raw<'de, S: DeserializeSeed<'de>>(
+    seed: S,
+    raw: &'de [u8],
+    unbounded_depth: bool,
+    json5: bool,
+) -> serde_json::Result<S::Value> {
+    match json5 {
+        #[cfg(feature = "json5")]
+        true => deserialize_json5(seed, raw),
+        _ => deserialize_seed(seed, SliceRead::new(raw), unbounded_depth),
+    }
+}
+
+/// Deserialize a single json5 value from `raw` with `seed`, its errors reported as the
+/// ones of `serde_json`.
+#[cfg(feature = "json5")]
+fn deserialize_json5<'de, S: DeserializeSeed<'de>>(
+    seed: S,
+    raw: &'de [u8],
+) -> serde_json::Result<S::Value> {
+    let text = std::str::from_utf8(raw).map_err(de::Error::custom)?;
+    let mut de = json5::Deserializer::from_str(text).map_err(de::Error::custom)?;
+    seed_value(seed, &mut de).map_err(de::Error::custom)
+}
+
+/// Wrap `make_seed` in a `SeedFn`, deserializing every element with a new seed.
+pub(crate) fn seed_fn<T, S, F>(mut make_seed: F) -> SeedFn<T>
+where
+    S: for<'de> DeserializeSeed<'de, Value = T>,
+    F: FnMut() -> S + Send + 'static,
+{
+    Arc::new(Mutex::new(move |bytes: &[u8], unbounded_depth, json5| {
+        deserialize_raw(make_seed(), bytes, unbounded_depth, json5)
+    }))
+}
+
+/// Visits a json value, failing on an object holding the same key twice.
+struct UniqueKeys;
+
+impl<'de> de::Visitor<'de> for UniqueKeys {
+    type Value = ();
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any json value")
+    }
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq.next_element_seed(UniqueKeys)?.is_some() {}
+        Ok(())
+    }
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut keys = HashSet::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if keys.contains(&key) {
+                return Err(de::Error::custom(format!("duplicate key '{}'", key)));
+            }
+            map.next_value_seed(UniqueKeys)?;
+            keys.insert(key);
+        }
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for UniqueKeys {
+    type Value = ();
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+/// Fail on an element holding an object with a duplicate key, which `serde_json` accepts
+/// by keeping the last value. The keys are compared once unescaped.
+fn check_unique_keys(raw: &[u8], json5: bool) -> serde_json::Result<()> {
+    deserialize_raw(UniqueKeys, raw, false, json5)
+}
+
+/// Where the scanner is in a comment of a json5 document.
+#[derive(Clone, Copy)]
+enum Comment {
+    /// After a `/`, which starts a comment with the next one.
+    Start,
+    /// In a `//` comment, up to the end of the line.
+    Line,
+    /// In a `/* */` comment.
+    Block,
+    /// After a `*` in a block comment, which ends it with a `/`.
+    BlockEnd,
+}
+
+/// The members of the object enclosing the array that follow it, being captured.
+struct Footer {
+    bytes: Vec<u8>,
+    /// The array was closed, the bytes are the ones of the footer.
+    capturing: bool,
+    /// The number of braces open within the footer.
+    depth: u32,
+}
+
 pub struct PartialJson<T> {
     buffer: VecDeque<u8>,
+    framing: Framing,
+    /// No more bytes will be pushed.
+    finished: bool,
     parens: u32,
     level: u32,
-    in_string: bool,
-    last_was_escape: bool,
+    strings: Strings,
     last_was_start: bool,
     i: usize,
+    /// Number of bytes already removed from the front of `buffer`.
+    offset: u64,
+    on_skip: Option<SkipHandler>,
+    lenient: bool,
+    /// Find the boundaries of the elements without deserializing them.
+    scan_only: bool,
+    /// Accept several top-level values one after the other.
+    multi_array: bool,
+    /// The number of unknown bytes tolerated before and after the document.
+    max_garbage: usize,
+    /// The number of unknown bytes skipped before the document.
+    leading_garbage: usize,
+    /// The number of unknown bytes skipped after the document.
+    trailing_garbage: usize,
+    /// A top-level value was closed.
+    closed: bool,
+    /// Check every deserialized element against its raw bytes.
+    checks: Vec<RawCheck>,
+    roundtrip: Option<RoundtripCheck<T>>,
+    /// Lift the recursion limit of `serde_json` for deeply nested elements.
+    unbounded_depth: bool,
+    /// Skip the comments and allow the syntax of json5, whose elements are deserialized
+    /// with the `json5` crate.
+    json5: bool,
+    /// The comment being scanned, in json5 mode.
+    comment: Option<Comment>,
+    /// Where the buffer comes from, and goes back to.
+    pool: Option<Arc<dyn BufferPool>>,
+    /// The size of the last deserialized element.
+    last_size: usize,
+    /// The time spent deserializing since the last `take_deserialize_time`.
+    #[cfg(feature = "profiling")]
+    deserialize_time: Duration,
+    /// The currently open braces, only tracked in robust mode.
+    openers: Vec<char>,
+    /// Where the elements larger than the threshold are handed to, instead of being
+    /// deserialized.
+    spill: Option<(usize, SpillHandler)>,
+    /// The bytes of the current element are being handed to the spill handler.
+    spilling: bool,
+    /// Deserializes the elements instead of `T::deserialize`.
+    seed: Option<SeedFn<T>>,
+    /// The size above which the elements are taken out of the parser instead of being
+    /// deserialized.
+    offload: Option<usize>,
+    /// The element taken out of the parser, until it is handed over.
+    offloaded: Option<Offloaded>,
+    /// Keep the raw json of the last element returned.
+    keep_raw: bool,
+    /// The raw json of the last element returned, when it is kept.
+    last_raw: Option<Vec<u8>>,
+    /// Scans the document up to the array to stream, until it is reached.
+    path: Option<PathTracker>,
+    /// The meta fields of the path, once the array is reached.
+    meta: Option<HashMap<String, serde_json::Value>>,
+    /// Captures the members of the object enclosing the array that follow it.
+    footer: Option<Footer>,
+    /// The members following the array as an object, once captured.
+    footer_bytes: Option<Vec<u8>>,
+    /// The chunk pushed by `push_bytes`, scanned in place from `chunk_pos` instead of being
+    /// copied to the buffer.
+    chunk: Bytes,
+    chunk_pos: usize,
     phantom: PhantomData<T>,
 }
 impl<T: DeserializeOwned> PartialJson<T> {
     pub fn new(size: usize, level: u32) -> Self {
+        PartialJson::with_framing(size, level, Framing::Array)
+    }
+    /// Create a parser for newline delimited json.
+    pub fn lines(size: usize) -> Self {
+        PartialJson::with_framing(size, 0, Framing::Lines)
+    }
+    /// Create a parser for json text sequences (RFC 7464).
+    pub fn json_seq(size: usize) -> Self {
+        PartialJson::with_framing(size, 0, Framing::JsonSeq)
+    }
+    /// Create a parser for newline delimited json arrays, yielding the elements of all the
+    /// arrays.
+    pub fn flattened_lines(size: usize) -> Self {
+        PartialJson::with_framing(size, 1, Framing::FlattenedLines)
+    }
+    /// Create a parser for the values of the members of an object.
+    pub fn object_values(size: usize, level: u32) -> Self {
+        PartialJson::with_framing(size, level, Framing::ObjectValues)
+    }
+    /// Create a parser for a document made of a single value.
+    pub fn single(size: usize) -> Self {
+        PartialJson::with_framing(size, 0, Framing::Single)
+    }
+    pub(crate) fn with_framing(size: usize, level: u32, framing: Framing) -> Self {
         PartialJson {
             buffer: VecDeque::with_capacity(size),
+            framing,
+            finished: false,
             parens: 0,
             level,
-            in_string: false,
-            last_was_escape: false,
+            strings: Strings::default(),
             last_was_start: false,
             i: 0,
+            offset: 0,
+            on_skip: None,
+            lenient: false,
+            scan_only: false,
+            multi_array: true,
+            max_garbage: 0,
+            leading_garbage: 0,
+            trailing_garbage: 0,
+            closed: false,
+            checks: Vec::new(),
+            roundtrip: None,
+            unbounded_depth: false,
+            json5: false,
+            comment: None,
+            pool: None,
+            last_size: 0,
+            #[cfg(feature = "profiling")]
+            deserialize_time: Duration::ZERO,
+            openers: Vec::new(),
+            spill: None,
+            spilling: false,
+            seed: None,
+            offload: None,
+            offloaded: None,
+            keep_raw: false,
+            last_raw: None,
+            path: None,
+            meta: None,
+            footer: None,
+            footer_bytes: None,
+            chunk: Bytes::new(),
+            chunk_pos: 0,
             phantom: PhantomData,
         }
     }
+    /// Switch to the robust scanning mode. Instead of failing on structurally invalid
+    /// bytes or on elements that cannot be deserialized, the scanner drops them, reports
+    /// the skipped range to `on_skip` and resynchronizes on the next element boundary.
+    pub fn robust(&mut self, on_skip: SkipHandler) {
+        self.on_skip = Some(on_skip);
+    }
+    /// Take the buffer from `pool`, with a capacity of `size` bytes. It is handed back to the
+    /// pool when the parser is dropped.
+    pub fn pool(&mut self, pool: Arc<dyn BufferPool>, size: usize) {
+        let mut buffer = pool.get(size);
+        buffer.clear();
+        self.buffer = VecDeque::from(buffer);
+        self.pool = Some(pool);
+    }
+    /// Report elements that cannot be deserialized as `JsonStreamError::InvalidElement`,
+    /// carrying the raw bytes of the element. The parser can continue after such errors.
+    pub fn lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+    /// Only find the boundaries of the elements, which are all deserialized from `()`
+    /// instead of their bytes. Meant for a `T` that ignores its input, like `IgnoredAny`.
+    pub fn scan_only(&mut self) {
+        self.scan_only = true;
+    }
+    /// Stream the elements of the arrays following the first one too, skipping anything
+    /// between them, which is the default. With `false`, anything but whitespace after the
+    /// first one is an error.
+    pub fn multi_array(&mut self, multi_array: bool) {
+        self.multi_array = multi_array;
+    }
+    /// Skip up to `max` bytes of anything before the document starts, and up to `max`
+    /// bytes other than whitespace after it ends when not `multi_array`, instead of failing
+    /// on them.
+    pub fn skip_garbage(&mut self, max: usize) {
+        self.max_garbage = max;
+    }
+    /// Fail on the elements for which `check` fails, as if they could not be deserialized.
+    /// The elements must pass the other checks too.
+    pub fn roundtrip(&mut self, check: RoundtripCheck<T>) {
+        self.roundtrip = Some(check);
+    }
+    /// Fail on the elements holding an object with a duplicate key, as if they could not
+    /// be deserialized, instead of keeping the last value like `serde_json`.
+    pub fn reject_duplicate_keys(&mut self) {
+        self.checks.push(check_unique_keys);
+    }
+    /// Deserialize the elements without the recursion limit of `serde_json`. A deeply
+    /// nested element can then overflow the stack.
+    pub fn disable_recursion_limit(&mut self, disable: bool) {
+        self.unbounded_depth = disable;
+    }
+    /// Parse a json5 document: the scanner of arrays skips the `//` and `/* */` comments,
+    /// allows single quoted strings and a trailing comma after the last element, and the
+    /// elements are deserialized with the `json5` crate, as are the seeds and the checks.
+    /// The path to the array, the footer and the keys of `Framing::ObjectValues` are still
+    /// scanned as json.
+    #[cfg(feature = "json5")]
+    pub fn json5(&mut self, json5: bool) {
+        self.json5 = json5;
+    }
+    /// Hand the raw bytes of the elements larger than `threshold` bytes to `on_chunk`
+    /// instead of deserializing them. The bytes of an element still incomplete past the
+    /// threshold are handed out as they arrive, so that it is never buffered whole.
+    pub fn spill_oversized(&mut self, threshold: usize, on_chunk: SpillHandler) {
+        self.spill = Some((threshold, on_chunk));
+    }
+    /// Deserialize the elements with `seed` instead of `T::deserialize`.
+    pub(crate) fn seed(&mut self, seed: SeedFn<T>) {
+        self.seed = Some(seed);
+    }
+    /// Take the elements larger than `threshold` bytes out of the parser instead of
+    /// deserializing them. `next` stops at such an element, which is then returned by
+    /// `take_offloaded`. Elements needing more than plain deserialization, like in robust
+    /// mode or with a seed, are still deserialized inline.
+    pub(crate) fn offload_large(&mut self, threshold: usize) {
+        self.offload = Some(threshold);
+    }
+    /// The element `next` stopped at, taken out of the parser by `offload_large`.
+    pub(crate) fn take_offloaded(&mut self) -> Option<Offloaded> {
+        self.offloaded.take()
+    }
+    /// Whether `next` stopped at an element taken out of the parser.
+    pub(crate) fn is_offloaded(&self) -> bool {
+        self.offloaded.is_some()
+    }
+    /// Whether the first `len` bytes of the buffer, holding an element, are taken out of
+    /// the parser.
+    fn is_large(&self, len: usize) -> bool {
+        match self.offload {
+            Some(threshold) => {
+                len > threshold
+                    && self.on_skip.is_none()
+                    && self.seed.is_none()
+                    && self.checks.is_empty()
+                    && self.roundtrip.is_none()
+                    && !self.scan_only
+            }
+            None => false,
+        }
+    }
+    /// Keep the raw json of the elements, taken with `take_raw`.
+    pub(crate) fn keep_raw(&mut self, keep: bool) {
+        self.keep_raw = keep;
+    }
+    /// The raw json of the last element returned, without the surrounding whitespace.
+    pub(crate) fn take_raw(&mut self) -> Option<Vec<u8>> {
+        self.last_raw.take()
+    }
+    /// Stream the array at `path` instead of the first one found after `level` opening
+    /// braces, capturing the meta fields of the path found before it.
+    pub(crate) fn at_path(&mut self, path: JsonPath) {
+        self.level = path.level();
+        self.path = Some(PathTracker::new(path));
+    }
+    /// The meta fields of the path, once the array is reached.
+    pub(crate) fn take_meta(&mut self) -> Option<HashMap<String, serde_json::Value>> {
+        self.meta.take()
+    }
+    /// Capture the members of the object enclosing the array that follow it, like the
+    /// `checksum` of `{"items": [...], "checksum": "..."}`, taken with `take_footer`.
+    pub(crate) fn capture_footer(&mut self) {
+        self.footer = Some(Footer {
+            bytes: Vec::new(),
+            capturing: false,
+            depth: 0,
+        });
+    }
+    /// The members following the array as an object, once the object enclosing the array
+    /// is closed.
+    pub(crate) fn take_footer(&mut self) -> Option<Vec<u8>> {
+        self.footer_bytes.take()
+    }
+    /// Capture the buffer up to the end of the object enclosing the array. Returns whether
+    /// the object is closed.
+    fn scan_footer(&mut self) -> Result<bool, JsonStreamError> {
+        let footer = match self.footer {
+            Some(ref mut footer) if footer.capturing => footer,
+            _ => return Ok(true),
+        };
+        while let Some(byte) = self.buffer.pop_front() {
+            self.offset += 1;
+            if self.strings.feed(byte, false) == Lexeme::Outside {
+                match byte {
+                    b'[' | b'{' => footer.depth += 1,
+                    b']' | b'}' if footer.depth == 0 => {
+                        let members = mem::take(&mut footer.bytes);
+                        let members = members.trim_ascii();
+                        let members = members.strip_prefix(b",").unwrap_or(members);
+                        let members = members.trim_ascii_start();
+                        let mut object = Vec::with_capacity(members.len() + 2);
+                        object.push(b'{');
+                        object.extend_from_slice(members);
+                        object.push(b'}');
+                        self.footer_bytes = Some(object);
+                        self.footer = None;
+                        if self.on_skip.is_some() {
+                            self.openers.pop();
+                        }
+                        self.parens -= 1;
+                        self.closed |= self.parens == 0;
+                        return Ok(true);
+                    }
+                    b']' | b'}' => footer.depth -= 1,
+                    _ => {}
+                }
+            }
+            footer.bytes.push(byte);
+        }
+        if self.finished {
+            return Err(JsonStreamError::json(
+                "Unterminated object after the array".to_string(),
+            ));
+        }
+        Ok(false)
+    }
+    /// Scan the buffer up to the array of the path. Returns whether it is reached.
+    fn scan_path(&mut self) -> Result<bool, JsonStreamError> {
+        let path = match self.path {
+            Some(ref mut path) => path,
+            None => return Ok(true),
+        };
+        while let Some(byte) = self.buffer.pop_front() {
+            self.offset += 1;
+            if path.feed(byte)? {
+                if self.on_skip.is_some() {
+                    self.openers = path.openers();
+                }
+                self.meta = Some(path.take_meta());
+                self.path = None;
+                self.parens = self.level;
+                self.last_was_start = true;
+                return Ok(true);
+            }
+        }
+        if self.finished {
+            return Err(path.not_found());
+        }
+        Ok(false)
+    }
+    /// The number of bytes spanned by the last element returned.
+    pub fn last_element_size(&self) -> usize {
+        self.last_size
+    }
+    /// The time spent deserializing the elements since the last call.
+    #[cfg(feature = "profiling")]
+    pub(crate) fn take_deserialize_time(&mut self) -> Duration {
+        mem::take(&mut self.deserialize_time)
+    }
     pub fn push(&mut self, bytes: &[u8]) {
+        self.flush_chunk();
         self.buffer.extend(bytes);
     }
-    fn next_value(&mut self) -> Result<T, JsonStreamError> {
-        let i = self.i - 1;
+    /// Append `bytes` to the document without copying them when possible. The elements
+    /// found whole in `bytes` are deserialized from it, only an element spanning several
+    /// chunks is copied to the buffer.
+    pub fn push_bytes(&mut self, bytes: Bytes) {
+        if self.framing != Framing::Array {
+            return self.push(&bytes);
+        }
+        self.flush_chunk();
+        self.chunk = bytes;
+    }
+    /// Copy what is left of the chunk of `push_bytes` to the buffer.
+    fn flush_chunk(&mut self) {
+        if self.chunk_pos < self.chunk.len() {
+            self.buffer.extend(&self.chunk[self.chunk_pos..]);
+        }
+        self.chunk = Bytes::new();
+        self.chunk_pos = 0;
+    }
+    /// Whether the chunk of `push_bytes` can be scanned in place: the scanner is between
+    /// the elements of an array, and the elements need no more than deserializing.
+    fn can_scan_chunk(&self) -> bool {
+        self.framing == Framing::Array
+            && self.level > 0
+            && self.parens >= self.level
+            && self.i == self.buffer.len()
+            && self.on_skip.is_none()
+            && self.spill.is_none()
+            && self.offload.is_none()
+            && !self.json5
+    }
+    /// Scan the chunk of `push_bytes` up to the end of the next element, deserializing it
+    /// from the chunk unless its start is in the buffer. Moves the chunk to the buffer when
+    /// it ends within an element, or when the array closes.
+    fn next_in_chunk(&mut self) -> Result<Option<T>, JsonStreamError> {
+        let chunk = self.chunk.clone();
+        let start = self.chunk_pos;
+        let mut pos = start;
+        while pos < chunk.len() {
+            let byte = chunk[pos];
+            pos += 1;
+            if self.strings.feed(byte, false) != Lexeme::Outside {
+                self.last_was_start = false;
+                continue;
+            }
+            match byte {
+                b'[' | b'{' => {
+                    self.parens += 1;
+                    self.last_was_start = false;
+                }
+                b',' if self.parens == self.level => {
+                    self.last_was_start = false;
+                    self.chunk_pos = pos;
+                    if !self.buffer.is_empty() {
+                        // The element started in an earlier chunk.
+                        self.buffer.extend(&chunk[start..pos]);
+                        let len = self.buffer.len();
+                        return self.next_element(len - 1, len);
+                    }
+                    return self.chunk_value(&chunk[start..pos - 1]).map(Some);
+                }
+                // The end of the array is handled by the scanner of the buffer.
+                b']' | b'}' if self.parens == self.level => {
+                    pos -= 1;
+                    break;
+                }
+                b']' | b'}' => {
+                    self.parens -= 1;
+                    self.last_was_start = false;
+                }
+                other => {
+                    if !other.is_ascii_whitespace() {
+                        self.last_was_start = false;
+                    }
+                }
+            }
+        }
+        let scanned = self.buffer.len() + pos - start;
+        self.flush_chunk();
+        self.i = scanned;
+        Ok(None)
+    }
+    /// Run the checks of the elements on one, deserialized from `raw`.
+    fn check(&self, value: &T, raw: &[u8]) -> serde_json::Result<()> {
+        self.checks
+            .iter()
+            .try_for_each(|check| check(raw, self.json5))?;
+        match self.roundtrip {
+            Some(roundtrip) => roundtrip(value, raw, self.json5),
+            None => Ok(()),
+        }
+    }
+    /// Deserialize the element made of `raw`, the bytes of the chunk of `push_bytes`
+    /// before its delimiter, which is consumed too.
+    fn chunk_value(&mut self, raw: &[u8]) -> Result<T, JsonStreamError> {
+        #[cfg(feature = "profiling")]
+        let started = Instant::now();
+        let res = match self.seed {
+            _ if self.scan_only => scanned([raw, &[]]),
+            Some(ref seed) => (*lock(seed))(raw, self.unbounded_depth, self.json5),
+            None => deserialize(SliceRead::new(raw), self.unbounded_depth),
+        };
+        #[cfg(feature = "profiling")]
+        {
+            self.deserialize_time += started.elapsed();
+        }
+        let res = match res {
+            Ok(value) if !self.scan_only => self.check(&value, raw).map(|()| value),
+            res => res,
+        };
+        let result =
+            res.map_err(|json_err| element_error(json_err, raw, 0, self.offset, self.lenient));
+        if self.keep_raw && result.is_ok() {
+            self.last_raw = Some(raw.trim_ascii().to_vec());
+        }
+        self.last_size = raw.len();
+        self.offset += raw.len() as u64 + 1;
+        result
+    }
+    /// Signal the end of the document, flushing a last value that has no delimiter.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+    pub(crate) fn framing(&self) -> Framing {
+        self.framing
+    }
+    /// Whether a top-level value was closed.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed
+    }
+    /// Whether no byte was ever pushed.
+    pub fn is_empty(&self) -> bool {
+        self.offset == 0 && self.buffer.is_empty() && self.chunk.is_empty()
+    }
+    fn skip(&mut self, range: Range<u64>) -> bool {
+        match self.on_skip {
+            Some(ref mut on_skip) => {
+                on_skip(range);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns the element made of the first `len` bytes of the buffer and removes
+    /// `consumed` bytes, or skips the element in robust mode if it cannot be deserialized.
+    fn next_element(&mut self, len: usize, consumed: usize) -> Result<Option<T>, JsonStreamError> {
+        if self.is_oversized(len) {
+            self.spill_bytes(len, consumed, true);
+            return Ok(None);
+        }
+        if self.is_large(len) {
+            let start = match self.framing {
+                Framing::ObjectValues => self.key_len(len),
+                _ => Ok(0),
+            };
+            // An invalid key is reported by the inline path.
+            if let Ok(start) = start {
+                let raw: Vec<u8> = self.buffer.range(..len).copied().collect();
+                if self.keep_raw {
+                    self.last_raw = Some(raw[start..].trim_ascii().to_vec());
+                }
+                self.offloaded = Some(Offloaded {
+                    raw,
+                    start,
+                    offset: self.offset,
+                    unbounded_depth: self.unbounded_depth,
+                    lenient: self.lenient,
+                    json5: self.json5,
+                });
+                self.last_size = len;
+                for _ in self.buffer.drain(..consumed) {}
+                self.offset += consumed as u64;
+                self.i = 0;
+                return Ok(None);
+            }
+        }
+        let start = self.offset;
+        match self.next_value(len, consumed) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) => {
+                if self.skip(start..start + len as u64) {
+                    // Whatever state the scanner was in, the boundary it found sits at the
+                    // requested level, so we can start fresh from there.
+                    self.strings.reset();
+                    Ok(None)
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+    /// The context of a failure on `popped`, the byte just removed from the front of the
+    /// buffer.
+    fn popped_context(&self, popped: u8) -> String {
+        let bytes: Vec<u8> = iter::once(popped)
+            .chain(self.buffer.iter().copied())
+            .take(CONTEXT_SIZE + 1)
+            .collect();
+        context(&bytes, 0, self.offset - 1)
+    }
+    /// Whether the element made of the first `len` bytes of the buffer goes to the spill
+    /// handler.
+    fn is_oversized(&self, len: usize) -> bool {
+        match self.spill {
+            Some((threshold, _)) => self.spilling || len > threshold,
+            None => false,
+        }
+    }
+    /// Hand the first `len` bytes of the buffer, part of an oversized element, to the spill
+    /// handler and remove `consumed` bytes.
+    fn spill_bytes(&mut self, len: usize, consumed: usize, last: bool) {
+        let bytes = &self.buffer.make_contiguous()[..len];
+        // Whitespace around the element is not part of it. Trailing whitespace stays in
+        // the buffer until we know whether the element continues after it.
+        let start = match self.spilling {
+            true => 0,
+            false => len - bytes.trim_ascii_start().len(),
+        };
+        let end = cmp::max(start, bytes.trim_ascii_end().len());
+        if last || start < end {
+            if let Some((_, ref mut on_chunk)) = self.spill {
+                on_chunk(&bytes[start..end], last);
+            }
+            self.spilling = !last;
+        }
+        let consumed = if last { consumed } else { end };
+        for _ in self.buffer.drain(..consumed) {}
+        self.offset += consumed as u64;
+        self.i = len - cmp::min(len, consumed);
+    }
+    /// The length of the `"key":` prefix of the first `len` bytes of the buffer, which
+    /// hold a member of an object.
+    fn key_len(&self, len: usize) -> Result<usize, serde_json::Error> {
+        let mut bytes = self
+            .buffer
+            .range(..len)
+            .enumerate()
+            .skip_while(|(_, b)| b.is_ascii_whitespace());
+        if !matches!(bytes.next(), Some((_, b'"'))) {
+            return Err(de::Error::custom("expected an object key"));
+        }
+        let mut strings = Strings::default();
+        strings.feed(b'"', false);
+        loop {
+            match bytes.next() {
+                None => return Err(de::Error::custom("unterminated object key")),
+                Some((_, &byte)) if strings.feed(byte, false) == Lexeme::Close => break,
+                _ => {}
+            }
+        }
+        match bytes.find(|(_, b)| !b.is_ascii_whitespace()) {
+            Some((j, b':')) => Ok(j + 1),
+            _ => Err(de::Error::custom("expected `:` after an object key")),
+        }
+    }
+    fn next_value(&mut self, i: usize, consumed: usize) -> Result<T, JsonStreamError> {
+        let start = match self.framing {
+            Framing::ObjectValues => self.key_len(i),
+            _ => Ok(0),
+        };
+        if self.seed.is_some() || self.json5 {
+            // The seed deserializes from a slice, the element must not wrap around.
+            self.buffer.make_contiguous();
+        }
         let (first, second) = self.buffer.as_slices();
-        let res = {
-            if first.len() < i {
-                from_reader(Cursor::new(first).chain(Cursor::new(&second[0..i - first.len()])))
-            } else {
-                from_slice(&first[0..i])
+        #[cfg(feature = "profiling")]
+        let started = Instant::now();
+        let (start, res) = match (start, &mut self.seed) {
+            (Err(err), _) => (0, Err(err)),
+            (Ok(start), _) if self.scan_only => {
+                let raw = if i <= first.len() {
+                    [&first[start..i], &[][..]]
+                } else if start >= first.len() {
+                    [&second[start - first.len()..i - first.len()], &[]]
+                } else {
+                    [&first[start..], &second[..i - first.len()]]
+                };
+                (start, scanned(raw))
+            }
+            (Ok(start), Some(seed)) => (
+                start,
+                (*lock(seed))(&first[start..i], self.unbounded_depth, self.json5),
+            ),
+            (Ok(start), None) if self.json5 => (
+                start,
+                deserialize_raw(PhantomData, &first[start..i], self.unbounded_depth, true),
+            ),
+            (Ok(start), None) if first.len() <= start => {
+                let slice = &second[start - first.len()..i - first.len()];
+                (
+                    start,
+                    deserialize(SliceRead::new(slice), self.unbounded_depth),
+                )
+            }
+            (Ok(start), None) if first.len() < i => {
+                let reader =
+                    Cursor::new(&first[start..]).chain(Cursor::new(&second[0..i - first.len()]));
+                (
+                    start,
+                    deserialize(IoRead::new(reader), self.unbounded_depth),
+                )
+            }
+            (Ok(start), None) => (
+                start,
+                deserialize(SliceRead::new(&first[start..i]), self.unbounded_depth),
+            ),
+        };
+        #[cfg(feature = "profiling")]
+        {
+            self.deserialize_time += started.elapsed();
+        }
+        let res = match res {
+            Ok(value)
+                if !self.scan_only && (!self.checks.is_empty() || self.roundtrip.is_some()) =>
+            {
+                let raw: Vec<u8> = first
+                    .iter()
+                    .chain(second)
+                    .take(i)
+                    .skip(start)
+                    .copied()
+                    .collect();
+                self.check(&value, &raw).map(|()| value)
             }
+            res => res,
         };
+        let lenient = self.lenient;
+        let offset = self.offset;
         let result = res.map_err(|json_err| {
-            let piece = if first.len() < i {
-                first[..].to_vec()
-            } else {
-                first[0..i].to_vec()
-            };
-            JsonStreamError::json(format!(
-                "{}: {}",
-                json_err,
-                String::from_utf8(piece).unwrap_or_else(|e| format!("Not valid UTF8: {}", e))
-            ))
+            let raw: Vec<u8> = first.iter().chain(second).take(i).copied().collect();
+            element_error(json_err, &raw, start, offset, lenient)
         });
-        for _ in self.buffer.drain(0..self.i) {}
+        if self.keep_raw && result.is_ok() {
+            let mut raw: Vec<u8> = first
+                .iter()
+                .chain(second)
+                .take(i)
+                .skip(start)
+                .skip_while(|b| b.is_ascii_whitespace())
+                .copied()
+                .collect();
+            raw.truncate(raw.trim_ascii_end().len());
+            self.last_raw = Some(raw);
+        }
+        self.last_size = i;
+        for _ in self.buffer.drain(0..consumed) {}
+        self.offset += consumed as u64;
         self.i = 0;
         result
     }
     pub fn next(&mut self) -> Result<Option<T>, JsonStreamError> {
+        match self.framing {
+            Framing::Array | Framing::FlattenedLines | Framing::ObjectValues => {
+                self.next_in_array()
+            }
+            Framing::Single => self.next_single(),
+            Framing::Lines => self.next_line(),
+            Framing::JsonSeq => self.next_record(),
+        }
+    }
+    /// The single value is only complete at the end of the document.
+    fn next_single(&mut self) -> Result<Option<T>, JsonStreamError> {
+        if !self.finished || self.buffer.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        let len = self.buffer.len();
+        self.next_element(len, len)
+    }
+    /// A record ends at the next record separator, or at a newline following a complete
+    /// value, so a record is not held back until the next one starts.
+    fn next_record(&mut self) -> Result<Option<T>, JsonStreamError> {
+        loop {
+            if self.offloaded.is_some() {
+                return Ok(None);
+            }
+            if self.i == 0 && self.buffer.front() == Some(&RECORD_SEPARATOR) {
+                self.buffer.pop_front();
+                self.offset += 1;
+                continue;
+            }
+            let mut end = None;
+            while self.i < self.buffer.len() {
+                let byte = self.buffer[self.i];
+                if byte == RECORD_SEPARATOR {
+                    end = Some((self.i, self.i));
+                    break;
+                }
+                self.i += 1;
+                if self.strings.feed(byte, false) != Lexeme::Outside {
+                    continue;
+                }
+                match byte {
+                    b'[' | b'{' => self.parens += 1,
+                    b']' | b'}' => self.parens = self.parens.saturating_sub(1),
+                    b'\n'
+                        if self.parens == 0
+                            && !self.buffer.range(..self.i).all(u8::is_ascii_whitespace) =>
+                    {
+                        end = Some((self.i - 1, self.i));
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let (len, consumed) = match end {
+                Some(end) => end,
+                None if self.finished && !self.buffer.is_empty() => {
+                    (self.buffer.len(), self.buffer.len())
+                }
+                None => return Ok(None),
+            };
+            self.parens = 0;
+            self.strings.reset();
+            if self.buffer.range(..len).all(u8::is_ascii_whitespace) {
+                for _ in self.buffer.drain(..consumed) {}
+                self.offset += consumed as u64;
+                self.i = 0;
+                continue;
+            }
+            if let Some(value) = self.next_element(len, consumed)? {
+                return Ok(Some(value));
+            }
+        }
+    }
+    fn next_line(&mut self) -> Result<Option<T>, JsonStreamError> {
+        loop {
+            if self.offloaded.is_some() {
+                return Ok(None);
+            }
+            let newline = self.buffer.range(self.i..).position(|&b| b == b'\n');
+            let (mut len, consumed) = match newline {
+                Some(pos) => (self.i + pos, self.i + pos + 1),
+                None if self.finished && !self.buffer.is_empty() => {
+                    (self.buffer.len(), self.buffer.len())
+                }
+                None => {
+                    self.i = self.buffer.len();
+                    return Ok(None);
+                }
+            };
+            // `\r\n` terminates a line just like `\n`, the `\r` is not part of the value.
+            if len > 0 && self.buffer[len - 1] == b'\r' {
+                len -= 1;
+            }
+            if self.buffer.range(..len).all(u8::is_ascii_whitespace) {
+                for _ in self.buffer.drain(..consumed) {}
+                self.offset += consumed as u64;
+                self.i = 0;
+                continue;
+            }
+            if let Some(value) = self.next_element(len, consumed)? {
+                return Ok(Some(value));
+            }
+        }
+    }
+    /// Whether `next_char` is part of a comment of a json5 document, or may start one.
+    fn is_comment(&self, next_char: char) -> bool {
+        self.json5 && (self.comment.is_some() || next_char == '/')
+    }
+    /// Follow the comments of a json5 document, outside of the strings. Returns whether
+    /// `next_char` is part of a comment, which stays in the bytes of the element it is in.
+    fn scan_comment(&mut self, next_char: char) -> bool {
+        self.comment = match (self.comment, next_char) {
+            (None, '/') => Some(Comment::Start),
+            (None, _) => return false,
+            (Some(Comment::Start), '/') => Some(Comment::Line),
+            (Some(Comment::Start), '*') => Some(Comment::Block),
+            (Some(Comment::Start), _) => {
+                // Not a comment, the deserializer fails on the `/`.
+                self.comment = None;
+                return false;
+            }
+            (Some(Comment::Line), '\n') => None,
+            (Some(Comment::Line), _) => Some(Comment::Line),
+            (Some(Comment::Block | Comment::BlockEnd), '*') => Some(Comment::BlockEnd),
+            (Some(Comment::BlockEnd), '/') => None,
+            (Some(_), _) => Some(Comment::Block),
+        };
+        true
+    }
+    /// Handle a line that does not hold an array, found at the start of the buffer.
+    /// Returns whether the line was skipped, or `false` if the line is not complete yet.
+    fn skip_line(&mut self) -> Result<bool, JsonStreamError> {
+        let (len, consumed) = match self.buffer.iter().position(|&b| b == b'\n') {
+            Some(pos) => (pos, pos + 1),
+            None if self.finished => (self.buffer.len(), self.buffer.len()),
+            None => return Ok(false),
+        };
+        let start = self.offset;
+        let raw: Vec<u8> = self.buffer.drain(..consumed).take(len).collect();
+        self.offset += consumed as u64;
+        if self.skip(start..start + len as u64) {
+            return Ok(true);
+        }
+        let json_err = match serde_json::from_slice::<Vec<IgnoredAny>>(&raw) {
+            Err(err) => err,
+            Ok(_) => unreachable!("a line not starting with '[' is not an array"),
+        };
+        if self.lenient {
+            return Err(JsonStreamError::InvalidElement(
+                json_err,
+                raw.trim_ascii().to_vec(),
+            ));
+        }
+        let at = error_position(&raw, &json_err);
+        Err(JsonStreamError::json(format!(
+            "{}, {}",
+            json_err,
+            context(&raw, at, start + at as u64)
+        )))
+    }
+    fn next_in_array(&mut self) -> Result<Option<T>, JsonStreamError> {
         loop {
+            if self.offloaded.is_some() {
+                return Ok(None);
+            }
+            if self.chunk_pos < self.chunk.len() {
+                if self.can_scan_chunk() {
+                    match self.next_in_chunk()? {
+                        Some(value) => return Ok(Some(value)),
+                        None => continue,
+                    }
+                }
+                // The bytes left in the buffer may still lead to the array, like its `[`.
+                let capturing = self.footer.as_ref().is_some_and(|footer| footer.capturing);
+                if self.i == self.buffer.len() || self.path.is_some() || capturing {
+                    self.flush_chunk();
+                }
+            }
+            if !self.scan_path()? || !self.scan_footer()? {
+                return Ok(None);
+            }
             if self.i == self.buffer.len() {
+                // Hand out what we have of an oversized element, instead of buffering it.
+                if self.parens >= self.level && self.level > 0 && self.is_oversized(self.i) {
+                    self.spill_bytes(self.i, self.i, false);
+                }
                 return Ok(None);
             }
             let next_char = self.buffer[self.i] as char;
+            if self.framing == Framing::FlattenedLines
+                && self.parens == 0
+                && next_char != '['
+                && !next_char.is_ascii_whitespace()
+            {
+                if self.skip_line()? {
+                    continue;
+                }
+                return Ok(None);
+            }
+            if self.max_garbage > 0
+                && self.parens == 0
+                && !self.closed
+                && self.framing != Framing::FlattenedLines
+                && !matches!(next_char, '[' | '{')
+                && !next_char.is_ascii_whitespace()
+                && !self.is_comment(next_char)
+            {
+                // Not interpreted at all, a quote or a closing brace does not matter here.
+                self.buffer.pop_front();
+                self.offset += 1;
+                if self.leading_garbage < self.max_garbage {
+                    self.leading_garbage += 1;
+                    continue;
+                }
+                return Err(JsonStreamError::json(format!(
+                    "Too many characters before the json document, {}",
+                    self.popped_context(next_char as u8)
+                )));
+            }
+            if self.parens == 0
+                && self.closed
+                && !self.multi_array
+                && self.framing != Framing::FlattenedLines
+                && !next_char.is_ascii_whitespace()
+                && !self.is_comment(next_char)
+            {
+                self.buffer.pop_front();
+                self.offset += 1;
+                if self.trailing_garbage < self.max_garbage {
+                    self.trailing_garbage += 1;
+                    continue;
+                }
+                if self.skip(self.offset - 1..self.offset) {
+                    continue;
+                }
+                return Err(JsonStreamError::json(format!(
+                    "Trailing characters after the json document, {}",
+                    self.popped_context(next_char as u8)
+                )));
+            }
             if self.parens < self.level {
                 self.buffer.pop_front();
+                self.offset += 1;
             } else {
                 self.i += 1;
             }
-            if self.in_string {
-                if self.last_was_escape {
-                    self.last_was_escape = false;
-                } else if next_char == '"' {
-                    self.in_string = false;
-                } else if next_char == '\\' {
-                    self.last_was_escape = true;
-                }
+            if self.json5 && !self.strings.in_string() && self.scan_comment(next_char) {
+                continue;
+            }
+            if self.strings.in_string() && next_char < ' ' && self.on_skip.is_some() {
+                // Control characters are not allowed in json strings, this is most likely
+                // an unterminated string. The element will fail to deserialize and be
+                // skipped, but the structure after it can still be recovered.
+                self.strings.reset();
+            } else if self.strings.feed(next_char as u8, self.json5) != Lexeme::Outside {
+                self.last_was_start = false;
             } else {
                 match next_char {
                     '[' | '{' => {
+                        if self.on_skip.is_some() {
+                            self.openers.push(next_char);
+                        }
                         self.parens += 1;
                         self.last_was_start = self.parens == self.level;
                     }
                     ',' => {
-                        self.last_was_start = false;
+                        // In json5, the array may close right after a trailing comma.
+                        self.last_was_start = self.json5 && self.parens == self.level;
                         if self.parens == self.level {
-                            return Ok(Some(self.next_value()?));
+                            match self.next_element(self.i - 1, self.i)? {
+                                Some(value) => return Ok(Some(value)),
+                                None => continue,
+                            }
                         }
                     }
-                    '"' => {
-                        self.last_was_start = false;
-                        self.in_string = true;
-                    }
                     ']' | '}' => {
                         if self.parens == 0 {
-                            return Err(JsonStreamError::json("Invalid json".to_string()));
+                            // The closing character was already removed from the buffer.
+                            if self.skip(self.offset - 1..self.offset) {
+                                continue;
+                            }
+                            return Err(JsonStreamError::json(format!(
+                                "Invalid json, {}",
+                                self.popped_context(next_char as u8)
+                            )));
+                        }
+                        if self.on_skip.is_some() {
+                            let expected = if next_char == ']' { '[' } else { '{' };
+                            if self.openers.last() != Some(&expected) {
+                                // A mismatched brace, ignore it. If it is part of an
+                                // element, that element will be skipped as a whole.
+                                self.last_was_start = false;
+                                continue;
+                            }
+                            self.openers.pop();
                         }
                         self.parens -= 1;
+                        self.closed |= self.parens == 0;
+                        if self.parens == self.level - 1 && self.parens > 0 {
+                            if let Some(ref mut footer) = self.footer {
+                                footer.capturing = true;
+                            }
+                        }
                         if self.parens == self.level - 1 && !self.last_was_start {
-                            return Ok(Some(self.next_value()?));
+                            match self.next_element(self.i - 1, self.i)? {
+                                Some(value) => return Ok(Some(value)),
+                                None => continue,
+                            }
+                        }
+                        if self.parens == self.level - 1 {
+                            // An empty array, drop it so that the scan continues from the
+                            // start of the buffer.
+                            for _ in self.buffer.drain(..self.i) {}
+                            self.offset += self.i as u64;
+                            self.i = 0;
                         }
                         self.last_was_start = false;
                     }
@@ -115,10 +1396,23 @@ impl<T: DeserializeOwned> PartialJson<T> {
     }
 }
 
+impl<T> Drop for PartialJson<T> {
+    fn drop(&mut self) {
+        if let Some(ref pool) = self.pool {
+            let mut buffer = Vec::from(mem::take(&mut self.buffer));
+            buffer.clear();
+            pool.put(buffer);
+        }
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes)]
 mod tests {
     use super::PartialJson;
+    use crate::util::JsonStreamError;
     use serde::Deserialize;
+    use serde_json::json;
     #[test]
     fn partial_json_test() {
         const JSON: &'static str = "[1, 2, 3, 4, 5]";
@@ -131,6 +1425,104 @@ mod tests {
         assert_eq!(res, [1, 2, 3, 4, 5]);
     }
     #[test]
+    fn footer() {
+        let mut json: PartialJson<u32> = PartialJson::new(0, 2);
+        json.capture_footer();
+        json.push(br#"{"items": [1, 2], "sum": {"a": [3, "}"]}, "count": 2} "#);
+        json.finish();
+        assert_eq!(json.next().unwrap(), Some(1));
+        assert_eq!(json.next().unwrap(), Some(2));
+        assert!(json.take_footer().is_none());
+        assert_eq!(json.next().unwrap(), None);
+        assert_eq!(
+            json.take_footer().unwrap(),
+            br#"{"sum": {"a": [3, "}"]}, "count": 2}"#
+        );
+
+        let mut json: PartialJson<u32> = PartialJson::new(0, 2);
+        json.capture_footer();
+        json.push(br#"{"items": []}"#);
+        assert_eq!(json.next().unwrap(), None);
+        assert_eq!(json.take_footer().unwrap(), b"{}");
+    }
+    #[test]
+    fn push_bytes() {
+        use crate::util::test_server::CITIES;
+        use hyper::body::Bytes;
+
+        let mut expected: PartialJson<serde_json::Value> = PartialJson::new(0, 1);
+        expected.push(CITIES);
+        let expected: Vec<serde_json::Value> =
+            std::iter::from_fn(|| expected.next().unwrap()).collect();
+        let document = Bytes::from_static(CITIES);
+        for chunk_size in [1, 2, 7, 64, 100, CITIES.len()] {
+            let mut json: PartialJson<serde_json::Value> = PartialJson::new(0, 1);
+            json.keep_raw(true);
+            let mut res = Vec::new();
+            let mut offset = 0;
+            while offset < document.len() {
+                let end = std::cmp::min(offset + chunk_size, document.len());
+                json.push_bytes(document.slice(offset..end));
+                offset = end;
+                while let Some(city) = json.next().unwrap() {
+                    let raw = json.take_raw().unwrap();
+                    assert_eq!(
+                        serde_json::from_slice::<serde_json::Value>(&raw).unwrap(),
+                        city
+                    );
+                    res.push(city);
+                }
+            }
+            json.finish();
+            assert!(json.next().unwrap().is_none());
+            assert_eq!(res, expected);
+        }
+
+        // The elements whole in a chunk are not copied to the buffer.
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.push(b"[");
+        let elements: String = (0..1000).map(|i| format!("{}, ", i)).collect();
+        json.push_bytes(Bytes::from(elements));
+        let res: Vec<u32> = std::iter::from_fn(|| json.next().unwrap()).collect();
+        assert_eq!(res.len(), 1000);
+        assert!(json.buffer.capacity() < 100);
+        json.push_bytes(Bytes::from_static(b"1000]"));
+        assert_eq!(json.next().unwrap(), Some(1000));
+        assert_eq!(json.next().unwrap(), None);
+
+        // An element that cannot be deserialized is reported like with `push`.
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.lenient(true);
+        json.push(b"[");
+        json.push_bytes(Bytes::from_static(b"1, \"a\", 3]"));
+        assert_eq!(json.next().unwrap(), Some(1));
+        assert!(
+            matches!(json.next(), Err(JsonStreamError::InvalidElement(_, raw)) if raw == b"\"a\"")
+        );
+        assert_eq!(json.next().unwrap(), Some(3));
+    }
+    #[test]
+    fn skip_garbage() {
+        let next = |json: &mut PartialJson<u32>| {
+            std::iter::from_fn(|| json.next().transpose()).collect::<Result<Vec<u32>, _>>()
+        };
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.skip_garbage(4);
+        json.multi_array(false);
+        json.push(b"\"]}[1, 2]\n\0\0x\r\n}");
+        assert_eq!(next(&mut json).unwrap(), [1, 2]);
+
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.skip_garbage(2);
+        json.multi_array(false);
+        json.push(b"[1]abc");
+        assert!(next(&mut json).is_err());
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.skip_garbage(2);
+        json.push(b"abc[1]");
+        assert!(next(&mut json).is_err());
+    }
+    #[test]
     fn partial_json_test_object() {
         const JSON: &'static str = "{list: [1, 2, 3, 4, 5]}";
         let mut json: PartialJson<u32> = PartialJson::new(100, 2);
@@ -192,6 +1584,33 @@ mod tests {
         }
     }
     #[test]
+    fn tuples() {
+        // Brackets in the strings must not change the depth of the scan.
+        const JSON: &str = r#"{"rows": [["a", 1], ["b]", 2], ["[c\"]", -3], [" ", 0]]}"#;
+        for i in 1..JSON.len() {
+            let mut json: PartialJson<(String, i32)> = PartialJson::new(0, 2);
+            let mut res = Vec::new();
+            for chunk in [&JSON.as_bytes()[..i], &JSON.as_bytes()[i..]] {
+                json.push(chunk);
+                while let Some(next) = json.next().unwrap() {
+                    res.push(next);
+                }
+            }
+            assert_eq!(
+                res,
+                [
+                    ("a".to_string(), 1),
+                    ("b]".to_string(), 2),
+                    ("[c\"]".to_string(), -3),
+                    (" ".to_string(), 0),
+                ]
+            );
+        }
+        let mut json: PartialJson<(String, i32)> = PartialJson::new(0, 1);
+        json.push(br#"[["a", 1, 2]]"#);
+        assert!(json.next().is_err());
+    }
+    #[test]
     fn empty_json() {
         const JSON: &'static str = "{[ \n]}";
         for i in 1..JSON.len() {
@@ -209,4 +1628,481 @@ mod tests {
             assert_eq!(res.len(), 0);
         }
     }
+    #[test]
+    fn robust_skips_invalid_elements() {
+        const JSON: &str = r#"[1, "two", 3, }4, {"five": 5}, 6]"#;
+        let skipped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        let sink = skipped.clone();
+        json.robust(Box::new(move |range| sink.lock().unwrap().push(range)));
+        json.push(JSON.as_bytes());
+        let mut res = Vec::new();
+        while let Some(next) = json.next().unwrap() {
+            res.push(next);
+        }
+        assert_eq!(res, [1, 3, 6]);
+        let skipped: Vec<_> = skipped
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|range| {
+                JSON[range.start as usize..range.end as usize]
+                    .trim()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(skipped, [r#""two""#, "}4", r#"{"five": 5}"#]);
+    }
+    #[test]
+    fn robust_recovers_from_unterminated_string() {
+        const JSON: &str = "[\"a\", \"b\n, \"c\"]";
+        let mut json: PartialJson<String> = PartialJson::new(0, 1);
+        json.robust(Box::new(|_| {}));
+        json.push(JSON.as_bytes());
+        let mut res = Vec::new();
+        while let Some(next) = json.next().unwrap() {
+            res.push(next);
+        }
+        assert_eq!(res, ["a", "c"]);
+    }
+    fn lines(chunks: &[&str]) -> Vec<serde_json::Value> {
+        let mut json: PartialJson<serde_json::Value> = PartialJson::lines(0);
+        let mut res = Vec::new();
+        for chunk in chunks {
+            json.push(chunk.as_bytes());
+            while let Some(next) = json.next().unwrap() {
+                res.push(next);
+            }
+        }
+        json.finish();
+        while let Some(next) = json.next().unwrap() {
+            res.push(next);
+        }
+        res
+    }
+    #[test]
+    fn ndjson_scalars() {
+        let res = lines(&["\"hello\"\n42\ntr", "ue\nnull\n{\"a\": [1]}\n[1, 2]"]);
+        assert_eq!(
+            res,
+            [
+                json!("hello"),
+                json!(42),
+                json!(true),
+                json!(null),
+                json!({"a": [1]}),
+                json!([1, 2])
+            ]
+        );
+    }
+    #[test]
+    fn ndjson_blank_lines() {
+        let res = lines(&["\n  \n1\n\t\n", "\n2  \n \n"]);
+        assert_eq!(res, [json!(1), json!(2)]);
+    }
+    #[test]
+    fn ndjson_crlf() {
+        let res = lines(&["\"a\"\r\n{\"b\": \"c\"}\r", "\n\r\n3\r\n"]);
+        assert_eq!(res, [json!("a"), json!({"b": "c"}), json!(3)]);
+        // Mixed with `\n`, and split anywhere.
+        const JSON: &str = "\"a\"\r\n\"b\"\n\"c\"\r\n";
+        for i in 1..JSON.len() {
+            assert_eq!(lines(&[&JSON[..i], &JSON[i..]]), ["a", "b", "c"]);
+        }
+    }
+    #[test]
+    fn ndjson_crlf_line_slice() {
+        let mut json: PartialJson<serde_json::Value> = PartialJson::lines(0);
+        let skipped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = skipped.clone();
+        json.robust(Box::new(move |range| sink.lock().unwrap().push(range)));
+        json.push(b"1\r\nnot json\r\n2\n");
+        assert_eq!(json.next().unwrap(), Some(json!(1)));
+        assert_eq!(json.next().unwrap(), Some(json!(2)));
+        // The skipped line excludes its `\r\n` terminator.
+        assert_eq!(
+            skipped.lock().unwrap()[..],
+            [std::ops::Range { start: 3, end: 11 }]
+        );
+    }
+    #[cfg(feature = "path-errors")]
+    #[test]
+    fn path_in_errors() {
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Address {
+            zip: u32,
+        }
+        #[derive(Deserialize, Debug)]
+        #[allow(dead_code)]
+        struct Person {
+            address: Address,
+        }
+        let mut json: PartialJson<Person> = PartialJson::new(0, 1);
+        json.push(br#"[{"address": {"zip": 1000}}, {"address": {"zip": "1000"}}]"#);
+        assert!(json.next().unwrap().is_some());
+        let err = json.next().unwrap_err().to_string();
+        assert!(
+            err.starts_with("address.zip: invalid type: string"),
+            "{}",
+            err
+        );
+    }
+    #[test]
+    fn lenient_invalid_element() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Item {
+            a: u32,
+        }
+        const JSON: &str = r#"[{"a": 1}, {"a": "two"}, {"a": 3}]"#;
+        for i in 1..JSON.len() {
+            let mut json: PartialJson<Item> = PartialJson::new(0, 1);
+            json.lenient(true);
+            let mut res = Vec::new();
+            for chunk in [&JSON[..i], &JSON[i..]] {
+                json.push(chunk.as_bytes());
+                loop {
+                    match json.next() {
+                        Ok(Some(item)) => res.push(Ok(item.a)),
+                        Ok(None) => break,
+                        Err(JsonStreamError::InvalidElement(_, raw)) => res.push(Err(raw)),
+                        Err(err) => panic!("{}", err),
+                    }
+                }
+            }
+            assert_eq!(res, [Ok(1), Err(br#"{"a": "two"}"#.to_vec()), Ok(3)]);
+        }
+    }
+    fn flattened_lines(input: &str, lenient: bool) -> Vec<Result<u32, String>> {
+        let mut res = Vec::new();
+        for i in 0..=input.len() {
+            let mut json: PartialJson<u32> = PartialJson::flattened_lines(0);
+            json.lenient(lenient);
+            let mut items = Vec::new();
+            'chunks: for (n, chunk) in [&input[..i], &input[i..]].into_iter().enumerate() {
+                json.push(chunk.as_bytes());
+                if n == 1 {
+                    json.finish();
+                }
+                loop {
+                    match json.next() {
+                        Ok(Some(item)) => items.push(Ok(item)),
+                        Ok(None) => break,
+                        Err(JsonStreamError::InvalidElement(_, raw)) => {
+                            items.push(Err(String::from_utf8(raw).unwrap()))
+                        }
+                        Err(err) => {
+                            items.push(Err(err.to_string()));
+                            break 'chunks;
+                        }
+                    }
+                }
+            }
+            if i > 0 {
+                assert_eq!(items, res, "split at {}", i);
+            }
+            res = items;
+        }
+        res
+    }
+    #[test]
+    fn flattened_lines_test() {
+        assert_eq!(
+            flattened_lines("[1, 2]\n[]\n\n[3]\r\n[4,\n5]", false),
+            [Ok(1), Ok(2), Ok(3), Ok(4), Ok(5)]
+        );
+        assert_eq!(
+            flattened_lines("[1]\n{\"a\": 2}\n[3]\n7", true),
+            [
+                Ok(1),
+                Err("{\"a\": 2}".to_string()),
+                Ok(3),
+                Err("7".to_string())
+            ]
+        );
+        let res = flattened_lines("[1]\n2\n[3]", false);
+        assert_eq!(res.len(), 2);
+        assert!(res[1]
+            .as_ref()
+            .unwrap_err()
+            .starts_with("invalid type: integer `2`"));
+    }
+    #[test]
+    fn object_values_and_single() {
+        const JSON: &str = r#"{"a": 1, "b\"]": [2, {"c": 3}] , "d":"4"}"#;
+        for i in 0..JSON.len() {
+            let mut values: PartialJson<serde_json::Value> = PartialJson::object_values(0, 1);
+            let mut single: PartialJson<serde_json::Value> = PartialJson::single(0);
+            let (mut res, mut whole) = (Vec::new(), Vec::new());
+            for chunk in [&JSON[..i], &JSON[i..]] {
+                values.push(chunk.as_bytes());
+                single.push(chunk.as_bytes());
+                while let Some(value) = values.next().unwrap() {
+                    res.push(value);
+                }
+                assert!(single.next().unwrap().is_none());
+            }
+            single.finish();
+            while let Some(value) = single.next().unwrap() {
+                whole.push(value);
+            }
+            assert_eq!(res, [json!(1), json!([2, {"c": 3}]), json!("4")]);
+            assert_eq!(
+                whole,
+                [serde_json::from_str::<serde_json::Value>(JSON).unwrap()]
+            );
+        }
+        let mut values: PartialJson<u32> = PartialJson::object_values(0, 1);
+        values.push(b"{1, 2}");
+        assert!(values.next().is_err());
+    }
+    /// Feed `input` one byte at a time, the slowest possible chunking.
+    fn byte_at_a_time(input: &[u8], level: u32) -> Vec<serde_json::Value> {
+        let mut json: PartialJson<serde_json::Value> = PartialJson::new(0, level);
+        let mut res = Vec::new();
+        for byte in input {
+            json.push(&[*byte]);
+            while let Some(value) = json.next().unwrap() {
+                res.push(value);
+            }
+        }
+        json.finish();
+        while let Some(value) = json.next().unwrap() {
+            res.push(value);
+        }
+        res
+    }
+    #[test]
+    fn one_byte_at_a_time() {
+        const JSON: &str = r#" [ "a,]}", {"b": "\"[{", "c": [[], {}]}, [1, [2]],
+            -1.5e3, "\\", "é ☃", {}, [], null, true, {"d": {"e": []}} ] "#;
+        let expected = serde_json::from_str::<serde_json::Value>(JSON).unwrap();
+        assert_eq!(
+            byte_at_a_time(JSON.as_bytes(), 1),
+            expected.as_array().unwrap()[..]
+        );
+
+        let nested = format!(r#"{{"count": 11, "items": {}}}"#, JSON);
+        assert_eq!(
+            byte_at_a_time(nested.as_bytes(), 2),
+            expected.as_array().unwrap()[..]
+        );
+
+        let cities = byte_at_a_time(crate::util::test_server::CITIES, 1);
+        assert_eq!(cities.len(), 12);
+    }
+    #[test]
+    fn recursion_limit() {
+        let deep = format!("[{}{}, 1]", "[".repeat(200), "]".repeat(200));
+        for unbounded_depth in [false, true] {
+            let mut json: PartialJson<serde_json::Value> = PartialJson::new(0, 1);
+            json.disable_recursion_limit(unbounded_depth);
+            json.push(deep.as_bytes());
+            match json.next() {
+                Ok(Some(_)) => assert!(unbounded_depth),
+                Err(err) => {
+                    assert!(!unbounded_depth);
+                    assert!(err.to_string().contains("recursion limit exceeded"));
+                }
+                Ok(None) => panic!("expected an element"),
+            }
+        }
+    }
+    fn json_seq(chunks: &[&[u8]]) -> Vec<Result<serde_json::Value, JsonStreamError>> {
+        let mut json: PartialJson<serde_json::Value> = PartialJson::json_seq(0);
+        json.lenient(true);
+        let mut res = Vec::new();
+        for chunk in chunks {
+            json.push(chunk);
+            while let Some(next) = json.next().transpose() {
+                res.push(next);
+            }
+        }
+        json.finish();
+        while let Some(next) = json.next().transpose() {
+            res.push(next);
+        }
+        res
+    }
+    #[test]
+    fn json_seq_records() {
+        const JSON: &[u8] =
+            b"\x1e{\"a\": 1}\n\x1e\"b\\n\"\n\x1e[\n  1,\n  2\n]\n\x1e\x1e3\n\x1etrue";
+        for i in 0..JSON.len() {
+            let res = json_seq(&[&JSON[..i], &JSON[i..]]);
+            let res: Vec<_> = res.into_iter().map(Result::unwrap).collect();
+            assert_eq!(
+                res,
+                [
+                    json!({"a": 1}),
+                    json!("b\n"),
+                    json!([1, 2]),
+                    json!(3),
+                    json!(true)
+                ]
+            );
+        }
+    }
+    #[test]
+    fn json_seq_truncated_record() {
+        let res = json_seq(&[b"\x1e{\"a\": ", b"\x1e{\"a\": 2}\n"]);
+        assert!(matches!(res[0], Err(JsonStreamError::InvalidElement(_, _))));
+        assert_eq!(res[1].as_ref().unwrap(), &json!({"a": 2}));
+    }
+    #[test]
+    fn spill_oversized() {
+        use std::sync::{Arc, Mutex};
+
+        const JSON: &[u8] = br#"[1, "a long string, spilled", {"b": [2, 3, 4, 5, 6]} , 7]"#;
+        for chunk_size in [1, 4, 1000] {
+            let chunks = Arc::new(Mutex::new(Vec::new()));
+            let spilled = chunks.clone();
+            let mut json: PartialJson<serde_json::Value> = PartialJson::new(0, 1);
+            json.spill_oversized(
+                8,
+                Box::new(move |bytes, last| spilled.lock().unwrap().push((bytes.to_vec(), last))),
+            );
+            let mut res = Vec::new();
+            for chunk in JSON.chunks(chunk_size) {
+                json.push(chunk);
+                while let Some(value) = json.next().unwrap() {
+                    res.push(value);
+                }
+            }
+            assert_eq!(res, [json!(1), json!(7)]);
+            let chunks = chunks.lock().unwrap();
+            // An element is never buffered much beyond the threshold.
+            assert!(chunks.len() > 2 || chunk_size == 1000);
+            let mut elements = vec![Vec::new()];
+            for (bytes, last) in chunks.iter() {
+                elements.last_mut().unwrap().extend_from_slice(bytes);
+                if *last {
+                    elements.push(Vec::new());
+                }
+            }
+            assert_eq!(
+                elements,
+                [
+                    &br#""a long string, spilled""#[..],
+                    &br#"{"b": [2, 3, 4, 5, 6]}"#[..],
+                    &b""[..]
+                ]
+            );
+        }
+    }
+    #[test]
+    fn error_context() {
+        let mut json: PartialJson<Vec<u32>> = PartialJson::new(0, 1);
+        json.push(b"[[1, 2], [3, \"x\", 5], [6]]");
+        assert!(json.next().unwrap().is_some());
+        let err = json.next().unwrap_err().to_string();
+        assert!(
+            err.ends_with(r#"near byte 15: " [3, \"x\", 5]""#),
+            "{}",
+            err
+        );
+
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.multi_array(false);
+        json.push(format!("[1{}] ]\x01", ", 2".repeat(20)).as_bytes());
+        json.finish();
+        let err = loop {
+            match json.next() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error"),
+                Err(err) => break err.to_string(),
+            }
+        };
+        assert_eq!(
+            err,
+            r#"Trailing characters after the json document, near byte 64: "]\x01""#
+        );
+
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.push(format!("[{}\n\"a\"]", "1,".repeat(40)).as_bytes());
+        let err = loop {
+            match json.next() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("no error"),
+                Err(err) => break err.to_string(),
+            }
+        };
+        assert!(err.ends_with(r#"near byte 84: "\n\"a\"""#), "{}", err);
+    }
+    #[test]
+    fn internally_tagged_enum() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        #[serde(tag = "type", rename_all = "lowercase")]
+        enum Event {
+            Click { x: u32, y: u32 },
+            Key { key: String },
+            Idle,
+        }
+        const JSON: &[u8] = br#" [ {"type": "click", "x": 1, "y": 2},
+            {"key": "]", "type": "key"} ,{"type":"idle"}
+        ] "#;
+        let expected = [
+            Event::Click { x: 1, y: 2 },
+            Event::Key { key: "]".into() },
+            Event::Idle,
+        ];
+        for i in 0..JSON.len() {
+            let mut json: PartialJson<Event> = PartialJson::new(0, 1);
+            let mut res = Vec::new();
+            for chunk in [&JSON[..i], &JSON[i..]] {
+                json.push(chunk);
+                while let Some(event) = json.next().unwrap() {
+                    res.push(event);
+                }
+            }
+            assert_eq!(res, expected);
+        }
+        let mut json: PartialJson<Event> = PartialJson::new(0, 1);
+        json.push(br#"[{"type": "scroll"}]"#);
+        let err = json.next().unwrap_err().to_string();
+        assert!(err.contains("unknown variant `scroll`"), "{}", err);
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn json5() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Item {
+            id: u32,
+            tags: Vec<String>,
+        }
+        const JSON: &[u8] =
+            b"// The items.\n[\n  {id: 1, tags: ['a]', \"b,\", 'c/*'], /* more */},\n\
+            /* ] */ {id: 0x2, tags: [], // none, really\n  },\n]\n/* The end. */\n";
+        let expected = [
+            Item {
+                id: 1,
+                tags: vec!["a]".into(), "b,".into(), "c/*".into()],
+            },
+            Item {
+                id: 2,
+                tags: vec![],
+            },
+        ];
+        for i in 0..JSON.len() {
+            let mut json: PartialJson<Item> = PartialJson::new(0, 1);
+            json.json5(true);
+            let mut res = Vec::new();
+            for chunk in [&JSON[..i], &JSON[i..]] {
+                json.push(chunk);
+                while let Some(item) = json.next().unwrap() {
+                    res.push(item);
+                }
+            }
+            json.finish();
+            assert!(json.next().unwrap().is_none());
+            assert_eq!(res, expected);
+        }
+        // A trailing comma closes the array, it does not skip an element.
+        let mut json: PartialJson<u32> = PartialJson::new(0, 1);
+        json.json5(true);
+        json.push(b"[1,, 2]");
+        assert_eq!(json.next().unwrap(), Some(1));
+        assert!(json.next().is_err());
+    }
 }
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use crate::stream::scanner::{Lexeme, Strings};
+use crate::util::JsonStreamError;
+
+/// The array to stream, and the fields to capture on the way to it, as dot separated keys
+/// of nested objects like `data.items`.
+#[derive(Debug, Clone)]
+pub(crate) struct JsonPath {
+    items: Vec<String>,
+    meta: Vec<Vec<String>>,
+}
+
+impl JsonPath {
+    pub(crate) fn new(items: &str, meta: &[&str]) -> Self {
+        JsonPath {
+            items: split(items),
+            meta: meta.iter().map(|path| split(path)).collect(),
+        }
+    }
+    /// The number of opening braces up to the elements of the array.
+    pub(crate) fn level(&self) -> u32 {
+        self.items.len() as u32 + 1
+    }
+}
+
+fn split(path: &str) -> Vec<String> {
+    match path {
+        "" => Vec::new(),
+        path => path.split('.').map(str::to_string).collect(),
+    }
+}
+
+enum Container {
+    /// The key of the current member, once read.
+    Object(Option<String>),
+    Array,
+}
+
+/// Scans the document up to the array of a `JsonPath`, capturing the values of its meta
+/// fields found before it.
+pub(crate) struct PathTracker {
+    path: JsonPath,
+    /// The first byte of the value at the path, `[` for an array.
+    target: u8,
+    stack: Vec<Container>,
+    strings: Strings,
+    /// The bytes of the key being read, quotes included.
+    key: Option<Vec<u8>>,
+    /// The next non-whitespace byte starts a value.
+    value_next: bool,
+    /// The meta field being captured, its raw bytes, and the depth of its value.
+    capture: Option<(String, Vec<u8>, usize)>,
+    meta: HashMap<String, serde_json::Value>,
+}
+
+impl PathTracker {
+    pub(crate) fn new(path: JsonPath) -> Self {
+        PathTracker {
+            path,
+            target: b'[',
+            stack: Vec::new(),
+            strings: Strings::default(),
+            key: None,
+            value_next: true,
+            capture: None,
+            meta: HashMap::new(),
+        }
+    }
+    /// Scan up to the string at the path instead of an array.
+    pub(crate) fn for_string(mut self) -> Self {
+        self.target = b'"';
+        self
+    }
+    /// The opening braces of the containers up to the array, the array included.
+    pub(crate) fn openers(&self) -> Vec<char> {
+        self.stack
+            .iter()
+            .map(|container| match container {
+                Container::Object(_) => '{',
+                Container::Array => '[',
+            })
+            .chain(Some('['))
+            .collect()
+    }
+    /// The meta fields captured, by their path.
+    pub(crate) fn take_meta(&mut self) -> HashMap<String, serde_json::Value> {
+        std::mem::take(&mut self.meta)
+    }
+    /// Whether `path` is the path of a value starting now.
+    fn is_at(&self, path: &[String]) -> bool {
+        path.len() == self.stack.len()
+            && self.stack.iter().zip(path).all(|(container, key)| {
+                matches!(container, Container::Object(Some(current)) if current == key)
+            })
+    }
+    /// Scan `byte`, returning whether it opens the array, or the string.
+    pub(crate) fn feed(&mut self, byte: u8) -> Result<bool, JsonStreamError> {
+        if let Some((_, ref mut raw, depth)) = self.capture {
+            if !self.strings.in_string()
+                && matches!(byte, b',' | b'}' | b']')
+                && self.stack.len() == depth
+            {
+                self.end_capture()?;
+            } else {
+                raw.push(byte);
+            }
+        }
+        let lexeme = self.strings.feed(byte, false);
+        if matches!(lexeme, Lexeme::Inside | Lexeme::Close) {
+            if let Some(ref mut key) = self.key {
+                key.push(byte);
+            }
+            if lexeme == Lexeme::Close {
+                if let Some(key) = self.key.take() {
+                    let key = serde_json::from_slice(&key)
+                        .map_err(|err| JsonStreamError::json(format!("Invalid key, {}", err)))?;
+                    if let Some(Container::Object(current)) = self.stack.last_mut() {
+                        *current = Some(key);
+                    }
+                }
+            }
+            return Ok(false);
+        }
+        if byte.is_ascii_whitespace() {
+            return Ok(false);
+        }
+        if self.value_next && !matches!(byte, b']' | b'}') {
+            self.value_next = false;
+            if self.is_at(&self.path.items) {
+                match byte == self.target {
+                    true => return Ok(true),
+                    false => return Err(self.not_found()),
+                }
+            }
+            if let Some(meta) = self.path.meta.iter().find(|meta| self.is_at(meta)) {
+                self.capture = Some((meta.join("."), vec![byte], self.stack.len()));
+            }
+        }
+        match byte {
+            b'{' => self.stack.push(Container::Object(None)),
+            b'[' => {
+                self.stack.push(Container::Array);
+                self.value_next = true;
+            }
+            b'"' => {
+                if let Some(Container::Object(None)) = self.stack.last() {
+                    self.key = Some(vec![byte]);
+                }
+            }
+            b':' => self.value_next = true,
+            b',' => match self.stack.last_mut() {
+                Some(Container::Object(key)) => *key = None,
+                Some(Container::Array) => self.value_next = true,
+                None => {}
+            },
+            b'}' | b']' => {
+                self.value_next = false;
+                self.stack.pop();
+                if self.stack.is_empty() {
+                    return Err(self.not_found());
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+    fn end_capture(&mut self) -> Result<(), JsonStreamError> {
+        if let Some((path, raw, _)) = self.capture.take() {
+            let value = serde_json::from_slice(&raw).map_err(|err| {
+                JsonStreamError::json(format!("Invalid value at `{}`, {}", path, err))
+            })?;
+            self.meta.insert(path, value);
+        }
+        Ok(())
+    }
+    /// The error of a document without an array, or a string, at the path.
+    pub(crate) fn not_found(&self) -> JsonStreamError {
+        let kind = match self.target {
+            b'[' => "array",
+            _ => "string",
+        };
+        JsonStreamError::json(format!("no {} at `{}`", kind, self.path.items.join(".")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonPath, PathTracker};
+    use serde_json::json;
+
+    /// Feed `json` up to the array, returning the rest of it.
+    fn scan<'a>(tracker: &mut PathTracker, json: &'a [u8]) -> Result<&'a [u8], String> {
+        for (i, &byte) in json.iter().enumerate() {
+            if tracker.feed(byte).map_err(|err| err.to_string())? {
+                return Ok(&json[i + 1..]);
+            }
+        }
+        Err("not found".to_string())
+    }
+
+    #[test]
+    fn meta_before_array() {
+        let json = br#"{"total": 3, "page": {"next": "a\"b", "tags": [1, {"x": 2}]},
+            "skipped": [4], "data": {"more": true, "items": [1, 2, 3]}}"#;
+        let path = JsonPath::new("data.items", &["total", "page.next", "data.more", "nope"]);
+        let mut tracker = PathTracker::new(path);
+        assert_eq!(scan(&mut tracker, json).unwrap(), b"1, 2, 3]}}");
+        assert_eq!(tracker.openers(), ['{', '{', '[']);
+        let meta = tracker.take_meta();
+        assert_eq!(meta.len(), 3);
+        assert_eq!(meta["total"], json!(3));
+        assert_eq!(meta["page.next"], json!("a\"b"));
+        assert_eq!(meta["data.more"], json!(true));
+    }
+
+    #[test]
+    fn missing_array() {
+        let mut tracker = PathTracker::new(JsonPath::new("items", &[]));
+        assert!(scan(&mut tracker, br#"{"other": [1], "items": {}}"#).is_err());
+        let mut tracker = PathTracker::new(JsonPath::new("items", &[]));
+        assert!(scan(&mut tracker, br#"{"other": [1]}"#).is_err());
+    }
+
+    #[test]
+    fn string_at_path() {
+        let mut tracker = PathTracker::new(JsonPath::new("a.b", &[])).for_string();
+        assert_eq!(
+            scan(&mut tracker, br#"{"a": {"c": "x", "b": "[y]"}}"#).unwrap(),
+            b"[y]\"}}"
+        );
+        let mut tracker = PathTracker::new(JsonPath::new("a", &[])).for_string();
+        assert!(scan(&mut tracker, br#"{"a": [1]}"#).is_err());
+    }
+}
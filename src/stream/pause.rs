@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::{fmt, mem};
+
+/// A switch pausing the download of all the streams sharing it, see
+/// `JsonStream::with_pause_signal`, for an external controller throttling them together.
+///
+/// Cloning the signal returns a handle to the same switch. A paused stream still yields
+/// the elements already received, then returns `Pending` instead of reading the body
+/// further, after registering its waker with the signal. `resume` wakes all the streams
+/// waiting on it, which go on reading their body on their next poll.
+#[derive(Clone, Default)]
+pub struct PauseSignal {
+    inner: Arc<Signal>,
+}
+
+#[derive(Default)]
+struct Signal {
+    paused: AtomicBool,
+    /// The streams waiting for the signal to be resumed.
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl PauseSignal {
+    /// Create a signal, not paused.
+    pub fn new() -> Self {
+        PauseSignal::default()
+    }
+    /// Pause the streams sharing the signal.
+    pub fn pause(&self) {
+        self.inner.paused.store(true, Ordering::Release);
+    }
+    /// Resume the streams sharing the signal, waking the ones waiting on it.
+    pub fn resume(&self) {
+        self.inner.paused.store(false, Ordering::Release);
+        let wakers = mem::take(
+            &mut *self
+                .inner
+                .wakers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        wakers.into_iter().for_each(Waker::wake);
+    }
+    /// Whether the streams sharing the signal are paused.
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::Acquire)
+    }
+    /// Returns `Ready` if the signal is not paused, or registers the waker of `cx` to be
+    /// woken by `resume`.
+    pub(crate) fn poll_resumed(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.is_paused() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self
+            .inner
+            .wakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        // Checked again with the lock held: a `resume` since the first check either
+        // cleared the flag before this one, or takes the lock after and wakes the task.
+        match self.is_paused() {
+            true => Poll::Pending,
+            false => Poll::Ready(()),
+        }
+    }
+}
+
+impl fmt::Debug for PauseSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PauseSignal")
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::sync::Mutex;
+
+/// A source of buffers for the body of the streams, for instance to allocate them from an
+/// arena or to reuse them between requests.
+///
+/// A stream takes a buffer from the pool once the response arrives and puts it back when
+/// it is done with it, or when it is dropped. The buffer may grow in between.
+pub trait BufferPool: Send + Sync {
+    /// Returns an empty buffer, preferably with a capacity of at least `capacity` bytes.
+    fn get(&self, capacity: usize) -> Vec<u8>;
+    /// Takes back a buffer handed out by `get`. The buffer is empty.
+    fn put(&self, buffer: Vec<u8>);
+}
+
+impl fmt::Debug for dyn BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("BufferPool")
+    }
+}
+
+/// Keeps the buffer of the last body of a stream for the next one, see `JsonStream::reset`.
+#[derive(Debug, Default)]
+pub(crate) struct SpareBuffer(Mutex<Option<Vec<u8>>>);
+
+impl BufferPool for SpareBuffer {
+    fn get(&self, capacity: usize) -> Vec<u8> {
+        let mut buffer = self.0.lock().unwrap().take().unwrap_or_default();
+        buffer.reserve(capacity);
+        buffer
+    }
+    fn put(&self, buffer: Vec<u8>) {
+        *self.0.lock().unwrap() = Some(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferPool, SpareBuffer};
+
+    #[test]
+    fn spare_buffer() {
+        let spare = SpareBuffer::default();
+        let buffer = spare.get(100);
+        assert!(buffer.capacity() >= 100);
+        let ptr = buffer.as_ptr();
+        spare.put(buffer);
+        let buffer = spare.get(10);
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert!(buffer.capacity() >= 100);
+        assert_eq!(spare.get(0).capacity(), 0);
+    }
+}
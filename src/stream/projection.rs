@@ -0,0 +1,103 @@
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, Visitor};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Deserializes a json object into a `Map` holding only some of its top-level fields, for
+/// wide records of which only a few columns are used. The other fields are skipped
+/// without being allocated, their keys included. Used by `JsonStream::project`.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    fields: Arc<HashSet<String>>,
+}
+
+impl Projection {
+    /// Keep the top-level `fields` of the objects.
+    pub fn new(fields: &[&str]) -> Self {
+        Projection {
+            fields: Arc::new(fields.iter().map(|field| field.to_string()).collect()),
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Projection {
+    type Value = Map<String, Value>;
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Projection {
+    type Value = Map<String, Value>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a json object")
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut projected = Map::new();
+        while let Some(key) = map.next_key_seed(Key(&self.fields))? {
+            match key {
+                Some(key) => {
+                    projected.insert(key, map.next_value()?);
+                }
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(projected)
+    }
+}
+
+/// Deserializes a key, only allocated when it is one of the fields kept.
+struct Key<'a>(&'a HashSet<String>);
+
+impl<'de> DeserializeSeed<'de> for Key<'_> {
+    type Value = Option<String>;
+    fn deserialize<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Key<'_> {
+    type Value = Option<String>;
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string key")
+    }
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Self::Value, E> {
+        Ok(self.0.get(key).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Projection;
+    use serde::de::DeserializeSeed;
+    use serde_json::json;
+
+    fn project(json: &str, fields: &[&str]) -> Result<serde_json::Value, String> {
+        let mut de = serde_json::Deserializer::from_str(json);
+        Projection::new(fields)
+            .deserialize(&mut de)
+            .map(serde_json::Value::Object)
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn projection() {
+        let json = r#"{"id": 1, "name": "a\"b", "tags": [1, {"id": 2}], "nested": {"name": 3}}"#;
+        assert_eq!(
+            project(json, &["id", "name", "missing"]).unwrap(),
+            json!({"id": 1, "name": "a\"b"})
+        );
+        assert_eq!(project(r#"{"id": 4}"#, &["id"]).unwrap(), json!({"id": 4}));
+        assert_eq!(project("{}", &["id"]).unwrap(), json!({}));
+        assert!(project("[1]", &["id"]).is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use futures_core::stream::{FusedStream, Stream};
+use hyper::body::Bytes;
+use serde::de::DeserializeOwned;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A stream yielding the elements of a `JsonStream` along with their raw json, created by
+/// `JsonStream::raw`. The raw json is the bytes the element was deserialized from, without
+/// the surrounding whitespace.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct RawJsonStream<T> {
+    stream: JsonStream<T>,
+}
+
+impl<T> RawJsonStream<T> {
+    pub(crate) fn new(stream: JsonStream<T>) -> Self {
+        RawJsonStream { stream }
+    }
+}
+
+// The compiler adds a T: Unpin bound, but a `JsonStream<T>` is Unpin.
+impl<T> Unpin for RawJsonStream<T> {}
+
+impl<T: DeserializeOwned> Stream for RawJsonStream<T> {
+    type Item = Result<(Bytes, T), JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(Bytes, T), JsonStreamError>>> {
+        let stream = &mut self.get_mut().stream;
+        match Pin::new(&mut *stream).poll_next(cx) {
+            Poll::Ready(Some(Ok(item))) => match stream.take_raw() {
+                Some(raw) => Poll::Ready(Some(Ok((raw, item)))),
+                // Every element is scanned with its raw json kept, this is a bug.
+                None => Poll::Ready(Some(Err(JsonStreamError::IOError(io::Error::other(
+                    "the raw json of the element was not kept",
+                ))))),
+            },
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FusedStream for RawJsonStream<T> {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
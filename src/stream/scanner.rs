@@ -0,0 +1,78 @@
+/// Where a byte stands relative to the strings of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Lexeme {
+    /// Outside of any string, the byte is part of the structure.
+    Outside,
+    /// The quote opening a string.
+    Open,
+    /// A byte within a string, escapes included.
+    Inside,
+    /// The quote closing a string.
+    Close,
+}
+
+/// Follows the strings of a document byte by byte, so that the scanners only interpret the
+/// braces and the delimiters outside of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Strings {
+    /// The quote closing the current string, when in one.
+    quote: Option<u8>,
+    /// The previous byte is a backslash, escaping this one.
+    escaped: bool,
+}
+
+impl Strings {
+    /// Scan `byte`, single quotes opening strings too when `single_quotes`, as in json5.
+    pub(crate) fn feed(&mut self, byte: u8, single_quotes: bool) -> Lexeme {
+        match self.quote {
+            None if byte == b'"' || (byte == b'\'' && single_quotes) => {
+                self.quote = Some(byte);
+                Lexeme::Open
+            }
+            None => Lexeme::Outside,
+            Some(_) if self.escaped => {
+                self.escaped = false;
+                Lexeme::Inside
+            }
+            Some(quote) if byte == quote => {
+                self.quote = None;
+                Lexeme::Close
+            }
+            Some(_) => {
+                self.escaped = byte == b'\\';
+                Lexeme::Inside
+            }
+        }
+    }
+    pub(crate) fn in_string(&self) -> bool {
+        self.quote.is_some()
+    }
+    /// Leave the current string, if any.
+    pub(crate) fn reset(&mut self) {
+        *self = Strings::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexeme, Strings};
+
+    fn lexemes(text: &[u8], single_quotes: bool) -> String {
+        let mut strings = Strings::default();
+        text.iter()
+            .map(|&byte| match strings.feed(byte, single_quotes) {
+                Lexeme::Outside => '.',
+                Lexeme::Open => '<',
+                Lexeme::Inside => '-',
+                Lexeme::Close => '>',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escapes_and_quotes() {
+        assert_eq!(lexemes(br#"["a\"b", 1]"#, false), r#".<---->...."#);
+        assert_eq!(lexemes(br#"{"\\":'"'}"#, false), r#".<-->..<--"#);
+        assert_eq!(lexemes(br#"{"\\":'"'}"#, true), r#".<-->.<->."#);
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+/// A summary of a `JsonStream`, accumulated while it is polled.
+///
+/// The sizes of the elements are the number of decoded bytes each of them spans.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamStats {
+    /// The number of elements yielded.
+    pub elements: u64,
+    /// The number of bytes of the body after decompression.
+    pub decoded_bytes: u64,
+    /// The number of bytes of the body as received, before decompression.
+    pub wire_bytes: u64,
+    /// The size of the smallest element, 0 if there were none.
+    pub min_element_size: u64,
+    /// The size of the largest element, 0 if there were none.
+    pub max_element_size: u64,
+    /// The time since the stream was first polled, until it completed.
+    pub elapsed: Duration,
+    /// The time since the stream was first polled, until the head of the response was
+    /// received. There is none for a stream created with `from_body`.
+    pub time_to_head: Option<Duration>,
+    /// The time since the stream was first polled, until the first bytes of the body were
+    /// received.
+    pub time_to_first_byte: Option<Duration>,
+    /// The time since the stream was first polled, until the first element was yielded.
+    /// Compared to `time_to_head`, this tells a slow upstream startup from a slow body.
+    pub time_to_first_element: Option<Duration>,
+    /// The nanoseconds spent decompressing the body, and transcoding it if needed.
+    #[cfg(feature = "profiling")]
+    pub decompress_ns: u64,
+    /// The nanoseconds spent scanning the decoded body for the bounds of the elements.
+    #[cfg(feature = "profiling")]
+    pub scan_ns: u64,
+    /// The nanoseconds spent deserializing the elements, on the task polling the stream:
+    /// the ones offloaded with `JsonStream::offload_large` are not counted.
+    #[cfg(feature = "profiling")]
+    pub deserialize_ns: u64,
+    total_element_size: u64,
+}
+
+impl StreamStats {
+    /// The average size of the elements, 0 if there were none.
+    pub fn avg_element_size(&self) -> f64 {
+        if self.elements == 0 {
+            return 0.0;
+        }
+        self.total_element_size as f64 / self.elements as f64
+    }
+    /// Add the time spent by a call to `PartialJson::next`, `deserialize` of which was
+    /// spent deserializing.
+    #[cfg(feature = "profiling")]
+    pub(crate) fn record_next(&mut self, total: Duration, deserialize: Duration) {
+        self.deserialize_ns += deserialize.as_nanos() as u64;
+        self.scan_ns += total.saturating_sub(deserialize).as_nanos() as u64;
+    }
+    #[cfg(feature = "profiling")]
+    pub(crate) fn record_decompress(&mut self, elapsed: Duration) {
+        self.decompress_ns += elapsed.as_nanos() as u64;
+    }
+    pub(crate) fn record_element(&mut self, size: u64) {
+        if self.elements == 0 || size < self.min_element_size {
+            self.min_element_size = size;
+        }
+        self.max_element_size = self.max_element_size.max(size);
+        self.total_element_size += size;
+        self.elements += 1;
+    }
+}
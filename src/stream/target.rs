@@ -0,0 +1,41 @@
+use crate::stream::partial_json::Framing;
+
+/// What a `JsonStream` yields from the json document.
+///
+/// The legacy `level` of `JsonStream::new` is the number of opening braces to skip before
+/// reaching the elements: a `level` of 1 is `TopLevelArray`, and a greater `level` is
+/// `NestedArray(level)`. A `level` of 0 skips no brace at all, it is `SingleValue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTarget {
+    /// The elements of the array making up the document, like `[1, 2, 3]`.
+    TopLevelArray,
+    /// The elements of an array found after the given number of opening braces, like the
+    /// shops of `{"shops": [...]}` with `NestedArray(2)`. `NestedArray(0)` is `SingleValue`.
+    NestedArray(u32),
+    /// The values of the members of the object making up the document, like `1` and `2`
+    /// in `{"a": 1, "b": 2}`. The keys are ignored.
+    ObjectValues,
+    /// The whole document as a single value, yielded once the body is complete.
+    SingleValue,
+}
+
+impl StreamTarget {
+    /// The target of a legacy `level`.
+    pub fn from_level(level: u32) -> Self {
+        match level {
+            0 => StreamTarget::SingleValue,
+            1 => StreamTarget::TopLevelArray,
+            level => StreamTarget::NestedArray(level),
+        }
+    }
+    /// How the parser delimits the elements of the target, and the number of opening
+    /// braces to skip before reaching them.
+    pub(crate) fn framing(self) -> (Framing, u32) {
+        match self {
+            StreamTarget::TopLevelArray => (Framing::Array, 1),
+            StreamTarget::NestedArray(0) | StreamTarget::SingleValue => (Framing::Single, 0),
+            StreamTarget::NestedArray(level) => (Framing::Array, level),
+            StreamTarget::ObjectValues => (Framing::ObjectValues, 1),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use futures_core::stream::{FusedStream, Stream};
+use serde::de::DeserializeOwned;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::stream::clock::{Clock, Sleep, TokioClock};
+use crate::stream::json_stream::JsonStream;
+use crate::util::JsonStreamError;
+
+/// A `JsonStream` waiting for a delay between the elements it yields, to test consumers
+/// against slow feeds. Created by `JsonStream::throttle`.
+#[must_use = "streams do nothing unless you poll them"]
+pub struct ThrottledJsonStream<T> {
+    stream: JsonStream<T>,
+    delay: Duration,
+    clock: Arc<dyn Clock>,
+    /// The delay before the next element, started once an element is yielded.
+    sleep: Option<Sleep>,
+}
+// The compiler adds a T: Unpin bound, but it is not needed as `JsonStream` is Unpin whatever
+// the T.
+impl<T> Unpin for ThrottledJsonStream<T> {}
+
+impl<T> ThrottledJsonStream<T> {
+    pub(crate) fn new(stream: JsonStream<T>, delay: Duration) -> Self {
+        ThrottledJsonStream {
+            stream,
+            delay,
+            clock: Arc::new(TokioClock),
+            sleep: None,
+        }
+    }
+    /// Measure the delays with `clock` instead of the timer of tokio.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self.sleep = None;
+        self
+    }
+}
+
+impl<T: DeserializeOwned> Stream for ThrottledJsonStream<T> {
+    type Item = Result<T, JsonStreamError>;
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T, JsonStreamError>>> {
+        let this = self.get_mut();
+        if let Some(ref mut sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+        let res = Pin::new(&mut this.stream).poll_next(cx);
+        if let Poll::Ready(Some(_)) = res {
+            let deadline = this.clock.now() + this.delay;
+            this.sleep = Some(this.clock.sleep_until(deadline));
+        }
+        res
+    }
+}
+
+impl<T: DeserializeOwned> FusedStream for ThrottledJsonStream<T> {
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::json_stream::JsonStream;
+    use crate::util::test_server::{chunked, respond};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[tokio::test(start_paused = true)]
+    async fn throttle() {
+        let stream: JsonStream<u32> =
+            JsonStream::new(respond(chunked(b"[1, 2, 3]", 4)).await, 1, 0);
+        let start = Instant::now();
+        let mut stream = stream.throttle(Duration::from_secs(5));
+        let mut yielded = Vec::new();
+        while let Some(item) = stream.next().await {
+            yielded.push((item.unwrap(), start.elapsed()));
+        }
+        assert_eq!(
+            yielded,
+            [
+                (1, Duration::ZERO),
+                (2, Duration::from_secs(5)),
+                (3, Duration::from_secs(10)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use futures_core::stream::Stream;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Drive `stream` to its end and return its items, asserting that every poll returning
+/// `Pending` arranged to be woken up: it either kept a clone of the waker of the context,
+/// or woke it already. A stream returning `Pending` without doing either is only polled
+/// again by chance, and otherwise hangs forever.
+///
+/// Each poll is given a fresh probe waker, wrapping the waker of the task, so that the
+/// clones kept from the previous polls are not mistaken for a new registration.
+///
+/// # Panics
+///
+/// Panics on the first `Pending` returned without registering the waker.
+pub async fn assert_wakes_on_pending<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+    let mut items = Vec::new();
+    poll_fn(|cx| loop {
+        let (probe, waker) = Probe::new(cx);
+        let poll = Pin::new(&mut stream).poll_next(&mut Context::from_waker(&waker));
+        drop(waker);
+        match poll {
+            Poll::Ready(Some(item)) => items.push(item),
+            Poll::Ready(None) => return Poll::Ready(()),
+            Poll::Pending => {
+                assert!(
+                    probe.registered(),
+                    "the stream returned Pending without registering the waker"
+                );
+                return Poll::Pending;
+            }
+        }
+    })
+    .await;
+    items
+}
+
+/// Records the wake-ups of the waker of a poll, passing them on to the task.
+pub(crate) struct Probe {
+    waker: Waker,
+    woken: AtomicBool,
+}
+
+impl Probe {
+    /// A probe wrapping the waker of `cx`, and the waker to poll with in its place.
+    pub(crate) fn new(cx: &Context<'_>) -> (Arc<Probe>, Waker) {
+        let probe = Arc::new(Probe {
+            waker: cx.waker().clone(),
+            woken: AtomicBool::new(false),
+        });
+        let waker = Waker::from(probe.clone());
+        (probe, waker)
+    }
+    /// Whether the poll kept a clone of the waker or woke it, once the waker given to the
+    /// poll is dropped.
+    pub(crate) fn registered(self: &Arc<Self>) -> bool {
+        Arc::strong_count(self) > 1 || self.woken.load(Ordering::Acquire)
+    }
+}
+
+impl Wake for Probe {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.waker.wake_by_ref();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assert_wakes_on_pending;
+    use crate::stream::json_stream::JsonStream;
+    use crate::stream::pause::PauseSignal;
+    use crate::util::test_server::{chunked, respond, City, CITIES};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures_core::stream::Stream;
+    use std::io::Write;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    #[tokio::test]
+    async fn json_stream_wakes() {
+        let stream: JsonStream<City> = JsonStream::new(respond(chunked(CITIES, 16)).await, 1, 0);
+        assert_eq!(assert_wakes_on_pending(stream).await.len(), 12);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CITIES).unwrap();
+        let mut response = chunked(&encoder.finish().unwrap(), 16);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", "gzip".parse().unwrap());
+        let signal = PauseSignal::new();
+        signal.pause();
+        let stream: JsonStream<City> =
+            JsonStream::new(respond(response).await, 1, 0).with_pause_signal(signal.clone());
+        let resume = tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            signal.resume();
+        });
+        assert_eq!(assert_wakes_on_pending(stream).await.len(), 12);
+        resume.await.unwrap();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "without registering the waker")]
+    async fn missing_waker() {
+        /// Returns `Pending` once, without registering the waker.
+        struct Forgetful(bool);
+        impl Stream for Forgetful {
+            type Item = ();
+            fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<()>> {
+                self.0 = !self.0;
+                match self.0 {
+                    true => Poll::Pending,
+                    false => Poll::Ready(None),
+                }
+            }
+        }
+        assert_wakes_on_pending(Forgetful(false)).await;
+    }
+}
@@ -1,6 +1,10 @@
+#[cfg(test)]
+pub(crate) mod test_server;
+
 use hyper::StatusCode;
 use std::fmt;
 use std::string::FromUtf8Error;
+use std::time::{Duration, SystemTime};
 
 /// Parse the content length header.
 pub fn get_content_length(parts: &http::response::Parts) -> usize {
@@ -12,6 +16,23 @@ pub fn get_content_length(parts: &http::response::Parts) -> usize {
         .unwrap_or(0)
 }
 
+/// Parse the retry after header, either a number of seconds or a date. A date in the past
+/// gives a zero duration.
+pub fn get_retry_after(headers: &http::HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers
+        .get(http::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+        }
+    }
+}
+
 #[derive(Debug)]
 #[non_exhaustive]
 pub enum JsonStreamError {
@@ -21,9 +42,24 @@ pub enum JsonStreamError {
     IOError(std::io::Error),
     JsonError(serde_json::Error),
     ApiError(StatusCode, String),
+    /// A `429 Too Many Requests` response, with the delay of its `Retry-After` header.
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
     /// This type is only returned if the format of the json downloaded is wrong.
     MalformedJson(String),
+    /// An element that could not be deserialized in lenient mode, with its raw bytes.
+    /// The stream continues after this error.
+    InvalidElement(serde_json::Error, Vec<u8>),
+    /// An element that could not be deserialized outside of lenient mode: the message,
+    /// with the context of the failure, and the raw bytes of the element. The stream ends
+    /// after this error.
+    MalformedElement(String, Vec<u8>),
     EncodingError(String),
+    /// The decompressed body does not match the checksum or the length recorded in the
+    /// trailer of the compressed body, like `gzip crc mismatch`: the body was corrupted.
+    ChecksumMismatch(String),
 }
 
 /// Load errors
@@ -31,6 +67,53 @@ impl JsonStreamError {
     pub(crate) fn json(s: String) -> JsonStreamError {
         JsonStreamError::MalformedJson(s)
     }
+    /// The raw bytes of the element that failed to deserialize, to write it to a
+    /// dead-letter store.
+    ///
+    /// They are held by a `JsonStreamError::InvalidElement`, for an element that failed to
+    /// deserialize in lenient mode, see `JsonStream::lenient`, and by the
+    /// `JsonStreamError::MalformedElement` ending the stream without lenient mode. This
+    /// returns `None` for the errors not about a single element.
+    pub fn raw_element(&self) -> Option<&[u8]> {
+        match self {
+            JsonStreamError::InvalidElement(_, raw) | JsonStreamError::MalformedElement(_, raw) => {
+                Some(raw)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// HTTP/2 stream resets
+impl JsonStreamError {
+    /// Whether retrying the request may succeed. This is the case when the HTTP/2 stream
+    /// was reset with `REFUSED_STREAM`, which guarantees the request was not processed, or
+    /// with `NO_ERROR`, as happens when the server shuts down gracefully.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.h2_reason(),
+            Some(reason) if reason == h2::Reason::REFUSED_STREAM || reason == h2::Reason::NO_ERROR
+        )
+    }
+    /// The reason of the HTTP/2 stream reset or connection error behind this error.
+    fn h2_reason(&self) -> Option<h2::Reason> {
+        let mut err: &(dyn std::error::Error + 'static) = match self {
+            JsonStreamError::HyperError(err) => err,
+            JsonStreamError::ClientError(err) => err,
+            JsonStreamError::IOError(err) => err,
+            _ => return None,
+        };
+        loop {
+            if let Some(err) = err.downcast_ref::<h2::Error>() {
+                return err.reason();
+            }
+            // The source of an io::Error is the source of the error it wraps, skipping it.
+            err = match err.downcast_ref::<std::io::Error>() {
+                Some(io_err) => io_err.get_ref()?,
+                None => err.source()?,
+            };
+        }
+    }
 }
 
 impl From<serde_json::Error> for JsonStreamError {
@@ -78,6 +161,26 @@ impl From<std::io::Error> for JsonStreamError {
         JsonStreamError::IOError(err)
     }
 }
+impl From<JsonStreamError> for std::io::Error {
+    fn from(err: JsonStreamError) -> std::io::Error {
+        use std::io::ErrorKind;
+        let kind = match err {
+            JsonStreamError::IOError(err) => return err,
+            JsonStreamError::HttpError(_) => ErrorKind::InvalidInput,
+            JsonStreamError::JsonError(_)
+            | JsonStreamError::MalformedJson(_)
+            | JsonStreamError::InvalidElement(_, _)
+            | JsonStreamError::MalformedElement(_, _)
+            | JsonStreamError::EncodingError(_)
+            | JsonStreamError::ChecksumMismatch(_) => ErrorKind::InvalidData,
+            JsonStreamError::HyperError(_)
+            | JsonStreamError::ClientError(_)
+            | JsonStreamError::ApiError(_, _)
+            | JsonStreamError::RateLimited { .. } => ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
 impl fmt::Display for JsonStreamError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -88,12 +191,50 @@ impl fmt::Display for JsonStreamError {
             JsonStreamError::ApiError(status, err) => {
                 write!(f, "{} : {}", status, err)
             }
+            JsonStreamError::RateLimited { retry_after, body } => match retry_after {
+                Some(delay) => write!(
+                    f,
+                    "{} (retry after {}s) : {}",
+                    StatusCode::TOO_MANY_REQUESTS,
+                    delay.as_secs(),
+                    body
+                ),
+                None => write!(f, "{} : {}", StatusCode::TOO_MANY_REQUESTS, body),
+            },
             JsonStreamError::MalformedJson(ref msg) => msg.fmt(f),
+            JsonStreamError::MalformedElement(ref msg, _) => msg.fmt(f),
+            JsonStreamError::InvalidElement(err, raw) => {
+                write!(f, "{}: {}", err, String::from_utf8_lossy(raw))
+            }
+            JsonStreamError::ClientError(err) if err.is_connect() => {
+                err.fmt(f)?;
+                write_sources(f, err)
+            }
             JsonStreamError::ClientError(err) => err.fmt(f),
             JsonStreamError::EncodingError(ref msg) => msg.fmt(f),
+            JsonStreamError::ChecksumMismatch(ref msg) => msg.fmt(f),
         }
     }
 }
+/// Write the messages of the sources of `err`, like `: dns error: failed to lookup address
+/// information`, which are the only readable part of the wrapped connector errors. A
+/// message already included in the previous ones is skipped.
+fn write_sources(f: &mut fmt::Formatter<'_>, err: &dyn std::error::Error) -> fmt::Result {
+    let mut written = Vec::new();
+    let mut source = err.source();
+    while let Some(err) = source {
+        let msg = err.to_string();
+        if !written
+            .iter()
+            .any(|previous: &String| previous.contains(&msg))
+        {
+            write!(f, ": {}", msg)?;
+            written.push(msg);
+        }
+        source = err.source();
+    }
+    Ok(())
+}
 impl std::error::Error for JsonStreamError {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match self {
@@ -102,9 +243,101 @@ impl std::error::Error for JsonStreamError {
             JsonStreamError::IOError(err) => Some(err),
             JsonStreamError::JsonError(err) => Some(err),
             JsonStreamError::ApiError(_, _) => None,
+            JsonStreamError::RateLimited { .. } => None,
             JsonStreamError::MalformedJson(_) => None,
+            JsonStreamError::MalformedElement(_, _) => None,
+            JsonStreamError::InvalidElement(err, _) => Some(err),
             JsonStreamError::ClientError(err) => err.source(),
             JsonStreamError::EncodingError(_) => None,
+            JsonStreamError::ChecksumMismatch(_) => None,
         }
     }
 }
+
+/// The error of `JsonStream::pipe_to`: the one of the stream, or the one of the sink its
+/// elements were sent to.
+#[cfg(feature = "sink")]
+#[derive(Debug)]
+pub enum PipeError<E> {
+    Stream(JsonStreamError),
+    Sink(E),
+}
+#[cfg(feature = "sink")]
+impl<E: fmt::Display> fmt::Display for PipeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipeError::Stream(err) => err.fmt(f),
+            PipeError::Sink(err) => write!(f, "sink error: {}", err),
+        }
+    }
+}
+#[cfg(feature = "sink")]
+impl<E: std::error::Error + 'static> std::error::Error for PipeError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PipeError::Stream(err) => Some(err),
+            PipeError::Sink(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_retry_after, JsonStreamError};
+    use hyper::StatusCode;
+    use std::io::{self, ErrorKind};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn retryable_resets() {
+        let reset = |reason| {
+            let err = io::Error::other(h2::Error::from(reason));
+            JsonStreamError::IOError(io::Error::new(ErrorKind::BrokenPipe, err))
+        };
+        assert!(reset(h2::Reason::REFUSED_STREAM).is_retryable());
+        assert!(reset(h2::Reason::NO_ERROR).is_retryable());
+        assert!(!reset(h2::Reason::INTERNAL_ERROR).is_retryable());
+        assert!(!JsonStreamError::IOError(ErrorKind::BrokenPipe.into()).is_retryable());
+        assert!(!JsonStreamError::MalformedJson("Invalid json".into()).is_retryable());
+    }
+
+    #[test]
+    fn retry_after() {
+        let mut headers = http::HeaderMap::new();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(get_retry_after(&headers, now), None);
+        headers.insert("Retry-After", "120".parse().unwrap());
+        assert_eq!(
+            get_retry_after(&headers, now),
+            Some(Duration::from_secs(120))
+        );
+        // 784111777 is Sun, 06 Nov 1994 08:49:37 GMT.
+        headers.insert(
+            "Retry-After",
+            "Sun, 06 Nov 1994 08:50:07 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            get_retry_after(&headers, now),
+            Some(Duration::from_secs(30))
+        );
+        headers.insert(
+            "Retry-After",
+            "Sun, 06 Nov 1994 08:00:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(get_retry_after(&headers, now), Some(Duration::ZERO));
+        headers.insert("Retry-After", "soon".parse().unwrap());
+        assert_eq!(get_retry_after(&headers, now), None);
+    }
+
+    #[test]
+    fn into_io_error() {
+        let err = io::Error::from(JsonStreamError::MalformedJson("Invalid json".into()));
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "Invalid json");
+        let err = io::Error::from(JsonStreamError::ApiError(StatusCode::NOT_FOUND, "".into()));
+        assert_eq!(err.kind(), ErrorKind::Other);
+        let err = io::Error::from(JsonStreamError::IOError(ErrorKind::TimedOut.into()));
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.get_ref().is_none());
+    }
+}
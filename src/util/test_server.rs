@@ -0,0 +1,88 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures_util::{stream, StreamExt};
+use http::Response;
+use http_body_util::{Empty, StreamBody};
+use hyper::body::{Bytes, Frame};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::{Client, ResponseFuture};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::TcpListener;
+
+/// A sample of the cities of https://github.com/lutangar/cities.json
+pub(crate) const CITIES: &[u8] = include_bytes!("../../tests/fixtures/cities.json");
+
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct City {
+    pub(crate) country: String,
+    pub(crate) name: String,
+    pub(crate) lat: String,
+}
+
+/// Serve `response` once over a local HTTP/2 connection and return the client side
+/// `ResponseFuture`. Every frame of the body is sent separately.
+pub(crate) async fn respond(response: Response<Vec<Frame<Bytes>>>) -> ResponseFuture {
+    serve(response, false).await
+}
+
+/// A `ResponseFuture` failing to connect, as nothing listens on its port anymore.
+pub(crate) async fn refused() -> ResponseFuture {
+    Client::builder(TokioExecutor::new())
+        .build_http::<Empty<Bytes>>()
+        .get(format!("http://{}", closed_addr().await).parse().unwrap())
+}
+
+/// An address nothing listens on anymore.
+pub(crate) async fn closed_addr() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap()
+}
+
+/// Like `respond`, but the body never ends after its frames.
+#[cfg(feature = "timeout")]
+pub(crate) async fn respond_stalled(response: Response<Vec<Frame<Bytes>>>) -> ResponseFuture {
+    serve(response, true).await
+}
+
+async fn serve(response: Response<Vec<Frame<Bytes>>>, stall: bool) -> ResponseFuture {
+    let addr = listen(response, stall).await;
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http::<Empty<Bytes>>();
+    client.get(format!("http://{}", addr).parse().unwrap())
+}
+
+/// Serve `response` once over a local HTTP/2 connection, returning the address to send
+/// the request to.
+pub(crate) async fn listen(response: Response<Vec<Frame<Bytes>>>, stall: bool) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let response = Arc::new(Mutex::new(Some(response)));
+    tokio::spawn(async move {
+        let (tcp, _) = listener.accept().await.unwrap();
+        let service = service_fn(move |_req| {
+            let response = response.lock().unwrap().take().unwrap();
+            let response = response.map(|frames| {
+                let frames = stream::iter(frames.into_iter().map(Ok::<_, Infallible>));
+                let stalled = stream::pending().take(usize::from(stall));
+                StreamBody::new(frames.chain(stalled))
+            });
+            async move { Ok::<_, Infallible>(response) }
+        });
+        let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+            .serve_connection(TokioIo::new(tcp), service)
+            .await;
+    });
+    addr
+}
+
+/// A response with the given body split into data frames of `chunk_size` bytes.
+pub(crate) fn chunked(body: &[u8], chunk_size: usize) -> Response<Vec<Frame<Bytes>>> {
+    Response::new(
+        body.chunks(chunk_size)
+            .map(|chunk| Frame::data(Bytes::copy_from_slice(chunk)))
+            .collect(),
+    )
+}